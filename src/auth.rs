@@ -2,193 +2,306 @@
 // Imports //
 /////////////
 
+use chrono::Utc;
 use rspotify::{
-    prelude::*, scopes, AuthCodeSpotify, Config, Credentials, OAuth, DEFAULT_API_PREFIX,
-    DEFAULT_CACHE_PATH, DEFAULT_PAGINATION_CHUNKS,
+    http::HttpError, prelude::*, scopes, AuthCodePkceSpotify, AuthCodeSpotify, ClientError,
+    ClientResult, Config, Credentials, OAuth, Token,
 };
-use std::{env, path};
-use urlshortener::{client::UrlShortener, providers::Provider};
+
+// Self made files
+use crate::{
+    callback,
+    config::{AppConfig, AuthFlow, ShortenerConfig},
+    error::SpotiAfkError,
+    headless, log,
+    logging::TERMINAL,
+    shortener::shorten_or_keep,
+    token_cache::TokenCache,
+};
+
+////////////
+// Traits //
+////////////
+
+// Spotify clients for the OAuth flows we support
+pub trait AuthClient: OAuthClient + Clone + 'static {
+    // Make the client for this flow
+    fn with_config(credentials: Credentials, oauth: OAuth, config: Config) -> Self;
+
+    // Url the user logs in on, PKCE also generates its code verifier here
+    fn authorize_url(&mut self) -> ClientResult<String>;
+
+    // Code verifier to send along with the authorization code, PKCE only
+    fn code_verifier(&self) -> Option<&str>;
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl AuthClient for AuthCodeSpotify {
+    fn with_config(credentials: Credentials, oauth: OAuth, config: Config) -> Self {
+        AuthCodeSpotify::with_config(credentials, oauth, config)
+    }
+
+    fn authorize_url(&mut self) -> ClientResult<String> {
+        self.get_authorize_url(true)
+    }
+
+    fn code_verifier(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl AuthClient for AuthCodePkceSpotify {
+    fn with_config(credentials: Credentials, oauth: OAuth, config: Config) -> Self {
+        AuthCodePkceSpotify::with_config(credentials, oauth, config)
+    }
+
+    fn authorize_url(&mut self) -> ClientResult<String> {
+        self.get_authorize_url(None)
+    }
+
+    fn code_verifier(&self) -> Option<&str> {
+        self.verifier.as_deref()
+    }
+}
 
 ///////////////
 // Functions //
 ///////////////
 
 // Auth to spotify API
-pub async fn auth_client() -> Result<rspotify::AuthCodeSpotify, String> {
-    let mut found_variables = 0;
-    // Check if environment variable are present
-    for (key, _) in env::vars() {
-        match key.as_str() {
-            "RSPOTIFY_CLIENT_ID" => found_variables += 1,
-            "RSPOTIFY_CLIENT_SECRET" => found_variables += 1,
-            "RSPOTIFY_REDIRECT_URI" => found_variables += 1,
-            _ => (),
-        }
-    }
-    if found_variables != 3 {
-        return Err(String::from("Failed parsing spotify api"));
-    }
+pub async fn auth_client<C: AuthClient>(config: &AppConfig) -> Result<C, SpotiAfkError> {
+    auth_session(config).await.map(|(client, _)| client)
+}
 
+// Auth to spotify API, also returning the token cache to keep the token up to date
+pub async fn auth_session<C: AuthClient>(
+    config: &AppConfig,
+) -> Result<(C, TokenCache), SpotiAfkError> {
     // Api scopes
     let scopes = scopes!(
         "user-modify-playback-state",
         "playlist-read-private",
-        "user-read-playback-state"
+        "user-read-playback-state",
+        "user-library-read",
+        "user-read-private"
     );
 
     // initialization of client
-    let config = match parse_client_config() {
-        Ok(config) => config,
-        Err(_) => return Err(String::from("Failed parsing spotify client")),
+    let spotify = &config.spotify;
+    let credentials = match config.auth.flow {
+        AuthFlow::Code => Credentials::new(&spotify.client_id, &spotify.client_secret),
+        AuthFlow::Pkce => Credentials::new_pkce(&spotify.client_id),
     };
-    let credentials = Credentials::from_env().unwrap();
-    let oauth = OAuth::from_env(scopes).unwrap();
-    let mut client = AuthCodeSpotify::with_config(credentials, oauth, config);
+    let oauth = OAuth {
+        redirect_uri: spotify.redirect_uri.clone(),
+        scopes,
+        ..Default::default()
+    };
+    let mut client = C::with_config(credentials, oauth, spotify.client_config());
+
+    // Use the cached token if there is one, refreshing it when expired
+    let cache = {
+        let _terminal = TERMINAL.lock().await;
+        TokenCache::new(spotify)?
+    };
+    // Logins from before a scope was added can not do everything, like reading Liked Songs
+    let token = cache.read()?.filter(|token| {
+        let missing: Vec<&str> = client
+            .get_oauth()
+            .scopes
+            .difference(&token.scopes)
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            log!(
+                "Cached token lacks the {} permission, please log in again",
+                missing.join(", ")
+            );
+        }
+        missing.is_empty()
+    });
+    if let Some(token) = token {
+        let token = match token.is_expired() {
+            true => refresh_token(config, &token).await.ok(),
+            false => Some(token),
+        };
+        if let Some(token) = token {
+            store_token(&client, &cache, token).await?;
+            return Ok((client, cache));
+        }
+        log!("Cached token could not be refreshed, please log in again");
+    }
+
+    let token = login(&mut client, config).await?;
+    store_token(&client, &cache, token).await?;
+    Ok((client, cache))
+}
+
+// Let the user log in and get a new token
+pub async fn login(
+    client: &mut impl AuthClient,
+    config: &AppConfig,
+) -> Result<Token, SpotiAfkError> {
+    // Accounts running at the same time log in one after another
+    let _terminal = TERMINAL.lock().await;
 
     // Get authorize url
-    let url = get_authorize_url(&client);
+    let url = get_authorize_url(client, &config.shortener).await?;
 
     // Let user login
-    match client.prompt_for_token(&url).await {
-        Ok(_) => Ok(client),
-        Err(_) => Err(String::from("Authorization failed")),
+    let code = get_code(client, config, &url).await?;
+    let mut form = vec![
+        ("grant_type", "authorization_code"),
+        ("code", code.as_str()),
+        ("redirect_uri", config.spotify.redirect_uri.as_str()),
+    ];
+    if let Some(verifier) = client.code_verifier() {
+        form.push(("code_verifier", verifier));
     }
+    fetch_token(config, &form)
+        .await
+        .map_err(SpotiAfkError::Authorization)
 }
 
-// Parse spotify client settings
-fn parse_client_config() -> Result<Config, String> {
-    // Make buffer variables
-    let mut rspotify_client_prefix = String::new();
-    let mut rspotify_client_cache_path = path::PathBuf::new();
-    let mut rspotify_client_pagination_chunks = 50;
-    let mut rspotify_client_token_cached = true;
-    let mut rspotify_client_token_refreshing = true;
-    let mut wrong_config = false;
-    let mut found_settings = 0;
-
-    // Loop over all environment variables
-    for (key, value) in env::vars() {
-        match key.as_str() {
-            // Set client prefix from .env
-            "RSPOTIFY_CLIENT_PREFIX" => {
-                match value.as_str() {
-                    "default" => rspotify_client_prefix = String::from(DEFAULT_API_PREFIX),
-                    _ => rspotify_client_prefix = value,
-                };
-                found_settings += 1;
-            }
+// Get a new access token with the refresh token
+pub async fn refresh_token(config: &AppConfig, token: &Token) -> Result<Token, ClientError> {
+    let refresh_token = token
+        .refresh_token
+        .clone()
+        .ok_or_else(|| ClientError::CacheFile(String::from("token has no refresh token")))?;
+    let mut new_token = fetch_token(
+        config,
+        &[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", &refresh_token),
+        ],
+    )
+    .await?;
 
-            // Set client cache path from .env
-            "RSPOTIFY_CLIENT_CACHE_PATH" => {
-                match value.as_str() {
-                    "default" => {
-                        rspotify_client_cache_path = path::PathBuf::from(DEFAULT_CACHE_PATH)
-                    }
-                    _ => rspotify_client_cache_path = path::PathBuf::from(value),
-                };
-                found_settings += 1
-            }
+    // Spotify only sends a new refresh token when the old one is replaced,
+    // the scopes stay the same when it leaves them out
+    if new_token.refresh_token.is_none() {
+        new_token.refresh_token = Some(refresh_token);
+    }
+    if new_token.scopes.is_empty() {
+        new_token.scopes = token.scopes.clone();
+    }
+    Ok(new_token)
+}
 
-            // Check client pagination chunks if correct in .env
-            "RSPOTIFY_CLIENT_PAGINATION_CHUNKS" => {
-                match value.as_str() {
-                    "default" => rspotify_client_pagination_chunks = DEFAULT_PAGINATION_CHUNKS,
-                    _ => {
-                        let pagination_chunks: u32 = value.parse().unwrap();
-                        if pagination_chunks <= 50 {
-                            rspotify_client_pagination_chunks = pagination_chunks
-                        } else {
-                            wrong_config = true
-                        }
-                    }
-                };
-                found_settings += 1
-            }
+// Request a token from the token endpoint
+async fn fetch_token(config: &AppConfig, form: &[(&str, &str)]) -> Result<Token, ClientError> {
+    let http_error = |e: reqwest::Error| ClientError::from(HttpError::from(e));
 
-            // Check client cached if correct in .env
-            "RSPOTIFY_CLIENT_TOKEN_CACHED" => {
-                match value.as_str() {
-                    "true" => rspotify_client_token_cached = true,
-                    "false" => rspotify_client_token_cached = false,
-                    _ => wrong_config = true,
-                };
-                found_settings += 1
-            }
+    // The code flow authenticates with the secret, PKCE only names the client
+    let request = reqwest::Client::new().post(&config.auth.token_url);
+    let request = match config.auth.flow {
+        AuthFlow::Code => request
+            .basic_auth(
+                &config.spotify.client_id,
+                Some(&config.spotify.client_secret),
+            )
+            .form(form),
+        AuthFlow::Pkce => request.form(
+            &[("client_id", config.spotify.client_id.as_str())]
+                .iter()
+                .chain(form)
+                .collect::<Vec<_>>(),
+        ),
+    };
+    let response = request.send().await.map_err(http_error)?;
+    if !response.status().is_success() {
+        return Err(ClientError::from(HttpError::StatusCode(response)));
+    }
 
-            // Check client refresh if correct in .env
-            "RSPOTIFY_CLIENT_TOKEN_REFRESHING" => {
-                match value.as_str() {
-                    "true" => rspotify_client_token_refreshing = true,
-                    "false" => rspotify_client_token_refreshing = false,
-                    _ => wrong_config = true,
-                };
-                found_settings += 1
-            }
-            _ => (),
-        }
+    let mut token: Token = serde_json::from_str(&response.text().await.map_err(http_error)?)?;
+    token.expires_at = Utc::now().checked_add_signed(token.expires_in);
+    Ok(token)
+}
+
+// Put the token in the client and cache it
+pub async fn store_token(
+    client: &impl AuthClient,
+    cache: &TokenCache,
+    token: Token,
+) -> Result<(), SpotiAfkError> {
+    cache.write(&token)?;
+    *client.get_token().lock().await.unwrap() = Some(token);
+    Ok(())
+}
+
+// Check if the token endpoint refused the refresh token, so only logging in again helps
+pub fn refresh_rejected(error: &ClientError) -> bool {
+    match error {
+        ClientError::Http(http_error) => match http_error.as_ref() {
+            HttpError::StatusCode(response) => matches!(response.status().as_u16(), 400 | 401),
+            _ => false,
+        },
+        // Tokens without a refresh token can never be refreshed
+        ClientError::CacheFile(_) => true,
+        _ => false,
     }
+}
 
-    // Check if there was a fail in the config
-    match wrong_config {
-        true => Err(String::from("Failed parsing spotify client")),
-        false => match found_settings {
-            5 => Ok(Config {
-                prefix: rspotify_client_prefix,
-                cache_path: rspotify_client_cache_path,
-                pagination_chunks: rspotify_client_pagination_chunks,
-                token_cached: rspotify_client_token_cached,
-                token_refreshing: rspotify_client_token_refreshing,
-            }),
-            _ => Err(String::from("Failed parsing spotify client")),
+// Get the authorization code, from the redirect listener or by letting the user paste the url
+async fn get_code(
+    client: &impl AuthClient,
+    config: &AppConfig,
+    url: &str,
+) -> Result<String, SpotiAfkError> {
+    let redirect_uri = &config.spotify.redirect_uri;
+    let bind_address = config.auth.listener_bind;
+    let listener = match config.auth.listener && callback::can_listen(redirect_uri, bind_address) {
+        true => match callback::bind(redirect_uri, bind_address).await {
+            Ok(listener) => Some(listener),
+            Err(e) => {
+                log!("{}, paste the url instead", e);
+                None
+            }
         },
+        false => None,
+    };
+
+    // Without a browser here the user logs in on another device
+    if config.auth.headless.enabled() {
+        return headless::get_code(client, config, url, listener).await;
+    }
+
+    match webbrowser::open(url) {
+        Ok(_) => log!("Opened {} in your browser.", url),
+        Err(_) => log!("Please open this url in your browser: {}", url),
+    }
+    match listener {
+        Some(listener) => {
+            log!("Waiting for Spotify to redirect to {}", redirect_uri);
+            callback::wait_for_code(
+                listener,
+                redirect_uri,
+                &client.get_oauth().state,
+                config.auth.listener_timeout,
+            )
+            .await
+        }
+        // Stdin is read on its own thread, so several accounts do not block the runtime
+        None => {
+            log!("Please paste the url you were redirected to:");
+            headless::pasted_code(client).await
+        }
     }
 }
 
 // Get url to open in browser
-fn get_authorize_url(client: &rspotify::AuthCodeSpotify) -> String {
+async fn get_authorize_url(
+    client: &mut impl AuthClient,
+    shortener: &ShortenerConfig,
+) -> Result<String, SpotiAfkError> {
     // Get destination url
-    let long_url = client.get_authorize_url(true).unwrap();
-    #[allow(unused_assignments)] // Compiler complains
-    let mut url = String::new();
-
-    // Check if in WM/DE to spare bitly links you only have 100
-    match env::var("DISPLAY") {
-        Ok(_) => {
-            // Check if bitly api key is provided
-            let bitly_key = env::var("BITLY_API_TOKEN");
-            let bitly = match bitly_key {
-                Ok(_) => true,
-                Err(_) => false,
-            };
-            // Generate urls with or without bitly shortener
-            url = if bitly {
-                let short_url = UrlShortener::new().unwrap().generate(
-                    &long_url,
-                    &Provider::BitLy {
-                        token: bitly_key.unwrap().to_owned(),
-                    },
-                );
-                match short_url {
-                    // If shortener successful return short url
-                    Ok(short_url) => match short_url.as_str() {
-                        "INVALID_ARG_ACCESS_TOKEN" | "MONTHLY_RATE_LIMIT_EXCEEDED" => {
-                            return long_url
-                        }
-                        _ => {
-                            return short_url;
-                        }
-                    },
-                    // If failed return long url
-                    Err(_) => return long_url,
-                }
-            } else {
-                // If no bitly api key is provided return
-                long_url
-            };
-        }
-        Err(_) => url = long_url,
-    }
+    let long_url = client
+        .authorize_url()
+        .map_err(SpotiAfkError::Authorization)?;
 
-    // Return url
-    url
+    Ok(shorten_or_keep(shortener, &long_url).await)
 }
@@ -0,0 +1,254 @@
+/////////////
+// Imports //
+/////////////
+
+use std::{collections::HashMap, net::IpAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::{timeout, timeout_at, Instant},
+};
+use url::Url;
+
+// Self made files
+use crate::{error::SpotiAfkError, log};
+
+///////////////
+// Constants //
+///////////////
+
+const SUCCESS_PAGE: &str = "<html><body><h1>SpotiAFK is logged in</h1>\
+<p>You can close this tab and go back to the terminal.</p></body></html>";
+
+// Browsers can open connections without sending anything on them
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const FAILURE_PAGE: &str = "<html><body><h1>SpotiAFK login failed</h1>\
+<p>Check the terminal for more information.</p></body></html>";
+
+///////////////
+// Functions //
+///////////////
+
+// Check if the redirect uri can reach this machine over plain http,
+// a bind address means the user forwards it here from another host
+pub fn can_listen(redirect_uri: &str, bind_address: Option<IpAddr>) -> bool {
+    match Url::parse(redirect_uri) {
+        Ok(url) => {
+            url.scheme() == "http"
+                && (bind_address.is_some()
+                    || matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]")))
+        }
+        Err(_) => false,
+    }
+}
+
+// Bind to the redirect uri before the user is sent to the authorize url
+pub async fn bind(
+    redirect_uri: &str,
+    bind_address: Option<IpAddr>,
+) -> Result<TcpListener, SpotiAfkError> {
+    let url = Url::parse(redirect_uri)
+        .map_err(|e| SpotiAfkError::AuthorizationCallback(e.to_string()))?;
+    let host = match (bind_address, url.host_str()) {
+        (Some(address), _) => address.to_string(),
+        (None, Some("localhost") | None) => String::from("127.0.0.1"),
+        (None, Some(host)) => String::from(host.trim_start_matches('[').trim_end_matches(']')),
+    };
+    let port = url.port_or_known_default().unwrap_or(80);
+    TcpListener::bind((host.as_str(), port)).await.map_err(|e| {
+        SpotiAfkError::AuthorizationCallback(format!("can not listen on {}:{} ({})", host, port, e))
+    })
+}
+
+// Wait for Spotify to redirect the browser back with the authorization code
+pub async fn wait_for_code(
+    listener: TcpListener,
+    redirect_uri: &str,
+    expected_state: &str,
+    wait: Duration,
+) -> Result<String, SpotiAfkError> {
+    let redirect = Url::parse(redirect_uri)
+        .map_err(|e| SpotiAfkError::AuthorizationCallback(e.to_string()))?;
+    let deadline = Instant::now() + wait;
+
+    loop {
+        let (mut stream, _) = match timeout_at(deadline, listener.accept()).await {
+            Ok(Ok(connection)) => connection,
+            Ok(Err(e)) => return Err(SpotiAfkError::AuthorizationCallback(e.to_string())),
+            Err(_) => {
+                return Err(SpotiAfkError::AuthorizationCallback(String::from(
+                    "timed out waiting for the browser to be redirected",
+                )))
+            }
+        };
+
+        // Ignore anything that is not the redirect, like the favicon
+        let target = match timeout(REQUEST_TIMEOUT, read_request_target(&mut stream)).await {
+            Ok(Some(target)) => target,
+            _ => continue,
+        };
+        let url = match redirect.join(&target) {
+            Ok(url) if url.path() == redirect.path() => url,
+            _ => {
+                respond(&mut stream, "404 Not Found", "").await;
+                continue;
+            }
+        };
+        let params: HashMap<_, _> = url.query_pairs().into_owned().collect();
+
+        // Someone else could be sending requests, only trust our own state
+        if params.get("state").map(String::as_str) != Some(expected_state) {
+            log!("Ignored a login redirect with the wrong state");
+            respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await;
+            continue;
+        }
+
+        match (params.get("code"), params.get("error")) {
+            (Some(code), _) => {
+                respond(&mut stream, "200 OK", SUCCESS_PAGE).await;
+                return Ok(code.clone());
+            }
+            (None, error) => {
+                respond(&mut stream, "400 Bad Request", FAILURE_PAGE).await;
+                return Err(SpotiAfkError::AuthorizationCallback(format!(
+                    "Spotify returned {}",
+                    error.map(String::as_str).unwrap_or("no code")
+                )));
+            }
+        }
+    }
+}
+
+// Read the path and query of a HTTP GET request
+async fn read_request_target(stream: &mut TcpStream) -> Option<String> {
+    let mut buffer = vec![0; 8192];
+    let mut read = 0;
+    while !buffer[..read]
+        .windows(4)
+        .any(|window| window == b"\r\n\r\n")
+    {
+        if read == buffer.len() {
+            return None;
+        }
+        match stream.read(&mut buffer[read..]).await {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => read += n,
+        }
+    }
+
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let mut request_line = request.lines().next()?.split_whitespace();
+    match (request_line.next(), request_line.next()) {
+        (Some("GET"), Some(target)) => Some(String::from(target)),
+        _ => None,
+    }
+}
+
+// Send a small HTML response and close the connection
+async fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await.ok();
+    stream.shutdown().await.ok();
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Listener on a free port and the redirect uri that reaches it
+    async fn listener() -> (TcpListener, String) {
+        let listener = bind("http://127.0.0.1:0/callback", None).await.unwrap();
+        let redirect_uri = format!("http://{}/callback", listener.local_addr().unwrap());
+        (listener, redirect_uri)
+    }
+
+    // Send a GET like a browser would and return the status line of the response
+    async fn get(redirect_uri: &str, target: &str) -> String {
+        let url = Url::parse(redirect_uri).unwrap();
+        let mut stream = TcpStream::connect((url.host_str().unwrap(), url.port().unwrap()))
+            .await
+            .unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", target);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        String::from(response.lines().next().unwrap_or_default())
+    }
+
+    fn spawn_wait(
+        listener: TcpListener,
+        redirect_uri: &str,
+        wait: Duration,
+    ) -> tokio::task::JoinHandle<Result<String, SpotiAfkError>> {
+        let redirect_uri = String::from(redirect_uri);
+        tokio::spawn(async move { wait_for_code(listener, &redirect_uri, "state", wait).await })
+    }
+
+    #[tokio::test]
+    async fn returns_the_code_of_the_redirect() {
+        let (listener, redirect_uri) = listener().await;
+        let code = spawn_wait(listener, &redirect_uri, Duration::from_secs(5));
+
+        let status = get(&redirect_uri, "/callback?code=abc&state=state").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(code.await.unwrap().unwrap(), "abc");
+    }
+
+    #[tokio::test]
+    async fn keeps_waiting_after_the_wrong_state() {
+        let (listener, redirect_uri) = listener().await;
+        let code = spawn_wait(listener, &redirect_uri, Duration::from_secs(5));
+
+        let status = get(&redirect_uri, "/callback?code=forged&state=other").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        let status = get(&redirect_uri, "/callback?code=abc&state=state").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(code.await.unwrap().unwrap(), "abc");
+    }
+
+    #[tokio::test]
+    async fn answers_other_paths_with_not_found() {
+        let (listener, redirect_uri) = listener().await;
+        let code = spawn_wait(listener, &redirect_uri, Duration::from_secs(5));
+
+        let status = get(&redirect_uri, "/favicon.ico?code=abc&state=state").await;
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        let status = get(&redirect_uri, "/callback?code=abc&state=state").await;
+        assert_eq!(status, "HTTP/1.1 200 OK");
+        assert_eq!(code.await.unwrap().unwrap(), "abc");
+    }
+
+    #[tokio::test]
+    async fn fails_with_the_error_spotify_returned() {
+        let (listener, redirect_uri) = listener().await;
+        let code = spawn_wait(listener, &redirect_uri, Duration::from_secs(5));
+
+        let status = get(&redirect_uri, "/callback?error=access_denied&state=state").await;
+        assert_eq!(status, "HTTP/1.1 400 Bad Request");
+        assert!(matches!(
+            code.await.unwrap(),
+            Err(SpotiAfkError::AuthorizationCallback(message))
+                if message == "Spotify returned access_denied"
+        ));
+    }
+
+    #[tokio::test]
+    async fn times_out_without_a_redirect() {
+        let (listener, redirect_uri) = listener().await;
+        let code = wait_for_code(listener, &redirect_uri, "state", Duration::from_millis(50)).await;
+        assert!(matches!(
+            code,
+            Err(SpotiAfkError::AuthorizationCallback(message)) if message.starts_with("timed out")
+        ));
+    }
+}
@@ -0,0 +1,121 @@
+/////////////
+// Imports //
+/////////////
+
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+// Self made files
+use crate::config::ConfigSources;
+
+///////////
+// Types //
+///////////
+
+// Command line arguments
+#[derive(Debug, Parser)]
+#[command(version, about = "Stay active on Spotify while AFK, using spotifyd")]
+pub struct Cli {
+    /// Config file to read instead of spotiafk.toml
+    #[arg(long, global = true, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Env file to read instead of .env
+    #[arg(long, global = true, value_name = "FILE")]
+    pub env_file: Option<PathBuf>,
+
+    /// Playlist to play as name, URI, URL or id, overrides PLAYLIST_NAME and PLAYLISTS
+    #[arg(long, global = true, value_name = "NAME")]
+    pub playlist: Option<String>,
+
+    /// Only use this account from the [accounts] section of the config file
+    #[arg(long, global = true, value_name = "NAME")]
+    pub account: Option<String>,
+
+    /// Show the login url as a QR code to scan with another device
+    #[arg(long, global = true)]
+    pub headless: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+// What to do, `run` when left out
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start spotifyd and AFK on the configured playlist, for every account (default)
+    Run,
+
+    /// Log in to Spotify and cache the token
+    Auth,
+
+    /// Validate the configuration and print it, for every account
+    CheckConfig,
+
+    /// List the playlists of the logged in user
+    ListPlaylists,
+
+    /// List the devices Spotify can play on
+    ListDevices,
+
+    /// Manage the spotifyd instance
+    Spotifyd {
+        #[command(subcommand)]
+        action: SpotifydAction,
+    },
+
+    /// Inspect or clear the cached playlists and tracks
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+// What to do with spotifyd
+#[derive(Debug, Subcommand)]
+pub enum SpotifydAction {
+    /// Write the spotifyd config if needed and start spotifyd
+    Start,
+
+    /// Stop the spotifyd started with this config
+    Stop,
+
+    /// Show if spotifyd is running
+    Status,
+}
+
+// What to do with the playlist cache
+#[derive(Debug, Subcommand)]
+pub enum CacheAction {
+    /// List the cached playlists and their snapshots
+    Show,
+
+    /// Delete the cache, playlists are fetched again on the next run
+    Clear,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl Cli {
+    // Config sources selected by the flags
+    pub fn config_sources(&self) -> ConfigSources {
+        let mut overrides = Vec::new();
+        if let Some(playlist) = &self.playlist {
+            overrides.push(("PLAYLIST_NAME", playlist.clone()));
+            // An empty list falls back to PLAYLIST_NAME
+            overrides.push(("PLAYLISTS", String::new()));
+        }
+        if self.headless {
+            overrides.push(("AUTH_HEADLESS", String::from("true")));
+        }
+
+        ConfigSources {
+            config_file: self.config.clone(),
+            env_file: self.env_file.clone(),
+            overrides,
+            account: self.account.clone(),
+        }
+    }
+}
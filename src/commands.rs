@@ -0,0 +1,280 @@
+/////////////
+// Imports //
+/////////////
+
+// Self made files
+use crate::{
+    auth::{auth_client, AuthClient},
+    cli::{CacheAction, SpotifydAction},
+    config::{AppConfig, RotationMode, ShortenerBackend, TokenEncryption, TrackOrder},
+    connectivity::probe,
+    error::SpotiAfkError,
+    functions::get_playlists,
+    playlist_cache::{self, PlaylistCache},
+    retry::Retrying,
+    spotify_api::SpotifyApi,
+    spotifyd::{init_spotifyd, spotifyd_pids, stop_spotifyd},
+};
+
+///////////////
+// Functions //
+///////////////
+
+// Log in and cache the token for later runs
+pub async fn auth<C: AuthClient>(config: &AppConfig) -> Result<(), SpotiAfkError> {
+    online_or_err(config).await?;
+    auth_client::<C>(config).await?;
+    match config.spotify.token_cached {
+        true => println!(
+            "Logged in, token cached at {}",
+            config.spotify.cache_path.display()
+        ),
+        false => {
+            println!("Logged in, but RSPOTIFY_CLIENT_TOKEN_CACHED is false so nothing was cached")
+        }
+    }
+    Ok(())
+}
+
+// Print the loaded configuration with secrets hidden
+pub fn check_config(config: &AppConfig) -> Result<(), SpotiAfkError> {
+    let hidden = |secret: &str| match secret.is_empty() {
+        true => String::from("(not set)"),
+        false => String::from("********"),
+    };
+
+    println!("Configuration is valid");
+    println!("[spotify]");
+    println!("  client_id = {}", config.spotify.client_id);
+    println!(
+        "  client_secret = {}",
+        hidden(&config.spotify.client_secret)
+    );
+    println!("  redirect_uri = {}", config.spotify.redirect_uri);
+    println!("  prefix = {}", config.spotify.prefix);
+    println!("  cache_path = {}", config.spotify.cache_path.display());
+    println!("  pagination_chunks = {}", config.spotify.pagination_chunks);
+    println!("  token_cached = {}", config.spotify.token_cached);
+    println!("  token_refreshing = {}", config.spotify.token_refreshing);
+    match &config.spotify.token_encryption {
+        TokenEncryption::Disabled => println!("  token_encryption = none"),
+        TokenEncryption::Env => println!("  token_encryption = env"),
+        TokenEncryption::File(key_file) => {
+            println!("  token_encryption = file");
+            println!("  token_key_file = {}", key_file.display());
+        }
+        TokenEncryption::Prompt => println!("  token_encryption = prompt"),
+    }
+    println!("  retries = {}", config.spotify.retries);
+    println!("[playback]");
+    for playlist in &config.playback.sources {
+        println!(
+            "  playlist = {} (weight {})",
+            playlist.selector, playlist.weight
+        );
+    }
+    match config.playback.rotation {
+        RotationMode::Weighted => println!("  rotation = weighted"),
+        RotationMode::RoundRobin(tracks) => {
+            println!("  rotation = round-robin");
+            println!("  rotation_tracks = {}", tracks);
+        }
+    }
+    match config.playback.order {
+        TrackOrder::InOrder => println!("  order = in-order"),
+        TrackOrder::Reverse => println!("  order = reverse"),
+        TrackOrder::Shuffle => println!("  order = shuffle"),
+        TrackOrder::NoRepeat(tracks) => {
+            println!("  order = no-repeat");
+            println!("  no_repeat_tracks = {}", tracks);
+        }
+        TrackOrder::LeastPlayed => println!("  order = least-played"),
+    }
+    if let Some(seed) = config.playback.seed {
+        println!("  seed = {}", seed);
+    }
+    println!(
+        "  poll_interval = {}s",
+        config.playback.poll_interval.as_secs()
+    );
+    println!(
+        "  checks_before_playing = {}",
+        config.playback.checks_before_playing
+    );
+    println!(
+        "  time_between_checks = {}s",
+        config.playback.time_between_checks.as_secs()
+    );
+    println!("  check_jitter = {}%", config.playback.check_jitter);
+    println!("  skip_tracks = {}", config.playback.skip_tracks);
+    println!("  listen_time = {}", config.playback.listen_time);
+    println!("  play_to_end = {}%", config.playback.play_to_end);
+    println!("  pause_chance = {}%", config.playback.pause_chance);
+    println!("  pause_length = {}", config.playback.pause_length);
+    println!("[filter]");
+    if let Some(min) = config.filter.min_duration {
+        println!("  min_duration = {}s", min.as_secs());
+    }
+    if let Some(max) = config.filter.max_duration {
+        println!("  max_duration = {}s", max.as_secs());
+    }
+    println!("  exclude_explicit = {}", config.filter.exclude_explicit);
+    println!(
+        "  exclude_artists = {}",
+        config.filter.exclude_artists.join(", ")
+    );
+    println!(
+        "  include_artists = {}",
+        config.filter.include_artists.join(", ")
+    );
+    println!("  dedupe = {}", config.filter.dedupe);
+    println!("[cache]");
+    println!("  enabled = {}", config.cache.enabled);
+    println!("  path = {}", config.cache.path.display());
+    println!("  wait = {}s", config.cache.wait.as_secs());
+    println!("[spotifyd]");
+    println!("  config_path = {}", config.spotifyd.config_path.display());
+    println!("  username = {}", config.spotifyd.username);
+    println!("  password = {}", hidden(&config.spotifyd.password));
+    println!("  device_name = {}", config.spotifyd.device_name);
+    println!(
+        "  connect_timeout = {}s",
+        config.spotifyd.connect_timeout.as_secs()
+    );
+    println!("[auth]");
+    println!("  flow = {}", config.auth.flow);
+    println!("  headless = {}", config.auth.headless);
+    println!("  listener = {}", config.auth.listener);
+    println!(
+        "  listener_bind = {}",
+        match config.auth.listener_bind {
+            Some(address) => address.to_string(),
+            None => String::from("(not set)"),
+        }
+    );
+    println!(
+        "  listener_timeout = {}s",
+        config.auth.listener_timeout.as_secs()
+    );
+    println!("  token_url = {}", config.auth.token_url);
+    println!("  reauth = {}", config.auth.reauth);
+    println!("[shortener]");
+    match &config.shortener.backend {
+        ShortenerBackend::Disabled => println!("  backend = none"),
+        ShortenerBackend::Bitly { token } => {
+            println!("  backend = bitly");
+            println!("  bitly_api_token = {}", hidden(token));
+        }
+        ShortenerBackend::Yourls { url, signature } => {
+            println!("  backend = yourls");
+            println!("  url = {}", url);
+            println!("  api_key = {}", hidden(signature.as_deref().unwrap_or("")));
+        }
+        ShortenerBackend::Shlink { url, api_key } => {
+            println!("  backend = shlink");
+            println!("  url = {}", url);
+            println!("  api_key = {}", hidden(api_key));
+        }
+    }
+    println!("  timeout = {}s", config.shortener.timeout.as_secs());
+    println!("[network]");
+    println!("  probe = {}", config.network.probe);
+    println!("  interval = {}s", config.network.interval.as_secs());
+    Ok(())
+}
+
+// Print the playlists of the logged in user
+pub async fn list_playlists<C: AuthClient>(config: &AppConfig) -> Result<(), SpotiAfkError> {
+    online_or_err(config).await?;
+    let client: C = auth_client(config).await?;
+    let api = Retrying::new(&client, config.spotify.retries);
+    for playlist in get_playlists(&api).await? {
+        println!(
+            "{}  {} ({} tracks)",
+            playlist.id, playlist.name, playlist.track_count
+        );
+    }
+    Ok(())
+}
+
+// Print the devices Spotify knows about
+pub async fn list_devices<C: AuthClient>(config: &AppConfig) -> Result<(), SpotiAfkError> {
+    online_or_err(config).await?;
+    let client: C = auth_client(config).await?;
+    let devices = Retrying::new(&client, config.spotify.retries)
+        .devices()
+        .await
+        .map_err(SpotiAfkError::spotify("getting devices"))?;
+    for device in devices {
+        println!(
+            "{}  {} ({}){}",
+            device.id.as_deref().unwrap_or("-"),
+            device.name,
+            device.kind,
+            match device.is_active {
+                true => ", active",
+                false => "",
+            }
+        );
+    }
+    Ok(())
+}
+
+// Start, stop or check spotifyd
+pub fn spotifyd(config: &AppConfig, action: &SpotifydAction) -> Result<(), SpotiAfkError> {
+    match action {
+        SpotifydAction::Start => {
+            init_spotifyd(&config.spotifyd)?;
+            println!("Started spotifyd as {}", config.spotifyd.device_name);
+        }
+        SpotifydAction::Stop => {
+            stop_spotifyd(&config.spotifyd)?;
+            println!("Stopped spotifyd");
+        }
+        SpotifydAction::Status => {
+            let pids = spotifyd_pids(&config.spotifyd)?;
+            match pids.is_empty() {
+                true => println!("spotifyd is not running"),
+                false => println!("spotifyd is running (pid {})", pids.join(", ")),
+            }
+        }
+    }
+    Ok(())
+}
+
+// Show or clear the playlist cache
+pub fn cache(config: &AppConfig, action: &CacheAction) -> Result<(), SpotiAfkError> {
+    let path = config.cache.path.display();
+    match action {
+        CacheAction::Show => {
+            if !config.cache.enabled {
+                println!("The playlist cache is turned off with PLAYLIST_CACHE");
+                return Ok(());
+            }
+            let cache = PlaylistCache::open(&config.cache);
+            println!("{} playlists listed in {}", cache.playlists().len(), path);
+            for cached in cache.cached() {
+                println!(
+                    "{}  {} ({} tracks, snapshot {})",
+                    cached.playlist.id,
+                    cached.playlist.name,
+                    cached.tracks.len(),
+                    cached.playlist.snapshot_id
+                );
+            }
+        }
+        CacheAction::Clear => match playlist_cache::clear(&config.cache)? {
+            true => println!("Cleared {}", path),
+            false => println!("There is no playlist cache at {}", path),
+        },
+    }
+    Ok(())
+}
+
+// Fail early when there is no internet connection
+async fn online_or_err(config: &AppConfig) -> Result<(), SpotiAfkError> {
+    match probe(&config.network.probe).await {
+        true => Ok(()),
+        false => Err(SpotiAfkError::Offline),
+    }
+}
@@ -0,0 +1,2070 @@
+/////////////
+// Imports //
+/////////////
+
+use regex::Regex;
+use rspotify::{DEFAULT_API_PREFIX, DEFAULT_CACHE_PATH, DEFAULT_PAGINATION_CHUNKS};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fmt, fs,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+use url::Url;
+
+// Self made files
+use crate::error::SpotiAfkError;
+
+///////////////
+// Constants //
+///////////////
+
+// Config files looked for in the working directory when no config file is given
+const CONFIG_FILES: [&str; 3] = ["spotiafk.toml", "spotiafk.yaml", "spotiafk.yml"];
+
+// Every setting as `(section, key in config file, environment variable)`
+const SETTINGS: &[(&str, &str, &str)] = &[
+    ("spotify", "client_id", "RSPOTIFY_CLIENT_ID"),
+    ("spotify", "client_secret", "RSPOTIFY_CLIENT_SECRET"),
+    ("spotify", "redirect_uri", "RSPOTIFY_REDIRECT_URI"),
+    ("spotify", "prefix", "RSPOTIFY_CLIENT_PREFIX"),
+    ("spotify", "cache_path", "RSPOTIFY_CLIENT_CACHE_PATH"),
+    (
+        "spotify",
+        "pagination_chunks",
+        "RSPOTIFY_CLIENT_PAGINATION_CHUNKS",
+    ),
+    ("spotify", "token_cached", "RSPOTIFY_CLIENT_TOKEN_CACHED"),
+    (
+        "spotify",
+        "token_refreshing",
+        "RSPOTIFY_CLIENT_TOKEN_REFRESHING",
+    ),
+    ("spotify", "token_encryption", "TOKEN_ENCRYPTION"),
+    ("spotify", "token_key_file", "TOKEN_KEY_FILE"),
+    ("spotify", "retries", "API_RETRIES"),
+    ("playback", "playlist_name", "PLAYLIST_NAME"),
+    ("playback", "playlist_match", "PLAYLIST_MATCH"),
+    ("playback", "playlists", "PLAYLISTS"),
+    ("playback", "rotation", "PLAYLIST_ROTATION"),
+    ("playback", "rotation_tracks", "ROTATION_TRACKS"),
+    ("playback", "order", "PLAYBACK_ORDER"),
+    ("playback", "no_repeat_tracks", "NO_REPEAT_TRACKS"),
+    ("playback", "seed", "SHUFFLE_SEED"),
+    ("playback", "poll_interval", "PLAYLIST_POLL_INTERVAL"),
+    ("playback", "checks_before_playing", "CHECKS_BEFORE_PLAYING"),
+    ("playback", "time_between_checks", "TIME_BETWEEN_CHECKS"),
+    ("playback", "check_jitter", "CHECK_JITTER"),
+    ("playback", "stop_when_taken_over", "STOP_WHEN_TAKEN_OVER"),
+    ("playback", "skip_tracks", "SKIP_TRACKS"),
+    ("playback", "wait_till_skip", "WAIT_TILL_SKIP"),
+    ("playback", "listen_time", "LISTEN_TIME"),
+    ("playback", "play_to_end", "PLAY_TO_END_CHANCE"),
+    ("playback", "pause_chance", "PAUSE_CHANCE"),
+    ("playback", "pause_length", "PAUSE_LENGTH"),
+    ("filter", "min_duration", "FILTER_MIN_DURATION"),
+    ("filter", "max_duration", "FILTER_MAX_DURATION"),
+    ("filter", "exclude_explicit", "FILTER_EXCLUDE_EXPLICIT"),
+    ("filter", "exclude_artists", "FILTER_EXCLUDE_ARTISTS"),
+    ("filter", "include_artists", "FILTER_INCLUDE_ARTISTS"),
+    ("filter", "dedupe", "FILTER_DEDUPE"),
+    ("cache", "enabled", "PLAYLIST_CACHE"),
+    ("cache", "path", "PLAYLIST_CACHE_PATH"),
+    ("cache", "wait", "PLAYLIST_CACHE_WAIT"),
+    ("spotifyd", "config_path", "SPOTIFYD_CONFIG_PATH"),
+    ("spotifyd", "username", "SPOTIFYD_USERNAME"),
+    ("spotifyd", "password", "SPOTIFYD_PASSWORD"),
+    ("spotifyd", "device_name", "SPOTIFYD_DEVICE_NAME"),
+    ("spotifyd", "connect_timeout", "SPOTIFYD_CONNECT_TIMEOUT"),
+    ("shortener", "backend", "SHORTENER_BACKEND"),
+    ("shortener", "bitly_api_token", "BITLY_API_TOKEN"),
+    ("shortener", "url", "SHORTENER_URL"),
+    ("shortener", "api_key", "SHORTENER_API_KEY"),
+    ("shortener", "timeout", "SHORTENER_TIMEOUT"),
+    ("auth", "flow", "AUTH_FLOW"),
+    ("auth", "headless", "AUTH_HEADLESS"),
+    ("auth", "listener", "AUTH_LISTENER"),
+    ("auth", "listener_bind", "AUTH_LISTENER_BIND"),
+    ("auth", "listener_timeout", "AUTH_LISTENER_TIMEOUT"),
+    ("auth", "token_url", "AUTH_TOKEN_URL"),
+    ("auth", "reauth", "AUTH_REAUTH"),
+    ("network", "probe", "NETWORK_PROBE"),
+    ("network", "interval", "NETWORK_PROBE_INTERVAL"),
+];
+
+// Built-in values for settings that are not required
+const DEFAULTS: &[(&str, &str)] = &[
+    ("RSPOTIFY_REDIRECT_URI", "http://localhost:8888/"),
+    ("RSPOTIFY_CLIENT_PREFIX", "default"),
+    ("RSPOTIFY_CLIENT_CACHE_PATH", "default"),
+    ("RSPOTIFY_CLIENT_PAGINATION_CHUNKS", "default"),
+    ("RSPOTIFY_CLIENT_TOKEN_CACHED", "true"),
+    ("RSPOTIFY_CLIENT_TOKEN_REFRESHING", "true"),
+    ("TOKEN_ENCRYPTION", "none"),
+    ("API_RETRIES", "5"),
+    ("PLAYLIST_MATCH", "auto"),
+    ("PLAYLIST_ROTATION", "weighted"),
+    ("ROTATION_TRACKS", "1"),
+    ("PLAYBACK_ORDER", "reverse"),
+    ("NO_REPEAT_TRACKS", "10"),
+    ("PLAYLIST_POLL_INTERVAL", "300"),
+    ("CHECKS_BEFORE_PLAYING", "5"),
+    ("TIME_BETWEEN_CHECKS", "30"),
+    ("CHECK_JITTER", "0"),
+    ("STOP_WHEN_TAKEN_OVER", "false"),
+    ("SKIP_TRACKS", "true"),
+    ("WAIT_TILL_SKIP", "35"),
+    ("LISTEN_TIME", "default"),
+    ("PLAY_TO_END_CHANCE", "0"),
+    ("PAUSE_CHANCE", "0"),
+    ("PAUSE_LENGTH", "10-60"),
+    ("FILTER_EXCLUDE_EXPLICIT", "false"),
+    ("FILTER_DEDUPE", "true"),
+    ("PLAYLIST_CACHE", "true"),
+    ("PLAYLIST_CACHE_PATH", "default"),
+    ("PLAYLIST_CACHE_WAIT", "10"),
+    ("SPOTIFYD_CONFIG_PATH", ".spotifyd.conf"),
+    ("SPOTIFYD_DEVICE_NAME", "AFK_DEVICE"),
+    ("SPOTIFYD_CONNECT_TIMEOUT", "30"),
+    ("SHORTENER_BACKEND", "auto"),
+    ("SHORTENER_TIMEOUT", "10"),
+    ("AUTH_FLOW", "code"),
+    ("AUTH_HEADLESS", "auto"),
+    ("AUTH_LISTENER", "true"),
+    ("AUTH_LISTENER_TIMEOUT", "300"),
+    ("AUTH_TOKEN_URL", "https://accounts.spotify.com/api/token"),
+    ("AUTH_REAUTH", "false"),
+    ("NETWORK_PROBE", "auto"),
+    ("NETWORK_PROBE_INTERVAL", "10"),
+];
+
+///////////
+// Types //
+///////////
+
+// All settings of the program, loaded and validated once at startup
+#[derive(Debug, Clone)]
+pub struct AppConfig {
+    pub spotify: SpotifyConfig,
+    pub playback: PlaybackConfig,
+    pub filter: FilterConfig,
+    pub cache: CacheConfig,
+    pub spotifyd: SpotifydConfig,
+    pub shortener: ShortenerConfig,
+    pub auth: AuthConfig,
+    pub network: NetworkConfig,
+}
+
+// Settings of one account profile, `name` is None without an [accounts] section
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub name: Option<String>,
+    pub config: AppConfig,
+}
+
+// Spotify API credentials and rspotify client settings
+#[derive(Debug, Clone)]
+pub struct SpotifyConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub prefix: String,
+    pub cache_path: PathBuf,
+    pub pagination_chunks: u32,
+    pub token_cached: bool,
+    pub token_refreshing: bool,
+    pub token_encryption: TokenEncryption,
+
+    // Times a throttled or failed request is tried again
+    pub retries: u32,
+}
+
+// Where the key to encrypt the cached token comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenEncryption {
+    // Cache the token as plain JSON
+    Disabled,
+
+    // Passphrase in the TOKEN_KEY environment variable
+    Env,
+
+    // Passphrase in a file
+    File(PathBuf),
+
+    // Ask for the passphrase when the cache is used
+    Prompt,
+}
+
+// What to play and when
+#[derive(Debug, Clone)]
+pub struct PlaybackConfig {
+    pub sources: Vec<WeightedSource>,
+    pub rotation: RotationMode,
+    pub order: TrackOrder,
+
+    // Seed for every random choice, a random one is logged when left out
+    pub seed: Option<u64>,
+
+    // How often playlists are checked for changes while playing, zero never
+    pub poll_interval: Duration,
+    pub checks_before_playing: u32,
+    pub time_between_checks: Duration,
+
+    // Percentage the time between checks is made longer or shorter at random
+    pub check_jitter: u32,
+
+    // End the session when another device takes playback over, instead of waiting for it to stop
+    pub stop_when_taken_over: bool,
+    pub skip_tracks: bool,
+
+    // How long a track is listened to before it is skipped
+    pub listen_time: ListenTime,
+
+    // Listen time for tracks of unknown length when LISTEN_TIME is a part of the track
+    pub wait_till_skip: Duration,
+
+    // Percentages of tracks played to the end anyway and of tracks paused once
+    pub play_to_end: u32,
+    pub pause_chance: u32,
+    pub pause_length: Bounds<Duration>,
+}
+
+// How long a track is listened to before it is skipped, picked for every track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenTime {
+    // Seconds between the bounds, WAIT_TILL_SKIP when LISTEN_TIME is default
+    Seconds(Bounds<Duration>),
+
+    // Part of the track in percent
+    Percent(Bounds<u32>),
+}
+
+// Random value between two bounds, both included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Bounds<T> {
+    pub min: T,
+    pub max: T,
+}
+
+// Rules for the tracks that may be played, unplayable tracks are always left out
+#[derive(Debug, Clone, Default)]
+pub struct FilterConfig {
+    pub min_duration: Option<Duration>,
+    pub max_duration: Option<Duration>,
+    pub exclude_explicit: bool,
+
+    // Artist names in lowercase, an empty include list allows every artist
+    pub exclude_artists: Vec<String>,
+    pub include_artists: Vec<String>,
+
+    // Play a track that is in a source more than once only once per round
+    pub dedupe: bool,
+}
+
+// Playlists and their tracks kept on disk between runs
+#[derive(Debug, Clone, Default)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub path: PathBuf,
+
+    // How long Spotify may take before the cached copy is used, zero waits for the answer
+    pub wait: Duration,
+}
+
+// Where to take tracks from and how often compared to the others
+#[derive(Debug, Clone)]
+pub struct WeightedSource {
+    pub selector: SourceSelector,
+    pub weight: u32,
+}
+
+// How the next track is picked from the sources
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotationMode {
+    // From a random source, chosen by weight
+    #[default]
+    Weighted,
+
+    // This many tracks from every source in turn
+    RoundRobin(u32),
+}
+
+// Order the tracks of a source are played in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrackOrder {
+    InOrder,
+
+    // Last track first, like the program always did
+    #[default]
+    Reverse,
+
+    // Shuffled again every time all tracks were played
+    Shuffle,
+
+    // Random, but none of the last this many tracks
+    NoRepeat(usize),
+
+    // Random, tracks played less often are more likely
+    LeastPlayed,
+}
+
+// What to play tracks from
+#[derive(Debug, Clone)]
+pub enum SourceSelector {
+    // Any playlist, fetched directly by its Spotify id
+    Playlist(String),
+
+    // One of the user's playlists, found by its name
+    PlaylistName { name: String, matching: NameMatch },
+
+    // Album by its id
+    Album(String),
+
+    // Top tracks of the artist with this id
+    TopTracks(String),
+
+    // Every album and single of the artist with this id
+    Discography(String),
+
+    // Liked Songs of the user
+    LikedSongs,
+
+    // Episodes of the podcast show with this id
+    Show(String),
+}
+
+// How a playlist name is compared to PLAYLIST_NAME
+#[derive(Debug, Clone, Default)]
+pub enum NameMatch {
+    #[default]
+    Exact,
+    IgnoreCase,
+    Regex(Regex),
+}
+
+// Settings written to the spotifyd config file
+#[derive(Debug, Clone)]
+pub struct SpotifydConfig {
+    pub config_path: PathBuf,
+    pub username: String,
+    pub password: String,
+    pub device_name: String,
+
+    // Longest wait for spotifyd to show up as a device after it was started
+    pub connect_timeout: Duration,
+}
+
+// Optional link shortener for the authorize url
+#[derive(Debug, Clone)]
+pub struct ShortenerConfig {
+    pub backend: ShortenerBackend,
+    pub timeout: Duration,
+}
+
+// Service that shortens the authorize url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShortenerBackend {
+    // Show the long url
+    Disabled,
+
+    // bit.ly, with an API token from <https://dev.bitly.com/>
+    Bitly {
+        token: String,
+    },
+
+    // Self-hosted YOURLS, the signature is not needed on public instances
+    Yourls {
+        url: String,
+        signature: Option<String>,
+    },
+
+    // Self-hosted Shlink
+    Shlink {
+        url: String,
+        api_key: String,
+    },
+}
+
+// How the user logs in to Spotify
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub flow: AuthFlow,
+    pub headless: Headless,
+    pub listener: bool,
+    pub listener_bind: Option<IpAddr>,
+    pub listener_timeout: Duration,
+    pub token_url: String,
+    pub reauth: bool,
+}
+
+// How the connection to Spotify is watched
+#[derive(Debug, Clone)]
+pub struct NetworkConfig {
+    // Address as host:port that is connected to, to see if we are online
+    pub probe: String,
+    pub interval: Duration,
+}
+
+// OAuth flow used to get a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AuthFlow {
+    // Authorization code flow, needs the client secret
+    #[default]
+    Code,
+
+    // Authorization code flow with PKCE, works without the client secret
+    Pkce,
+}
+
+// When to show the authorize url as a QR code for another device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Headless {
+    // Only when there is no graphical session
+    #[default]
+    Auto,
+
+    // Always
+    On,
+
+    // Never
+    Off,
+}
+
+// A single problem found while validating the settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigIssue {
+    // Setting is not set at all
+    Missing(&'static str),
+
+    // Setting is set to something unusable
+    Invalid {
+        key: &'static str,
+        value: String,
+        expected: &'static str,
+    },
+
+    // Config file contains a setting that does not exist
+    Unknown(String),
+
+    // Problem in the settings of one account
+    InAccount {
+        account: String,
+        issue: Box<ConfigIssue>,
+    },
+
+    // --account names an account that is not in the config file
+    UnknownAccount(String),
+
+    // Several accounts are configured but the command works on one
+    AccountRequired(Vec<String>),
+
+    // Two accounts use the same file or device, so they would get in each other's way
+    Shared {
+        key: &'static str,
+        accounts: (String, String),
+    },
+}
+
+// Sections of a config file, accounts have sections of their own
+#[derive(Debug, Deserialize)]
+struct ConfigFile {
+    #[serde(default)]
+    accounts: BTreeMap<String, BTreeMap<String, BTreeMap<String, FileValue>>>,
+
+    #[serde(flatten)]
+    sections: BTreeMap<String, BTreeMap<String, FileValue>>,
+}
+
+// Settings read from a config file, keyed by environment variable
+#[derive(Debug, Default)]
+struct FileSettings {
+    values: HashMap<String, String>,
+    accounts: BTreeMap<String, HashMap<String, String>>,
+    issues: Vec<ConfigIssue>,
+}
+
+// A single value in a config file, written to the variables as text
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FileValue {
+    Flag(bool),
+    Integer(i64),
+    Float(f64),
+    Text(String),
+}
+
+// Where to load settings from, filled in from the command line
+#[derive(Debug, Clone, Default)]
+pub struct ConfigSources {
+    // Config file to read instead of looking for one
+    pub config_file: Option<PathBuf>,
+
+    // .env file to read instead of `.env`
+    pub env_file: Option<PathBuf>,
+
+    // Settings that override every other source, keyed by environment variable
+    pub overrides: Vec<(&'static str, String)>,
+
+    // Only load this account from the [accounts] section
+    pub account: Option<String>,
+}
+
+// Reads settings out of a set of variables and remembers every problem
+struct Variables<'a> {
+    values: &'a HashMap<String, String>,
+    issues: Vec<ConfigIssue>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl AppConfig {
+    // Load settings of every account, later sources override earlier ones:
+    // built-in defaults, config file, .env file, environment variables,
+    // the account's sections in the config file, overrides
+    pub fn load_accounts(sources: &ConfigSources) -> Result<Vec<Account>, SpotiAfkError> {
+        let mut values: HashMap<String, String> = DEFAULTS
+            .iter()
+            .map(|(key, value)| (String::from(*key), String::from(*value)))
+            .collect();
+
+        // Config file
+        let file = match config_file_path(sources)? {
+            Some(path) => read_config_file(&path)?,
+            None => FileSettings::default(),
+        };
+        values.extend(file.values);
+        let mut issues = file.issues;
+
+        // .env only sets variables that are not in the environment yet
+        match &sources.env_file {
+            Some(path) => {
+                dotenv::from_path(path).map_err(|e| SpotiAfkError::ConfigFile {
+                    path: path.clone(),
+                    reason: e.to_string(),
+                })?;
+            }
+            None => {
+                dotenv::from_filename(".env").ok();
+            }
+        }
+        values.extend(env::vars());
+
+        // Without accounts the shared settings are the only account,
+        // an account's sections override the shared settings and the environment
+        let mut accounts = match file.accounts.is_empty() {
+            true => vec![(None, values)],
+            false => file
+                .accounts
+                .into_iter()
+                .map(|(name, account_values)| {
+                    let mut values = values.clone();
+                    values.extend(account_values);
+                    (Some(name), values)
+                })
+                .collect(),
+        };
+
+        // Pick the account from --account
+        if let Some(name) = &sources.account {
+            accounts.retain(|(account, _)| account.as_ref() == Some(name));
+            if accounts.is_empty() {
+                issues.push(ConfigIssue::UnknownAccount(name.clone()));
+            }
+        }
+
+        // Command line flags
+        let mut loaded = Vec::new();
+        for (name, mut values) in accounts {
+            for (key, value) in &sources.overrides {
+                values.insert(String::from(*key), value.clone());
+            }
+            match (Self::validate(&values, Vec::new()), &name) {
+                (Ok(config), _) => loaded.push(Account { name, config }),
+                (Err(SpotiAfkError::Config(account_issues)), Some(account)) => issues.extend(
+                    account_issues
+                        .into_iter()
+                        .map(|issue| ConfigIssue::InAccount {
+                            account: account.clone(),
+                            issue: Box::new(issue),
+                        }),
+                ),
+                (Err(SpotiAfkError::Config(account_issues)), None) => issues.extend(account_issues),
+                (Err(e), _) => return Err(e),
+            }
+        }
+        issues.extend(shared_settings(&loaded));
+
+        match issues.is_empty() {
+            true => Ok(loaded),
+            false => Err(SpotiAfkError::Config(issues)),
+        }
+    }
+
+    // Build the config from key value pairs, reporting all issues at once
+    fn validate(
+        values: &HashMap<String, String>,
+        issues: Vec<ConfigIssue>,
+    ) -> Result<Self, SpotiAfkError> {
+        let mut vars = Variables { values, issues };
+        let wait_till_skip = vars.seconds("WAIT_TILL_SKIP");
+        let flow = vars.choice(
+            "AUTH_FLOW",
+            &[("code", AuthFlow::Code), ("pkce", AuthFlow::Pkce)],
+            "code or pkce",
+        );
+
+        let prefix = vars.or_default("RSPOTIFY_CLIENT_PREFIX", DEFAULT_API_PREFIX);
+        let token_cache =
+            PathBuf::from(vars.or_default("RSPOTIFY_CLIENT_CACHE_PATH", DEFAULT_CACHE_PATH));
+
+        let config = AppConfig {
+            spotify: SpotifyConfig {
+                client_id: vars.required("RSPOTIFY_CLIENT_ID"),
+                // PKCE does not need the secret, so it does not have to be on disk
+                client_secret: match flow {
+                    AuthFlow::Code => vars.required("RSPOTIFY_CLIENT_SECRET"),
+                    AuthFlow::Pkce => vars.optional("RSPOTIFY_CLIENT_SECRET").unwrap_or_default(),
+                },
+                redirect_uri: vars.required("RSPOTIFY_REDIRECT_URI"),
+                prefix: prefix.clone(),
+                cache_path: token_cache.clone(),
+                pagination_chunks: vars.pagination_chunks("RSPOTIFY_CLIENT_PAGINATION_CHUNKS"),
+                token_cached: vars.flag("RSPOTIFY_CLIENT_TOKEN_CACHED"),
+                token_refreshing: vars.flag("RSPOTIFY_CLIENT_TOKEN_REFRESHING"),
+                token_encryption: vars.token_encryption("TOKEN_ENCRYPTION"),
+                retries: vars.number("API_RETRIES"),
+            },
+            playback: PlaybackConfig {
+                sources: vars.sources("PLAYLISTS", "PLAYLIST_NAME", "PLAYLIST_MATCH"),
+                rotation: vars.rotation("PLAYLIST_ROTATION", "ROTATION_TRACKS"),
+                order: vars.order("PLAYBACK_ORDER", "NO_REPEAT_TRACKS"),
+                seed: vars.optional_number("SHUFFLE_SEED"),
+                poll_interval: vars.seconds("PLAYLIST_POLL_INTERVAL"),
+                checks_before_playing: vars.number("CHECKS_BEFORE_PLAYING"),
+                time_between_checks: vars.seconds("TIME_BETWEEN_CHECKS"),
+                check_jitter: vars.percentage("CHECK_JITTER"),
+                stop_when_taken_over: vars.flag("STOP_WHEN_TAKEN_OVER"),
+                skip_tracks: vars.flag("SKIP_TRACKS"),
+                listen_time: vars.listen_time("LISTEN_TIME", wait_till_skip),
+                wait_till_skip,
+                play_to_end: vars.percentage("PLAY_TO_END_CHANCE"),
+                pause_chance: vars.percentage("PAUSE_CHANCE"),
+                pause_length: vars.seconds_between("PAUSE_LENGTH"),
+            },
+            filter: FilterConfig {
+                min_duration: vars.optional_seconds("FILTER_MIN_DURATION"),
+                max_duration: vars.optional_seconds("FILTER_MAX_DURATION"),
+                exclude_explicit: vars.flag("FILTER_EXCLUDE_EXPLICIT"),
+                exclude_artists: vars.names("FILTER_EXCLUDE_ARTISTS"),
+                include_artists: vars.names("FILTER_INCLUDE_ARTISTS"),
+                dedupe: vars.flag("FILTER_DEDUPE"),
+            },
+            // Next to the token cache by default, so every account has its own
+            cache: CacheConfig {
+                enabled: vars.flag("PLAYLIST_CACHE"),
+                path: PathBuf::from(
+                    vars.or_default(
+                        "PLAYLIST_CACHE_PATH",
+                        &token_cache
+                            .with_extension("playlists.json")
+                            .to_string_lossy(),
+                    ),
+                ),
+                wait: vars.seconds("PLAYLIST_CACHE_WAIT"),
+            },
+            spotifyd: SpotifydConfig {
+                config_path: PathBuf::from(vars.required("SPOTIFYD_CONFIG_PATH")),
+                username: vars.required("SPOTIFYD_USERNAME"),
+                password: vars.required("SPOTIFYD_PASSWORD"),
+                device_name: vars.required("SPOTIFYD_DEVICE_NAME"),
+                connect_timeout: vars.seconds("SPOTIFYD_CONNECT_TIMEOUT"),
+            },
+            shortener: ShortenerConfig {
+                backend: vars.shortener_backend("SHORTENER_BACKEND"),
+                timeout: vars.seconds("SHORTENER_TIMEOUT"),
+            },
+            auth: AuthConfig {
+                flow,
+                headless: vars.choice(
+                    "AUTH_HEADLESS",
+                    &[
+                        ("auto", Headless::Auto),
+                        ("true", Headless::On),
+                        ("false", Headless::Off),
+                    ],
+                    "auto, true or false",
+                ),
+                listener: vars.flag("AUTH_LISTENER"),
+                listener_bind: vars.address("AUTH_LISTENER_BIND"),
+                listener_timeout: vars.seconds("AUTH_LISTENER_TIMEOUT"),
+                token_url: vars.required("AUTH_TOKEN_URL"),
+                reauth: vars.flag("AUTH_REAUTH"),
+            },
+            network: NetworkConfig {
+                probe: vars.probe("NETWORK_PROBE", &prefix),
+                interval: vars.interval("NETWORK_PROBE_INTERVAL"),
+            },
+        };
+
+        match vars.issues.is_empty() {
+            true => Ok(config),
+            false => Err(SpotiAfkError::Config(vars.issues)),
+        }
+    }
+}
+
+impl SpotifyConfig {
+    // Settings for the rspotify client
+    pub fn client_config(&self) -> rspotify::Config {
+        rspotify::Config {
+            prefix: self.prefix.clone(),
+            cache_path: self.cache_path.clone(),
+            pagination_chunks: self.pagination_chunks,
+            // The token cache is ours, rspotify would write the token unencrypted
+            token_cached: false,
+            // The token supervisor refreshes, rspotify panics when its refresh fails
+            token_refreshing: false,
+        }
+    }
+}
+
+impl<'a> Variables<'a> {
+    // Setting that has to be present
+    fn required(&mut self, key: &'static str) -> String {
+        match self.values.get(key) {
+            Some(value) => value.clone(),
+            None => {
+                self.issues.push(ConfigIssue::Missing(key));
+                String::new()
+            }
+        }
+    }
+
+    // Setting that may be left out
+    fn optional(&self, key: &'static str) -> Option<String> {
+        self.values.get(key).cloned()
+    }
+
+    // Setting that has to be present but can be "default"
+    fn or_default(&mut self, key: &'static str, default: &str) -> String {
+        match self.required(key).as_str() {
+            "default" => String::from(default),
+            value => String::from(value),
+        }
+    }
+
+    // Setting that is either true or false
+    fn flag(&mut self, key: &'static str) -> bool {
+        match self.values.get(key).map(String::as_str) {
+            Some("true") => true,
+            Some("false") => false,
+            _ => self.reject(key, "true or false"),
+        }
+    }
+
+    // Setting that is one of a fixed set of words
+    fn choice<T: Copy + Default>(
+        &mut self,
+        key: &'static str,
+        options: &[(&str, T)],
+        expected: &'static str,
+    ) -> T {
+        let value = self.values.get(key).map(String::as_str);
+        match options.iter().find(|(name, _)| Some(*name) == value) {
+            Some((_, option)) => *option,
+            None => self.reject(key, expected),
+        }
+    }
+
+    // Setting that is a positive number
+    fn number<T: FromStr + Default>(&mut self, key: &'static str) -> T {
+        match self.values.get(key).map(|value| value.parse::<T>()) {
+            Some(Ok(number)) => number,
+            _ => self.reject(key, "a positive number"),
+        }
+    }
+
+    // Setting that is an amount of seconds
+    fn seconds(&mut self, key: &'static str) -> Duration {
+        Duration::from_secs(self.number(key))
+    }
+
+    // Setting that is the seconds between rounds of a loop, which would never wait at 0
+    fn interval(&mut self, key: &'static str) -> Duration {
+        match self.values.get(key).map(|value| value.parse::<u64>()) {
+            Some(Ok(seconds)) if seconds > 0 => Duration::from_secs(seconds),
+            _ => self.reject(key, "at least 1 second"),
+        }
+    }
+
+    // Setting that is a percentage, the % sign may be left out
+    fn percentage(&mut self, key: &'static str) -> u32 {
+        let value = self
+            .values
+            .get(key)
+            .map(|value| value.trim().trim_end_matches('%'));
+        match value.map(str::parse::<u32>) {
+            Some(Ok(percent)) if percent <= 100 => percent,
+            _ => self.reject(key, "a percentage from 0 to 100"),
+        }
+    }
+
+    // Setting that is an amount of seconds or a range of them like 10-60
+    fn seconds_between(&mut self, key: &'static str) -> Bounds<Duration> {
+        match self.values.get(key).and_then(|value| bounds::<u64>(value)) {
+            Some(seconds) => Bounds {
+                min: Duration::from_secs(seconds.min),
+                max: Duration::from_secs(seconds.max),
+            },
+            None => self.reject(key, "seconds like 35 or 10-60"),
+        }
+    }
+
+    // Optional setting that is a positive number, empty leaves it out
+    fn optional_number<T: FromStr>(&mut self, key: &'static str) -> Option<T> {
+        match self.values.get(key).map(|value| value.trim()) {
+            Some("") | None => None,
+            Some(value) => match value.parse::<T>() {
+                Ok(number) => Some(number),
+                Err(_) => self.reject(key, "a positive number"),
+            },
+        }
+    }
+
+    // Optional setting that is an amount of seconds
+    fn optional_seconds(&mut self, key: &'static str) -> Option<Duration> {
+        self.optional_number(key).map(Duration::from_secs)
+    }
+
+    // Comma separated names, compared without case
+    fn names(&self, key: &'static str) -> Vec<String> {
+        self.optional(key)
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    // Shortener and the settings it needs
+    fn shortener_backend(&mut self, key: &'static str) -> ShortenerBackend {
+        let backend = self.choice(
+            key,
+            &[
+                ("auto", "auto"),
+                ("none", "none"),
+                ("bitly", "bitly"),
+                ("yourls", "yourls"),
+                ("shlink", "shlink"),
+            ],
+            "auto, none, bitly, yourls or shlink",
+        );
+        match backend {
+            "bitly" => ShortenerBackend::Bitly {
+                token: self.required("BITLY_API_TOKEN"),
+            },
+            "yourls" => ShortenerBackend::Yourls {
+                url: self.required("SHORTENER_URL"),
+                signature: self.optional("SHORTENER_API_KEY"),
+            },
+            "shlink" => ShortenerBackend::Shlink {
+                url: self.required("SHORTENER_URL"),
+                api_key: self.required("SHORTENER_API_KEY"),
+            },
+            // Auto spares the Bitly quota when there is no graphical session
+            "auto" => match (self.optional("BITLY_API_TOKEN"), env::var_os("DISPLAY")) {
+                (Some(token), Some(_)) => ShortenerBackend::Bitly { token },
+                _ => ShortenerBackend::Disabled,
+            },
+            _ => ShortenerBackend::Disabled,
+        }
+    }
+
+    // Token encryption and the settings it needs
+    fn token_encryption(&mut self, key: &'static str) -> TokenEncryption {
+        let encryption = self.choice(
+            key,
+            &[
+                ("none", "none"),
+                ("env", "env"),
+                ("file", "file"),
+                ("prompt", "prompt"),
+            ],
+            "none, env, file or prompt",
+        );
+        match encryption {
+            "env" => TokenEncryption::Env,
+            "file" => TokenEncryption::File(PathBuf::from(self.required("TOKEN_KEY_FILE"))),
+            "prompt" => TokenEncryption::Prompt,
+            _ => TokenEncryption::Disabled,
+        }
+    }
+
+    // Optional setting that is an ip address
+    fn address(&mut self, key: &'static str) -> Option<IpAddr> {
+        match self.values.get(key).map(|value| value.parse::<IpAddr>()) {
+            Some(Ok(address)) => Some(address),
+            Some(Err(_)) => self.reject(key, "an ip address like 0.0.0.0"),
+            None => None,
+        }
+    }
+
+    // Address to probe the connection with, `auto` is the server of the Spotify API
+    fn probe(&mut self, key: &'static str, api_prefix: &str) -> String {
+        let expected = "auto or host:port like api.spotify.com:443";
+        match self.values.get(key).map(String::as_str) {
+            Some("auto") => match Url::parse(api_prefix) {
+                Ok(url) => match (url.host_str(), url.port_or_known_default()) {
+                    (Some(host), Some(port)) => format!("{}:{}", host, port),
+                    _ => self.reject(key, expected),
+                },
+                Err(_) => self.reject(key, expected),
+            },
+            Some(value) => match value.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {
+                    String::from(value)
+                }
+                _ => self.reject(key, expected),
+            },
+            None => self.reject(key, expected),
+        }
+    }
+
+    // Sources to rotate over, PLAYLIST_NAME alone when there is no list
+    fn sources(
+        &mut self,
+        list_key: &'static str,
+        name_key: &'static str,
+        match_key: &'static str,
+    ) -> Vec<WeightedSource> {
+        let mode = self.choice(
+            match_key,
+            &[
+                ("auto", "auto"),
+                ("id", "id"),
+                ("exact", "exact"),
+                ("ignore-case", "ignore-case"),
+                ("regex", "regex"),
+            ],
+            "auto, id, exact, ignore-case or regex",
+        );
+        let list = match self.optional(list_key) {
+            Some(list) if !list.trim().is_empty() => list,
+            _ => {
+                let value = self.required(name_key);
+                return vec![WeightedSource {
+                    selector: self.source(name_key, &value, mode),
+                    weight: 1,
+                }];
+            }
+        };
+
+        // Entries like `name=60`, without a weight the source has weight 1
+        let mut sources = Vec::new();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (value, weight) = match split_weight(entry) {
+                Some((value, weight)) => match weight.trim().parse::<u32>() {
+                    Ok(weight) => (value.trim(), weight),
+                    // Name the entry, a typo in one weight is hard to find in the whole list
+                    Err(_) => {
+                        self.issues.push(ConfigIssue::Invalid {
+                            key: list_key,
+                            value: String::from(entry),
+                            expected: "a weight that is a whole number, like AFK=60",
+                        });
+                        continue;
+                    }
+                },
+                None => (entry, 1),
+            };
+            if weight == 0 {
+                self.reject::<()>(list_key, "playlists with a weight above 0");
+                continue;
+            }
+            sources.push(WeightedSource {
+                selector: self.source(list_key, value, mode),
+                weight,
+            });
+        }
+        if sources.is_empty() {
+            self.reject::<()>(list_key, "playlists like AFK=60, Chill=40");
+        }
+
+        // Sources are drawn by their share of the total weight, which has to fit in a u32
+        let total = sources
+            .iter()
+            .try_fold(0u32, |total, source| total.checked_add(source.weight));
+        if total.is_none() {
+            self.reject::<()>(list_key, "weights that add up to at most 4294967295");
+        }
+        sources
+    }
+
+    // Source to play, a keyword, URI, URL or id unless the match mode says it is a name
+    fn source(&mut self, key: &'static str, value: &str, mode: &str) -> SourceSelector {
+        let name = |matching| SourceSelector::PlaylistName {
+            name: String::from(value),
+            matching,
+        };
+        match mode {
+            "id" => match source_id(value, false) {
+                Some(Ok(source)) => source,
+                Some(Err(expected)) => self.reject(key, expected),
+                None => self.reject(key, "a URI, URL or id with PLAYLIST_MATCH=id"),
+            },
+            "ignore-case" => name(NameMatch::IgnoreCase),
+            "regex" => match Regex::new(value) {
+                Ok(regex) => name(NameMatch::Regex(regex)),
+                Err(_) => name(self.reject(key, "a regular expression with PLAYLIST_MATCH=regex")),
+            },
+            "exact" => name(NameMatch::Exact),
+            // Auto only takes bare ids that look like one, other text is a name
+            _ => match source_id(value, true) {
+                Some(Ok(source)) => source,
+                Some(Err(expected)) => self.reject(key, expected),
+                None => name(NameMatch::Exact),
+            },
+        }
+    }
+
+    // How tracks are spread over the sources
+    fn rotation(&mut self, key: &'static str, tracks_key: &'static str) -> RotationMode {
+        let mode = self.choice(
+            key,
+            &[("weighted", "weighted"), ("round-robin", "round-robin")],
+            "weighted or round-robin",
+        );
+        match mode {
+            "round-robin" => match self.number(tracks_key) {
+                0 => self.reject(tracks_key, "a number above 0"),
+                tracks => RotationMode::RoundRobin(tracks),
+            },
+            _ => RotationMode::Weighted,
+        }
+    }
+
+    // Order to play the tracks of a source in
+    fn order(&mut self, key: &'static str, window_key: &'static str) -> TrackOrder {
+        let order = self.choice(
+            key,
+            &[
+                ("in-order", TrackOrder::InOrder),
+                ("reverse", TrackOrder::Reverse),
+                ("shuffle", TrackOrder::Shuffle),
+                ("no-repeat", TrackOrder::NoRepeat(0)),
+                ("least-played", TrackOrder::LeastPlayed),
+            ],
+            "in-order, reverse, shuffle, no-repeat or least-played",
+        );
+        match order {
+            TrackOrder::NoRepeat(_) => match self.number(window_key) {
+                0 => self.reject(window_key, "a number above 0"),
+                tracks => TrackOrder::NoRepeat(tracks),
+            },
+            order => order,
+        }
+    }
+
+    // Listen time in seconds or percent of the track, default is WAIT_TILL_SKIP seconds
+    fn listen_time(&mut self, key: &'static str, wait_till_skip: Duration) -> ListenTime {
+        let expected = "default, seconds like 30-90 or a part of the track like 40%-80%";
+        let value = match self.values.get(key) {
+            Some(value) => value.trim().to_string(),
+            None => return self.reject(key, expected),
+        };
+        if value == "default" {
+            return ListenTime::Seconds(Bounds {
+                min: wait_till_skip,
+                max: wait_till_skip,
+            });
+        }
+        match value.contains('%') {
+            true => match bounds::<u32>(&value.replace('%', "")) {
+                Some(percent) if percent.min > 0 && percent.max <= 100 => {
+                    ListenTime::Percent(percent)
+                }
+                _ => self.reject(key, expected),
+            },
+            false => match bounds::<u64>(&value) {
+                Some(seconds) => ListenTime::Seconds(Bounds {
+                    min: Duration::from_secs(seconds.min),
+                    max: Duration::from_secs(seconds.max),
+                }),
+                None => self.reject(key, expected),
+            },
+        }
+    }
+
+    // Pagination is capped at 50 items by the Spotify API
+    fn pagination_chunks(&mut self, key: &'static str) -> u32 {
+        match self.values.get(key).map(String::as_str) {
+            Some("default") => DEFAULT_PAGINATION_CHUNKS,
+            Some(value) => match value.parse::<u32>() {
+                Ok(chunks) if (1..=50).contains(&chunks) => chunks,
+                _ => self.reject(key, "a number from 1 to 50 or default"),
+            },
+            None => self.reject(key, "a number from 1 to 50 or default"),
+        }
+    }
+
+    // Remember a missing or malformed setting and return a placeholder
+    fn reject<T: Default>(&mut self, key: &'static str, expected: &'static str) -> T {
+        self.issues.push(match self.values.get(key) {
+            Some(value) => ConfigIssue::Invalid {
+                key,
+                value: value.clone(),
+                expected,
+            },
+            None => ConfigIssue::Missing(key),
+        });
+        T::default()
+    }
+}
+
+impl NameMatch {
+    // Check a playlist name against the wanted name or pattern
+    pub fn matches(&self, wanted: &str, name: &str) -> bool {
+        match self {
+            NameMatch::Exact => name == wanted,
+            NameMatch::IgnoreCase => name.to_lowercase() == wanted.to_lowercase(),
+            NameMatch::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+impl Default for ListenTime {
+    fn default() -> Self {
+        ListenTime::Seconds(Bounds::default())
+    }
+}
+
+impl fmt::Display for ListenTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenTime::Seconds(seconds) => write!(f, "{}", seconds),
+            ListenTime::Percent(Bounds { min, max }) if min == max => write!(f, "{}%", min),
+            ListenTime::Percent(Bounds { min, max }) => write!(f, "{}%-{}%", min, max),
+        }
+    }
+}
+
+impl fmt::Display for Bounds<Duration> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.min == self.max {
+            true => write!(f, "{}s", self.min.as_secs()),
+            false => write!(f, "{}-{}s", self.min.as_secs(), self.max.as_secs()),
+        }
+    }
+}
+
+impl Default for SourceSelector {
+    fn default() -> Self {
+        SourceSelector::PlaylistName {
+            name: String::new(),
+            matching: NameMatch::Exact,
+        }
+    }
+}
+
+impl fmt::Display for SourceSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceSelector::Playlist(id) => write!(f, "spotify:playlist:{}", id),
+            SourceSelector::Album(id) => write!(f, "spotify:album:{}", id),
+            SourceSelector::TopTracks(id) => write!(f, "spotify:artist:{}", id),
+            SourceSelector::Discography(id) => write!(f, "discography:spotify:artist:{}", id),
+            SourceSelector::LikedSongs => write!(f, "liked"),
+            SourceSelector::Show(id) => write!(f, "spotify:show:{}", id),
+            SourceSelector::PlaylistName { name, matching } => match matching {
+                NameMatch::Exact => write!(f, "{}", name),
+                NameMatch::IgnoreCase => write!(f, "{} (ignoring case)", name),
+                NameMatch::Regex(_) => write!(f, "/{}/", name),
+            },
+        }
+    }
+}
+
+impl Headless {
+    // Resolve auto by looking for an X11 or Wayland session
+    pub fn enabled(self) -> bool {
+        match self {
+            Headless::Auto => {
+                env::var_os("DISPLAY").is_none() && env::var_os("WAYLAND_DISPLAY").is_none()
+            }
+            Headless::On => true,
+            Headless::Off => false,
+        }
+    }
+}
+
+impl fmt::Display for Headless {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Headless::Auto => write!(f, "auto"),
+            Headless::On => write!(f, "true"),
+            Headless::Off => write!(f, "false"),
+        }
+    }
+}
+
+impl fmt::Display for AuthFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthFlow::Code => write!(f, "code"),
+            AuthFlow::Pkce => write!(f, "pkce"),
+        }
+    }
+}
+
+impl fmt::Display for FileValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FileValue::Flag(flag) => write!(f, "{}", flag),
+            FileValue::Integer(number) => write!(f, "{}", number),
+            FileValue::Float(number) => write!(f, "{}", number),
+            FileValue::Text(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigIssue::Missing(key) => write!(f, "{} is missing", setting_name(key)),
+            ConfigIssue::Invalid {
+                key,
+                value,
+                expected,
+            } => write!(
+                f,
+                "{} has invalid value \"{}\", expected {}",
+                setting_name(key),
+                value,
+                expected
+            ),
+            ConfigIssue::Unknown(key) => write!(f, "{} is not a known setting", key),
+            ConfigIssue::InAccount { account, issue } => {
+                write!(f, "account \"{}\": {}", account, issue)
+            }
+            ConfigIssue::UnknownAccount(account) => {
+                write!(f, "account \"{}\" is not in the config file", account)
+            }
+            ConfigIssue::AccountRequired(accounts) => write!(
+                f,
+                "several accounts are configured ({}), choose one with --account",
+                accounts.join(", ")
+            ),
+            ConfigIssue::Shared { key, accounts } => write!(
+                f,
+                "accounts \"{}\" and \"{}\" have the same {}, give every account its own",
+                accounts.0,
+                accounts.1,
+                setting_name(key)
+            ),
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Config file to read, if any
+fn config_file_path(sources: &ConfigSources) -> Result<Option<PathBuf>, SpotiAfkError> {
+    let chosen = match &sources.config_file {
+        Some(path) => Some(path.clone()),
+        None => env::var("SPOTIAFK_CONFIG").ok().map(PathBuf::from),
+    };
+    match chosen {
+        Some(path) => match path.exists() {
+            true => Ok(Some(path)),
+            false => Err(SpotiAfkError::ConfigFile {
+                path,
+                reason: String::from("file does not exist"),
+            }),
+        },
+        None => Ok(CONFIG_FILES
+            .iter()
+            .map(PathBuf::from)
+            .find(|path| path.exists())),
+    }
+}
+
+// Read a TOML or YAML config file into variables named like the environment variables
+fn read_config_file(path: &Path) -> Result<FileSettings, SpotiAfkError> {
+    let file_error = |reason: String| SpotiAfkError::ConfigFile {
+        path: path.to_path_buf(),
+        reason,
+    };
+
+    let text = fs::read_to_string(path).map_err(|e| file_error(e.to_string()))?;
+    let file: ConfigFile = match path.extension().and_then(|extension| extension.to_str()) {
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(&text).map_err(|e| file_error(e.to_string()))?
+        }
+        _ => toml::from_str(&text).map_err(|e| file_error(e.to_string()))?,
+    };
+
+    let mut settings = FileSettings::default();
+    settings.values = section_values(file.sections, "", &mut settings.issues);
+    for (name, sections) in file.accounts {
+        let prefix = format!("accounts.{}.", name);
+        let values = section_values(sections, &prefix, &mut settings.issues);
+        settings.accounts.insert(name, values);
+    }
+    Ok(settings)
+}
+
+// Settings of config file sections keyed by environment variable, `prefix` is only used to report unknown keys
+fn section_values(
+    sections: BTreeMap<String, BTreeMap<String, FileValue>>,
+    prefix: &str,
+    issues: &mut Vec<ConfigIssue>,
+) -> HashMap<String, String> {
+    let mut values = HashMap::new();
+    for (section, settings) in sections {
+        for (key, value) in settings {
+            match SETTINGS
+                .iter()
+                .find(|(s, k, _)| *s == section.as_str() && *k == key.as_str())
+            {
+                Some((_, _, variable)) => {
+                    values.insert(String::from(*variable), value.to_string());
+                }
+                None => issues.push(ConfigIssue::Unknown(format!(
+                    "{}{}.{}",
+                    prefix, section, key
+                ))),
+            }
+        }
+    }
+    values
+}
+
+// Accounts sharing a token cache, spotifyd config or device would take over each other's session
+fn shared_settings(accounts: &[Account]) -> Vec<ConfigIssue> {
+    let mut issues = Vec::new();
+    for (index, first) in accounts.iter().enumerate() {
+        for second in &accounts[index + 1..] {
+            let shared = [
+                (
+                    "RSPOTIFY_CLIENT_CACHE_PATH",
+                    first.config.spotify.cache_path == second.config.spotify.cache_path,
+                ),
+                // Every account writes the whole cache file, a turned off cache is never written
+                (
+                    "PLAYLIST_CACHE_PATH",
+                    first.config.cache.enabled
+                        && second.config.cache.enabled
+                        && first.config.cache.path == second.config.cache.path,
+                ),
+                (
+                    "SPOTIFYD_CONFIG_PATH",
+                    first.config.spotifyd.config_path == second.config.spotifyd.config_path,
+                ),
+                (
+                    "SPOTIFYD_DEVICE_NAME",
+                    first.config.spotifyd.device_name == second.config.spotifyd.device_name,
+                ),
+            ];
+            for (key, _) in shared.iter().filter(|(_, same)| *same) {
+                issues.push(ConfigIssue::Shared {
+                    key,
+                    accounts: (
+                        first.name.clone().unwrap_or_default(),
+                        second.name.clone().unwrap_or_default(),
+                    ),
+                });
+            }
+        }
+    }
+    issues
+}
+
+// Name of a setting both as environment variable and in the config file
+fn setting_name(variable: &str) -> String {
+    match SETTINGS.iter().find(|(_, _, v)| *v == variable) {
+        Some((section, key, _)) => format!("{} ({}.{})", variable, section, key),
+        None => String::from(variable),
+    }
+}
+
+// Source out of a keyword, a Spotify URI, an open.spotify.com URL or a bare playlist id,
+// None when it is none of them. Bare ids have to look like a real id when `strict`,
+// URIs and URLs of something that can not be played as a source are an error
+fn source_id(value: &str, strict: bool) -> Option<Result<SourceSelector, &'static str>> {
+    if value == "liked" {
+        return Some(Ok(SourceSelector::LikedSongs));
+    }
+    if let Some(artist) = value.strip_prefix("discography:") {
+        return Some(match spotify_id(artist.trim()) {
+            Some((Some("artist") | None, id)) => Ok(SourceSelector::Discography(id)),
+            _ => Err("an artist URI, URL or id after discography:"),
+        });
+    }
+    match spotify_id(value) {
+        Some((Some("album"), id)) => Some(Ok(SourceSelector::Album(id))),
+        Some((Some("artist"), id)) => Some(Ok(SourceSelector::TopTracks(id))),
+        Some((Some("show"), id)) => Some(Ok(SourceSelector::Show(id))),
+        Some((Some(_), id)) => Some(Ok(SourceSelector::Playlist(id))),
+        Some((None, id)) if !strict || is_spotify_id(&id) => Some(Ok(SourceSelector::Playlist(id))),
+        Some((None, _)) => None,
+        // A track or user link is no playlist name either
+        None if is_spotify_link(value) => Some(Err("a playlist, album, artist or show URI or URL")),
+        None => None,
+    }
+}
+
+// Entry like `name=60` split in the source and the weight, the `=` of a URL query like
+// `?si=abc` is no weight
+fn split_weight(entry: &str) -> Option<(&str, &str)> {
+    let (value, weight) = entry.rsplit_once('=')?;
+    let in_query = value.contains("://")
+        && value
+            .rfind(['?', '&', '='])
+            .is_some_and(|at| value[at..].starts_with(['?', '&']));
+    match in_query {
+        true => None,
+        false => Some((value, weight)),
+    }
+}
+
+// Kind and id out of a URI like spotify:album:<id>, an open.spotify.com URL or a bare id,
+// the kind is None for a bare id
+fn spotify_id(value: &str) -> Option<(Option<&'static str>, String)> {
+    const KINDS: [&str; 4] = ["playlist", "album", "artist", "show"];
+    let base62 = |id: &str| !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric());
+    let path;
+    let segments: Vec<&str> = match value.strip_prefix("spotify:") {
+        Some(uri) => uri.split(':').collect(),
+        None => match Url::parse(value) {
+            // Links can have a locale in front, like /intl-nl/playlist/<id>
+            Ok(url) if url.host_str() == Some("open.spotify.com") => {
+                path = String::from(url.path());
+                path.split('/')
+                    .filter(|s| !s.is_empty() && !s.starts_with("intl-"))
+                    .collect()
+            }
+            Ok(_) => return None,
+            Err(_) => return base62(value).then(|| (None, String::from(value))),
+        },
+    };
+
+    // Older playlist URIs and links have the owner in front, like spotify:user:<name>:playlist:<id>
+    let (kind, id) = match segments[..] {
+        ["user", _, "playlist", id] => ("playlist", id),
+        [kind, id] => (*KINDS.iter().find(|k| **k == kind)?, id),
+        _ => return None,
+    };
+    base62(id).then(|| (Some(kind), String::from(id)))
+}
+
+// URI or open.spotify.com URL, no matter what it points at
+fn is_spotify_link(value: &str) -> bool {
+    value.starts_with("spotify:")
+        || Url::parse(value).is_ok_and(|url| url.host_str() == Some("open.spotify.com"))
+}
+
+// Spotify ids are 22 base62 characters
+fn is_spotify_id(id: &str) -> bool {
+    id.len() == 22 && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+// A value like `35` or a range like `10-60`, the first bound not above the second
+fn bounds<T: FromStr + PartialOrd>(value: &str) -> Option<Bounds<T>> {
+    let (min, max) = value.split_once('-').unwrap_or((value, value));
+    let bounds = Bounds {
+        min: min.trim().parse().ok()?,
+        max: max.trim().parse().ok()?,
+    };
+    (bounds.min <= bounds.max).then_some(bounds)
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Settings keyed by environment variable, like validate gets them
+    fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (String::from(*key), String::from(*value)))
+            .collect()
+    }
+
+    // The defaults with the settings that have none, enough to validate
+    fn complete(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        let mut complete = values(DEFAULTS);
+        complete.extend(values(&[
+            ("RSPOTIFY_CLIENT_ID", "id"),
+            ("RSPOTIFY_CLIENT_SECRET", "secret"),
+            ("PLAYLIST_NAME", "AFK"),
+            ("SPOTIFYD_USERNAME", "user"),
+            ("SPOTIFYD_PASSWORD", "password"),
+        ]));
+        complete.extend(values(pairs));
+        complete
+    }
+
+    // Tests that change the environment of the process take turns
+    static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+    // Settings without defaults as TOML, with CHECKS_BEFORE_PLAYING when given
+    fn toml_file(checks: Option<u32>) -> String {
+        let checks = checks.map(|checks| format!("checks_before_playing = {}\n", checks));
+        format!(
+            "[spotify]\nclient_id = \"id\"\nclient_secret = \"secret\"\n\n\
+             [playback]\nplaylist_name = \"AFK\"\n{}\n\
+             [spotifyd]\nusername = \"user\"\npassword = \"password\"\n",
+            checks.unwrap_or_default()
+        )
+    }
+
+    // Config file and .env file in a directory of their own
+    fn files(
+        config_name: &str,
+        config: &str,
+        env_file: &str,
+    ) -> (tempfile::TempDir, ConfigSources) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(config_name), config).unwrap();
+        fs::write(dir.path().join(".env"), env_file).unwrap();
+        let sources = ConfigSources {
+            config_file: Some(dir.path().join(config_name)),
+            env_file: Some(dir.path().join(".env")),
+            ..ConfigSources::default()
+        };
+        (dir, sources)
+    }
+
+    // CHECKS_BEFORE_PLAYING of the only account, the .env file leaves what it read in the environment
+    fn checks(sources: &ConfigSources) -> u32 {
+        let accounts = AppConfig::load_accounts(sources);
+        env::remove_var("CHECKS_BEFORE_PLAYING");
+        accounts.unwrap()[0].config.playback.checks_before_playing
+    }
+
+    #[test]
+    fn reports_every_invalid_setting_at_once() {
+        assert!(AppConfig::validate(&complete(&[]), Vec::new()).is_ok());
+
+        let mut values = complete(&[
+            ("RSPOTIFY_CLIENT_PAGINATION_CHUNKS", "100"),
+            ("TIME_BETWEEN_CHECKS", "soon"),
+            ("SKIP_TRACKS", "maybe"),
+        ]);
+        values.remove("SPOTIFYD_PASSWORD");
+        let issues = match AppConfig::validate(&values, Vec::new()) {
+            Err(SpotiAfkError::Config(issues)) => issues,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+
+        let keys: Vec<&str> = issues
+            .iter()
+            .map(|issue| match issue {
+                ConfigIssue::Missing(key) | ConfigIssue::Invalid { key, .. } => *key,
+                other => panic!("{}", other),
+            })
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "RSPOTIFY_CLIENT_PAGINATION_CHUNKS",
+                "TIME_BETWEEN_CHECKS",
+                "SKIP_TRACKS",
+                "SPOTIFYD_PASSWORD"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_probing_the_network_without_waiting() {
+        let values = complete(&[("NETWORK_PROBE_INTERVAL", "0")]);
+        let issues = match AppConfig::validate(&values, Vec::new()) {
+            Err(SpotiAfkError::Config(issues)) => issues,
+            other => panic!("{:?}", other.map(|_| ())),
+        };
+        assert_eq!(
+            issues[0].to_string(),
+            "NETWORK_PROBE_INTERVAL (network.interval) has invalid value \"0\", \
+             expected at least 1 second"
+        );
+
+        let values = complete(&[("NETWORK_PROBE_INTERVAL", "1")]);
+        let config = AppConfig::validate(&values, Vec::new()).unwrap();
+        assert_eq!(config.network.interval, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn config_file_beats_the_defaults() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+        let (_dir, sources) = files("spotiafk.toml", &toml_file(None), "");
+        assert_eq!(checks(&sources), 5);
+
+        let (_dir, sources) = files("spotiafk.toml", &toml_file(Some(1)), "");
+        assert_eq!(checks(&sources), 1);
+
+        let yaml = "spotify:\n  client_id: id\n  client_secret: secret\n\
+                    playback:\n  playlist_name: AFK\n  checks_before_playing: 1\n\
+                    spotifyd:\n  username: user\n  password: password\n";
+        let (_dir, sources) = files("spotiafk.yaml", yaml, "");
+        assert_eq!(checks(&sources), 1);
+    }
+
+    #[test]
+    fn env_file_beats_the_config_file() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+        let (_dir, sources) = files(
+            "spotiafk.toml",
+            &toml_file(Some(1)),
+            "CHECKS_BEFORE_PLAYING=2\n",
+        );
+        assert_eq!(checks(&sources), 2);
+    }
+
+    #[test]
+    fn environment_beats_the_env_file() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+        let (_dir, sources) = files(
+            "spotiafk.toml",
+            &toml_file(Some(1)),
+            "CHECKS_BEFORE_PLAYING=2\n",
+        );
+        env::set_var("CHECKS_BEFORE_PLAYING", "3");
+        assert_eq!(checks(&sources), 3);
+    }
+
+    #[test]
+    fn overrides_beat_the_environment() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+        let (_dir, mut sources) = files(
+            "spotiafk.toml",
+            &toml_file(Some(1)),
+            "CHECKS_BEFORE_PLAYING=2\n",
+        );
+        sources
+            .overrides
+            .push(("CHECKS_BEFORE_PLAYING", String::from("4")));
+        env::set_var("CHECKS_BEFORE_PLAYING", "3");
+        assert_eq!(checks(&sources), 4);
+    }
+
+    #[test]
+    fn account_sections_beat_the_environment() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+        let config = toml_file(None)
+            + "[accounts.alice.spotify]\ncache_path = \"alice.json\"\n\
+               [accounts.alice.playback]\nchecks_before_playing = 1\n\
+               [accounts.alice.spotifyd]\nconfig_path = \"alice.conf\"\ndevice_name = \"ALICE\"\n\
+               [accounts.bob.spotify]\ncache_path = \"bob.json\"\n\
+               [accounts.bob.spotifyd]\nconfig_path = \"bob.conf\"\ndevice_name = \"BOB\"\n";
+        let (_dir, sources) = files("spotiafk.toml", &config, "");
+        let checks = |accounts: Vec<Account>| -> Vec<(String, u32)> {
+            let mut checks: Vec<_> = accounts
+                .into_iter()
+                .map(|account| {
+                    let checks = account.config.playback.checks_before_playing;
+                    (account.name.unwrap(), checks)
+                })
+                .collect();
+            checks.sort();
+            checks
+        };
+        let accounts = AppConfig::load_accounts(&sources).unwrap();
+        assert_eq!(
+            checks(accounts),
+            [(String::from("alice"), 1), (String::from("bob"), 5)]
+        );
+
+        env::set_var("CHECKS_BEFORE_PLAYING", "3");
+        let accounts = AppConfig::load_accounts(&sources);
+        env::remove_var("CHECKS_BEFORE_PLAYING");
+        assert_eq!(
+            checks(accounts.unwrap()),
+            [(String::from("alice"), 1), (String::from("bob"), 3)]
+        );
+    }
+
+    #[test]
+    fn accounts_keep_their_device_names_over_the_env_file() {
+        let _environment = ENVIRONMENT.lock().unwrap_or_else(|e| e.into_inner());
+        let config = toml_file(None)
+            + "[accounts.alice.spotify]\ncache_path = \"alice.json\"\n\
+               [accounts.alice.spotifyd]\nconfig_path = \"alice.conf\"\ndevice_name = \"ALICE\"\n\
+               [accounts.bob.spotify]\ncache_path = \"bob.json\"\n\
+               [accounts.bob.spotifyd]\nconfig_path = \"bob.conf\"\ndevice_name = \"BOB\"\n";
+        let (_dir, sources) = files("spotiafk.toml", &config, "SPOTIFYD_DEVICE_NAME=SHARED\n");
+        let accounts = AppConfig::load_accounts(&sources);
+        env::remove_var("SPOTIFYD_DEVICE_NAME");
+
+        let mut devices: Vec<_> = accounts
+            .unwrap()
+            .into_iter()
+            .map(|account| account.config.spotifyd.device_name)
+            .collect();
+        devices.sort();
+        assert_eq!(devices, ["ALICE", "BOB"]);
+    }
+
+    // Account with the given settings on top of complete ones
+    fn account(name: &str, pairs: &[(&str, &str)]) -> Account {
+        Account {
+            name: Some(String::from(name)),
+            config: AppConfig::validate(&complete(pairs), Vec::new()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn accounts_need_their_own_cache_spotifyd_and_device() {
+        let alice = account(
+            "alice",
+            &[
+                ("RSPOTIFY_CLIENT_CACHE_PATH", "alice.json"),
+                ("SPOTIFYD_CONFIG_PATH", "alice.conf"),
+                ("SPOTIFYD_DEVICE_NAME", "ALICE"),
+            ],
+        );
+        let bob = account(
+            "bob",
+            &[
+                ("RSPOTIFY_CLIENT_CACHE_PATH", "bob.json"),
+                ("SPOTIFYD_CONFIG_PATH", "bob.conf"),
+                ("SPOTIFYD_DEVICE_NAME", "BOB"),
+            ],
+        );
+        assert!(shared_settings(&[alice.clone(), bob]).is_empty());
+
+        let carol = account(
+            "carol",
+            &[
+                ("RSPOTIFY_CLIENT_CACHE_PATH", "carol.json"),
+                ("SPOTIFYD_CONFIG_PATH", "carol.conf"),
+                ("SPOTIFYD_DEVICE_NAME", "ALICE"),
+            ],
+        );
+        let shared: Vec<_> = shared_settings(&[alice, carol])
+            .into_iter()
+            .map(|issue| match issue {
+                ConfigIssue::Shared { key, accounts } => (key, accounts),
+                issue => panic!("unexpected issue {}", issue),
+            })
+            .collect();
+        assert_eq!(
+            shared,
+            [(
+                "SPOTIFYD_DEVICE_NAME",
+                (String::from("alice"), String::from("carol"))
+            )]
+        );
+
+        // Every pair is reported once
+        let defaults = [account("a", &[]), account("b", &[]), account("c", &[])];
+        assert_eq!(shared_settings(&defaults).len(), 12);
+    }
+
+    #[test]
+    fn accounts_need_their_own_playlist_cache() {
+        let own = |name: &str, cache: &str| {
+            account(
+                name,
+                &[
+                    ("RSPOTIFY_CLIENT_CACHE_PATH", &format!("{}.json", name)),
+                    ("SPOTIFYD_CONFIG_PATH", &format!("{}.conf", name)),
+                    ("SPOTIFYD_DEVICE_NAME", name),
+                    ("PLAYLIST_CACHE_PATH", cache),
+                ],
+            )
+        };
+
+        // The default is next to the token cache of every account
+        let alice = own("alice", "default");
+        assert!(shared_settings(&[alice, own("bob", "default")]).is_empty());
+
+        let alice = own("alice", "playlists.json");
+        let shared = shared_settings(&[alice.clone(), own("bob", "playlists.json")]);
+        assert!(matches!(
+            shared[..],
+            [ConfigIssue::Shared {
+                key: "PLAYLIST_CACHE_PATH",
+                ..
+            }]
+        ));
+
+        // Nothing is written when the cache is off
+        let mut carol = own("carol", "playlists.json");
+        carol.config.cache.enabled = false;
+        assert!(shared_settings(&[alice, carol]).is_empty());
+    }
+
+    fn sources(pairs: &[(&str, &str)]) -> (Vec<WeightedSource>, Vec<ConfigIssue>) {
+        let values = values(pairs);
+        let mut vars = Variables {
+            values: &values,
+            issues: Vec::new(),
+        };
+        let sources = vars.sources("PLAYLISTS", "PLAYLIST_NAME", "PLAYLIST_MATCH");
+        (sources, vars.issues)
+    }
+
+    fn source(name: &str, matching: &str) -> (SourceSelector, Vec<ConfigIssue>) {
+        let (mut sources, issues) =
+            sources(&[("PLAYLIST_NAME", name), ("PLAYLIST_MATCH", matching)]);
+        (sources.remove(0).selector, issues)
+    }
+
+    fn id(selector: SourceSelector) -> Option<String> {
+        match selector {
+            SourceSelector::Playlist(id) => Some(id),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn takes_the_id_out_of_uris_and_urls() {
+        let wanted = Some(String::from("37i9dQZF1DXcBWIGoYBM5M"));
+        for value in [
+            "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M",
+            "https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M?si=abc",
+            "https://open.spotify.com/intl-nl/playlist/37i9dQZF1DXcBWIGoYBM5M",
+            "37i9dQZF1DXcBWIGoYBM5M",
+            "spotify:user:spotify:playlist:37i9dQZF1DXcBWIGoYBM5M",
+            "https://open.spotify.com/user/spotify/playlist/37i9dQZF1DXcBWIGoYBM5M",
+        ] {
+            assert_eq!(id(source(value, "auto").0), wanted, "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_uris_and_urls_of_other_kinds() {
+        for value in [
+            "spotify:track:4uLU6hMCjMI75M1A2tKUQC",
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC",
+            "spotify:episode:512ojhOuo1ktJprKbVcKyQ",
+            "spotify:user:spotify",
+            "https://open.spotify.com/user/spotify",
+        ] {
+            for matching in ["auto", "id"] {
+                let (_, issues) = source(value, matching);
+                assert_eq!(issues.len(), 1, "{} {}", value, matching);
+                assert!(issues[0]
+                    .to_string()
+                    .ends_with("expected a playlist, album, artist or show URI or URL"));
+            }
+        }
+    }
+
+    #[test]
+    fn other_text_is_a_name() {
+        for value in ["AFK", "Chill Mix", "https://example.com/playlist/abc"] {
+            assert_eq!(id(source(value, "auto").0), None, "{}", value);
+        }
+
+        // Even when it looks like an id
+        let (selector, _) = source("37i9dQZF1DXcBWIGoYBM5M", "exact");
+        assert_eq!(id(selector), None);
+    }
+
+    #[test]
+    fn rejects_what_the_match_mode_can_not_use() {
+        let (_, issues) = source("Chill Mix", "id");
+        assert_eq!(issues.len(), 1);
+
+        let (_, issues) = source("(afk", "regex");
+        assert_eq!(issues.len(), 1);
+
+        let (_, issues) = source("AFK", "fuzzy");
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn reads_other_kinds_of_sources() {
+        for (value, wanted) in [
+            ("liked", "liked"),
+            (
+                "https://open.spotify.com/album/4aawyAB9vmqN3uQ7FjRGTy?si=x",
+                "spotify:album:4aawyAB9vmqN3uQ7FjRGTy",
+            ),
+            (
+                "spotify:artist:0OdUWJ0sBjDrqHygGUXeCF",
+                "spotify:artist:0OdUWJ0sBjDrqHygGUXeCF",
+            ),
+            (
+                "discography:https://open.spotify.com/artist/0OdUWJ0sBjDrqHygGUXeCF",
+                "discography:spotify:artist:0OdUWJ0sBjDrqHygGUXeCF",
+            ),
+            (
+                "spotify:show:5CfCWKI5pZ28U0uOzXkDHe",
+                "spotify:show:5CfCWKI5pZ28U0uOzXkDHe",
+            ),
+        ] {
+            let (selector, issues) = source(value, "auto");
+            assert!(issues.is_empty(), "{:?}", issues);
+            assert_eq!(selector.to_string(), wanted);
+        }
+
+        let (_, issues) = source("discography:spotify:album:4aawyAB9vmqN3uQ7FjRGTy", "auto");
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn reads_weighted_playlists() {
+        let (playlists, issues) = sources(&[
+            ("PLAYLIST_NAME", "Ignored"),
+            ("PLAYLIST_MATCH", "auto"),
+            (
+                "PLAYLISTS",
+                "AFK=60, spotify:playlist:37i9dQZF1DXcBWIGoYBM5M = 30, Chill Mix",
+            ),
+        ]);
+        assert!(issues.is_empty(), "{:?}", issues);
+        let read: Vec<(String, u32)> = playlists
+            .into_iter()
+            .map(|playlist| (playlist.selector.to_string(), playlist.weight))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                (String::from("AFK"), 60),
+                (String::from("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M"), 30),
+                (String::from("Chill Mix"), 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_weights_behind_shared_links() {
+        let link = "https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M?si=123";
+        let (playlists, issues) = sources(&[
+            ("PLAYLIST_MATCH", "auto"),
+            (
+                "PLAYLISTS",
+                &format!("{}, {}&pt=abc=5, Top=10=2", link, link),
+            ),
+        ]);
+        assert!(issues.is_empty(), "{:?}", issues);
+        let read: Vec<(String, u32)> = playlists
+            .into_iter()
+            .map(|playlist| (playlist.selector.to_string(), playlist.weight))
+            .collect();
+        assert_eq!(
+            read,
+            [
+                (String::from("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M"), 1),
+                (String::from("spotify:playlist:37i9dQZF1DXcBWIGoYBM5M"), 5),
+                (String::from("Top=10"), 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_a_weight_that_is_not_a_number() {
+        let (playlists, issues) = sources(&[
+            ("PLAYLIST_MATCH", "auto"),
+            ("PLAYLISTS", "MyList=abc, Chill=1"),
+        ]);
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].to_string(),
+            "PLAYLISTS (playback.playlists) has invalid value \"MyList=abc\", \
+             expected a weight that is a whole number, like AFK=60"
+        );
+    }
+
+    #[test]
+    fn rejects_a_zero_weight() {
+        let (playlists, issues) =
+            sources(&[("PLAYLIST_MATCH", "auto"), ("PLAYLISTS", "AFK=0, Chill=1")]);
+        assert_eq!(playlists.len(), 1);
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[test]
+    fn reads_filter_rules() {
+        let values = values(&[
+            ("FILTER_MIN_DURATION", "60"),
+            ("FILTER_MAX_DURATION", ""),
+            ("FILTER_EXCLUDE_ARTISTS", "Noise, The Band ,"),
+        ]);
+        let mut vars = Variables {
+            values: &values,
+            issues: Vec::new(),
+        };
+
+        assert_eq!(
+            vars.optional_seconds("FILTER_MIN_DURATION"),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(vars.optional_seconds("FILTER_MAX_DURATION"), None);
+        assert_eq!(vars.names("FILTER_EXCLUDE_ARTISTS"), ["noise", "the band"]);
+        assert!(vars.names("FILTER_INCLUDE_ARTISTS").is_empty());
+        assert!(vars.issues.is_empty());
+    }
+
+    #[test]
+    fn reads_listen_times_and_chances() {
+        let listen_time = |value: &str| {
+            let values = values(&[("LISTEN_TIME", value)]);
+            let mut vars = Variables {
+                values: &values,
+                issues: Vec::new(),
+            };
+            let listen_time = vars.listen_time("LISTEN_TIME", Duration::from_secs(35));
+            (listen_time, vars.issues.len())
+        };
+        let seconds = |min, max| {
+            ListenTime::Seconds(Bounds {
+                min: Duration::from_secs(min),
+                max: Duration::from_secs(max),
+            })
+        };
+
+        assert_eq!(listen_time("default"), (seconds(35, 35), 0));
+        assert_eq!(listen_time("30 - 90"), (seconds(30, 90), 0));
+        assert_eq!(
+            listen_time("40%-80%"),
+            (ListenTime::Percent(Bounds { min: 40, max: 80 }), 0)
+        );
+        assert_eq!(listen_time("50%").0.to_string(), "50%");
+        for wrong in ["90-30", "0%", "50%-120%", "a while"] {
+            assert_eq!(listen_time(wrong).1, 1, "{}", wrong);
+        }
+
+        let values = values(&[("PAUSE_CHANCE", "5%"), ("CHECK_JITTER", "150")]);
+        let mut vars = Variables {
+            values: &values,
+            issues: Vec::new(),
+        };
+        assert_eq!(vars.percentage("PAUSE_CHANCE"), 5);
+        vars.percentage("CHECK_JITTER");
+        assert_eq!(vars.issues.len(), 1);
+    }
+
+    #[test]
+    fn rejects_weights_above_the_total_a_rotation_can_draw_from() {
+        let (_, issues) = sources(&[
+            ("PLAYLIST_MATCH", "auto"),
+            ("PLAYLISTS", "AFK=4000000000, Chill=4000000000"),
+        ]);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0]
+            .to_string()
+            .ends_with("expected weights that add up to at most 4294967295"));
+
+        let (_, issues) = sources(&[
+            ("PLAYLIST_MATCH", "auto"),
+            ("PLAYLISTS", "AFK=4000000000, Chill=294967295"),
+        ]);
+        assert!(issues.is_empty());
+    }
+}
@@ -0,0 +1,176 @@
+/////////////
+// Imports //
+/////////////
+
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    net::TcpStream,
+    sync::watch,
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+
+// Self made files
+use crate::{config::NetworkConfig, log, logging};
+
+///////////////
+// Constants //
+///////////////
+
+// Longest a probe may take before counting as offline
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+///////////
+// Types //
+///////////
+
+// Background task probing the connection, shares if we are online
+pub struct ConnectivityMonitor {
+    probe: String,
+    sender: Arc<watch::Sender<bool>>,
+    state: watch::Receiver<bool>,
+    task: JoinHandle<()>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl ConnectivityMonitor {
+    // Probe once, then keep probing every interval
+    pub async fn start(config: &NetworkConfig) -> Self {
+        let online = probe(&config.probe).await;
+        let (sender, state) = watch::channel(online);
+        let sender = Arc::new(sender);
+        let task = logging::spawn(monitor(config.clone(), sender.clone()));
+        ConnectivityMonitor {
+            probe: config.probe.clone(),
+            sender,
+            state,
+            task,
+        }
+    }
+
+    // Probe right now, like after a failed request, instead of waiting for the next probe
+    pub async fn check(&self) -> bool {
+        let online = probe(&self.probe).await;
+        update(&self.sender, &self.probe, online);
+        online
+    }
+
+    // Last known state, without waiting for a probe
+    pub fn is_online(&self) -> bool {
+        *self.state.borrow()
+    }
+
+    // Wait until the connection is back, returns right away when online
+    pub async fn online(&mut self) {
+        // The sender only goes away with the task, which runs until dropped
+        let _ = self.state.wait_for(|online| *online).await;
+    }
+}
+
+impl Drop for ConnectivityMonitor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Check if a connection to the probe address can be made
+pub async fn probe(address: &str) -> bool {
+    matches!(
+        timeout(PROBE_TIMEOUT, TcpStream::connect(address)).await,
+        Ok(Ok(_))
+    )
+}
+
+// Probe every interval
+async fn monitor(config: NetworkConfig, state: Arc<watch::Sender<bool>>) {
+    loop {
+        sleep(config.interval).await;
+        let online = probe(&config.probe).await;
+        update(&state, &config.probe, online);
+    }
+}
+
+// Share the probe result and report when the connection comes or goes
+fn update(state: &watch::Sender<bool>, probe: &str, online: bool) {
+    if online == *state.borrow() {
+        return;
+    }
+    match online {
+        true => log!("Connection to {} is back, resuming", probe),
+        false => log!("Lost the connection to {}, pausing until it is back", probe),
+    }
+    state.send_replace(online);
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn config(probe: String) -> NetworkConfig {
+        NetworkConfig {
+            probe,
+            interval: Duration::from_millis(20),
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_connects_to_the_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        assert!(probe(&address).await);
+
+        drop(listener);
+        assert!(!probe(&address).await);
+    }
+
+    #[tokio::test]
+    async fn monitor_follows_the_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut monitor = ConnectivityMonitor::start(&config(address.to_string())).await;
+        assert!(monitor.is_online());
+
+        drop(listener);
+        timeout(
+            Duration::from_secs(5),
+            monitor.state.wait_for(|online| !online),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(!monitor.is_online());
+
+        let _listener = TcpListener::bind(address).await.unwrap();
+        timeout(Duration::from_secs(5), monitor.online())
+            .await
+            .unwrap();
+        assert!(monitor.is_online());
+    }
+
+    #[tokio::test]
+    async fn check_updates_the_state_right_away() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let monitor = ConnectivityMonitor::start(&NetworkConfig {
+            probe: address,
+            interval: Duration::from_secs(3600),
+        })
+        .await;
+
+        drop(listener);
+        assert!(!monitor.check().await);
+        assert!(!monitor.is_online());
+    }
+}
@@ -0,0 +1,244 @@
+/////////////
+// Imports //
+/////////////
+
+use rspotify::ClientError;
+use std::{error::Error, fmt, io, path::PathBuf};
+
+// Self made files
+use crate::{config::ConfigIssue, spotify_api::ApiError};
+
+///////////
+// Types //
+///////////
+
+// Every way the program can fail, carrying enough context to explain why
+#[derive(Debug)]
+pub enum SpotiAfkError {
+    // No internet connection available
+    Offline,
+
+    // One or more settings are missing or malformed
+    Config(Vec<ConfigIssue>),
+
+    // The config file can not be read or parsed
+    ConfigFile {
+        path: PathBuf,
+        reason: String,
+    },
+
+    // The cached token can not be read, decrypted or written
+    TokenCache {
+        path: PathBuf,
+        reason: String,
+    },
+
+    // Spotify refused or failed the authorization flow
+    Authorization(ClientError),
+
+    // The redirect back from Spotify could not be received
+    AuthorizationCallback(String),
+
+    // The refresh token stopped working, the user has to log in again
+    ReauthRequired(String),
+
+    // A Spotify API request failed, `action` tells what we were doing
+    Spotify {
+        action: &'static str,
+        source: ApiError,
+    },
+
+    // A source from PLAYLIST_NAME or PLAYLISTS does not exist or is not in the user's playlists
+    SourceNotFound(String),
+
+    // Several of the user's playlists match PLAYLIST_NAME, as "id (name)"
+    PlaylistAmbiguous {
+        wanted: String,
+        matches: Vec<String>,
+    },
+
+    // A source has nothing to play, with what the filters left out when they left out everything
+    EmptySource {
+        source: String,
+        filtered: Option<String>,
+    },
+
+    // The playlist cache can not be deleted
+    PlaylistCache {
+        path: PathBuf,
+        reason: String,
+    },
+
+    // The device from SPOTIFYD_DEVICE_NAME is not known to Spotify
+    DeviceNotFound(String),
+
+    // Writing the spotifyd config file failed
+    SpotifydConfig(io::Error),
+
+    // Spawning spotifyd failed
+    SpotifydStart(io::Error),
+
+    // Running pgrep to find spotifyd failed
+    SpotifydPid(io::Error),
+
+    // Running kill to stop spotifyd failed
+    SpotifydStop(io::Error),
+
+    // Sessions of these accounts failed, the others finished
+    Accounts(Vec<(String, SpotiAfkError)>),
+
+    // A session task panicked
+    Crashed(String),
+}
+
+impl SpotiAfkError {
+    // Process exit code, unique per kind of failure
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            SpotiAfkError::Offline => 2,
+            SpotiAfkError::Config(_) => 3,
+            SpotiAfkError::Authorization(_) | SpotiAfkError::AuthorizationCallback(_) => 4,
+            SpotiAfkError::Spotify { .. } => 5,
+            SpotiAfkError::SourceNotFound(_) => 6,
+            SpotiAfkError::DeviceNotFound(_) => 7,
+            SpotiAfkError::SpotifydConfig(_) => 8,
+            SpotiAfkError::SpotifydStart(_) => 9,
+            SpotiAfkError::SpotifydPid(_) => 10,
+            SpotiAfkError::SpotifydStop(_) => 11,
+            SpotiAfkError::ConfigFile { .. } => 12,
+            SpotiAfkError::TokenCache { .. } => 13,
+            SpotiAfkError::ReauthRequired(_) => 14,
+            SpotiAfkError::PlaylistAmbiguous { .. } => 15,
+            SpotiAfkError::EmptySource { .. } => 16,
+            SpotiAfkError::PlaylistCache { .. } => 17,
+            // The first failed account decides, like a single account would
+            SpotiAfkError::Accounts(failed) => {
+                failed.first().map(|(_, e)| e.exit_code()).unwrap_or(1)
+            }
+            SpotiAfkError::Crashed(_) => 101,
+        }
+    }
+
+    // Shorthand to wrap a failed Spotify request
+    pub fn spotify<E: Into<ApiError>>(action: &'static str) -> impl FnOnce(E) -> SpotiAfkError {
+        move |source| SpotiAfkError::Spotify {
+            action,
+            source: source.into(),
+        }
+    }
+}
+
+impl fmt::Display for SpotiAfkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpotiAfkError::Offline => write!(
+                f,
+                "Failed to connect to the internet, please check your connection"
+            ),
+            SpotiAfkError::Config(issues) => {
+                write!(f, "Invalid configuration, please check your config file or .env")?;
+                for issue in issues {
+                    write!(f, "\n  - {}", issue)?;
+                }
+                Ok(())
+            }
+            SpotiAfkError::ConfigFile { path, reason } => {
+                write!(f, "Failed to read config file {}: {}", path.display(), reason)
+            }
+            SpotiAfkError::TokenCache { path, reason } => {
+                write!(f, "Failed to use token cache {}: {}", path.display(), reason)
+            }
+            SpotiAfkError::Authorization(e) => {
+                write!(f, "Authorization failed ({}); please try again", e)
+            }
+            SpotiAfkError::AuthorizationCallback(reason) => {
+                write!(f, "Authorization failed ({}); please try again", reason)
+            }
+            SpotiAfkError::ReauthRequired(reason) => write!(
+                f,
+                "Spotify login expired ({}), run `spoti_afk auth` to log in again",
+                reason
+            ),
+            SpotiAfkError::Spotify { action, source } => {
+                write!(f, "Spotify request failed while {}: {}", action, source)
+            }
+            SpotiAfkError::SourceNotFound(name) => write!(
+                f,
+                "\"{}\" not found, please check PLAYLIST_NAME or PLAYLISTS in your .env file",
+                name
+            ),
+            SpotiAfkError::PlaylistAmbiguous { wanted, matches } => {
+                write!(
+                    f,
+                    "Playlist \"{}\" matches {} playlists, use the id of one in PLAYLIST_NAME:",
+                    wanted,
+                    matches.len()
+                )?;
+                for playlist in matches {
+                    write!(f, "\n  - {}", playlist)?;
+                }
+                Ok(())
+            }
+            SpotiAfkError::EmptySource {
+                source,
+                filtered: Some(filtered),
+            } => write!(
+                f,
+                "Nothing left to play from the {} ({}), please check the FILTER_ settings",
+                source, filtered
+            ),
+            SpotiAfkError::EmptySource {
+                source,
+                filtered: None,
+            } => write!(f, "Nothing to play from the {}, it has no tracks", source),
+            SpotiAfkError::PlaylistCache { path, reason } => {
+                write!(f, "Failed to clear playlist cache {}: {}", path.display(), reason)
+            }
+            SpotiAfkError::DeviceNotFound(name) => write!(
+                f,
+                "Device \"{}\" not found, make sure spotifyd is running and logged in",
+                name
+            ),
+            SpotiAfkError::SpotifydConfig(e) => {
+                write!(f, "Failed to make spotifyd config file ({})", e)
+            }
+            SpotiAfkError::SpotifydStart(e) => write!(
+                f,
+                "Failed to start spotifyd ({}), make sure spotifyd is installed and added to your PATH",
+                e
+            ),
+            SpotiAfkError::SpotifydPid(e) => write!(
+                f,
+                "Failed getting pid of spotifyd ({}), make sure pgrep is installed",
+                e
+            ),
+            SpotiAfkError::SpotifydStop(e) => write!(
+                f,
+                "Failed stopping spotifyd ({}), make sure kill is installed",
+                e
+            ),
+            SpotiAfkError::Accounts(failed) => {
+                write!(f, "AFK failed for {} account(s)", failed.len())?;
+                for (account, e) in failed {
+                    write!(f, "\n  - {}: {}", account, e)?;
+                }
+                Ok(())
+            }
+            SpotiAfkError::Crashed(reason) => write!(f, "AFK session crashed ({})", reason),
+        }
+    }
+}
+
+impl Error for SpotiAfkError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SpotiAfkError::Authorization(e) => Some(e),
+            SpotiAfkError::Spotify { source, .. } => Some(source),
+            SpotiAfkError::SpotifydConfig(e)
+            | SpotiAfkError::SpotifydStart(e)
+            | SpotiAfkError::SpotifydPid(e)
+            | SpotiAfkError::SpotifydStop(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,513 @@
+/////////////
+// Imports //
+/////////////
+
+use async_trait::async_trait;
+use rspotify::model::Country;
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+// Self made files
+use crate::spotify_api::{
+    ApiError, Collection, Device, Page, Playing, Playlist, SpotifyApi, Track, TrackKind,
+};
+
+///////////
+// Types //
+///////////
+
+// In-memory Spotify account to test against
+#[derive(Default)]
+pub struct FakeSpotify {
+    state: Mutex<FakeState>,
+}
+
+// Everything the fake knows, tests can change it between calls
+#[derive(Default)]
+pub struct FakeState {
+    pub page_size: u32,
+    pub country: Option<Country>,
+    pub playlists: Vec<(Playlist, Vec<Track>)>,
+    pub albums: Vec<(Collection, Vec<Track>)>,
+    pub shows: Vec<(Collection, Vec<Track>)>,
+
+    // Artists with their top tracks and the ids of their albums
+    pub artists: Vec<(Collection, Vec<Track>, Vec<String>)>,
+
+    // Liked Songs
+    pub saved: Vec<Track>,
+    pub devices: Vec<Device>,
+    pub playing: Option<Playing>,
+
+    // Tracks the device plays after the playing one
+    pub queue: VecDeque<Track>,
+
+    // When the playing track ends and the device goes on with the queue, None while paused
+    pub track_end: Option<Instant>,
+
+    // What was left of the track when it was paused
+    pub paused_left: Option<Duration>,
+
+    // Calls that change playback, like "queue track-1 on device-1"
+    pub calls: Vec<String>,
+
+    // Every method called, failed ones too
+    pub requests: Vec<&'static str>,
+
+    // Next requests that fail with this status, keyed by method name
+    pub failing: Vec<(&'static str, u16)>,
+
+    // Retry-After sent along with failures
+    pub retry_after: Option<Duration>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl FakeSpotify {
+    // Account without playlists or devices, pages of 2 items to test paging
+    pub fn new() -> Self {
+        let fake = FakeSpotify::default();
+        fake.state().page_size = 2;
+        fake
+    }
+
+    // Add a playlist with tracks named after their ids
+    pub fn with_playlist(self, name: &str, track_ids: &[&str]) -> Self {
+        {
+            let mut state = self.state();
+            let playlist = Playlist {
+                id: format!("playlist-{}", state.playlists.len() + 1),
+                name: String::from(name),
+                track_count: track_ids.len() as u32,
+                snapshot_id: String::from("snapshot-1"),
+            };
+            state
+                .playlists
+                .push((playlist, tracks(track_ids, TrackKind::Music)));
+        }
+        self
+    }
+
+    // Add an album with tracks named after their ids, its id is like "album-1"
+    pub fn with_album(self, name: &str, track_ids: &[&str]) -> Self {
+        {
+            let mut state = self.state();
+            let album = collection("album", state.albums.len(), name);
+            state
+                .albums
+                .push((album, tracks(track_ids, TrackKind::Music)));
+        }
+        self
+    }
+
+    // Add an artist with top tracks and albums, added before with `with_album`
+    pub fn with_artist(self, name: &str, top_track_ids: &[&str], album_ids: &[&str]) -> Self {
+        {
+            let mut state = self.state();
+            let artist = collection("artist", state.artists.len(), name);
+            let albums = album_ids.iter().map(|id| String::from(*id)).collect();
+            state
+                .artists
+                .push((artist, tracks(top_track_ids, TrackKind::Music), albums));
+        }
+        self
+    }
+
+    // Add a podcast show with episodes, its id is like "show-1"
+    pub fn with_show(self, name: &str, episode_ids: &[&str]) -> Self {
+        {
+            let mut state = self.state();
+            let mut show = collection("show", state.shows.len(), name);
+            show.publisher = Some(String::from("Publisher"));
+            state
+                .shows
+                .push((show, tracks(episode_ids, TrackKind::Episode)));
+        }
+        self
+    }
+
+    // Set the Liked Songs
+    pub fn with_saved(self, track_ids: &[&str]) -> Self {
+        self.state().saved = tracks(track_ids, TrackKind::Music);
+        self
+    }
+
+    // Add a device
+    pub fn with_device(self, name: &str, id: &str, is_active: bool) -> Self {
+        self.state().devices.push(Device {
+            id: Some(String::from(id)),
+            name: String::from(name),
+            kind: String::from("Computer"),
+            is_active,
+        });
+        self
+    }
+
+    // Set what the user is playing
+    pub fn with_playing(self, is_playing: bool) -> Self {
+        self.state().playing = Some(Playing {
+            is_playing,
+            track_id: None,
+        });
+        self
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, FakeState> {
+        self.state.lock().unwrap()
+    }
+
+    // Playlist and its tracks, a 404 like Spotify sends for unknown ids
+    fn find_playlist(&self, playlist_id: &str) -> Result<(Playlist, Vec<Track>), ApiError> {
+        self.state()
+            .playlists
+            .iter()
+            .find(|(playlist, _)| playlist.id == playlist_id)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    // Artist with its top tracks and album ids
+    fn find_artist(
+        &self,
+        artist_id: &str,
+    ) -> Result<(Collection, Vec<Track>, Vec<String>), ApiError> {
+        self.state()
+            .artists
+            .iter()
+            .find(|(artist, _, _)| artist.id == artist_id)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    // Fail when the test asked for it, otherwise record the call
+    fn call(&self, method: &'static str, call: Option<String>) -> Result<(), ApiError> {
+        let mut state = self.state();
+        state.requests.push(method);
+        if let Some(index) = state.failing.iter().position(|(name, _)| *name == method) {
+            let (_, status) = state.failing.remove(index);
+            return Err(ApiError {
+                status: Some(status),
+                message: format!("{} failed with {}", method, status),
+                retry_after: state.retry_after,
+            });
+        }
+        state.calls.extend(call);
+        Ok(())
+    }
+}
+
+impl FakeState {
+    // Go on with the queue for every track that ended by now, like a device does
+    fn play_on(&mut self) {
+        while let Some(end) = self.track_end.filter(|end| *end <= Instant::now()) {
+            self.next_in_queue(end);
+        }
+    }
+
+    // Play the next track of the queue from `start`, playback stops when the queue is empty
+    fn next_in_queue(&mut self, start: Instant) {
+        match self.queue.pop_front() {
+            Some(track) => {
+                self.track_end = Some(start + track.duration);
+                self.playing = Some(Playing {
+                    is_playing: true,
+                    track_id: track.id,
+                });
+            }
+            None => {
+                self.track_end = None;
+                if let Some(playing) = &mut self.playing {
+                    playing.is_playing = false;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SpotifyApi for FakeSpotify {
+    fn page_size(&self) -> u32 {
+        self.state().page_size
+    }
+
+    async fn user_country(&self) -> Result<Option<Country>, ApiError> {
+        self.call("user_country", None)?;
+        Ok(self.state().country)
+    }
+
+    async fn playlists(&self, limit: u32, offset: u32) -> Result<Page<Playlist>, ApiError> {
+        self.call("playlists", None)?;
+        let playlists = self
+            .state()
+            .playlists
+            .iter()
+            .map(|(p, _)| p.clone())
+            .collect();
+        Ok(page(playlists, limit, offset))
+    }
+
+    async fn playlist(&self, playlist_id: &str) -> Result<Playlist, ApiError> {
+        self.call("playlist", None)?;
+        let (playlist, _) = self.find_playlist(playlist_id)?;
+        Ok(playlist)
+    }
+
+    async fn playlist_snapshot(&self, playlist_id: &str) -> Result<String, ApiError> {
+        self.call("playlist_snapshot", None)?;
+        let (playlist, _) = self.find_playlist(playlist_id)?;
+        Ok(playlist.snapshot_id)
+    }
+
+    async fn playlist_tracks(
+        &self,
+        playlist_id: &str,
+        _market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.call("playlist_tracks", None)?;
+        let (_, tracks) = self.find_playlist(playlist_id)?;
+        Ok(page(tracks, limit, offset))
+    }
+
+    async fn album(&self, album_id: &str) -> Result<Collection, ApiError> {
+        self.call("album", None)?;
+        let (album, _) = find(&self.state().albums, album_id)?;
+        Ok(album)
+    }
+
+    async fn album_tracks(
+        &self,
+        album_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.call("album_tracks", None)?;
+        let (_, mut tracks) = find(&self.state().albums, album_id)?;
+
+        // Like Spotify every track looks playable without a market
+        if market.is_none() {
+            for track in &mut tracks {
+                track.playable = true;
+            }
+        }
+        Ok(page(tracks, limit, offset))
+    }
+
+    async fn artist(&self, artist_id: &str) -> Result<Collection, ApiError> {
+        self.call("artist", None)?;
+        let (artist, _, _) = self.find_artist(artist_id)?;
+        Ok(artist)
+    }
+
+    async fn artist_top_tracks(
+        &self,
+        artist_id: &str,
+        _market: Option<Country>,
+    ) -> Result<Vec<Track>, ApiError> {
+        self.call("artist_top_tracks", None)?;
+        let (_, top_tracks, _) = self.find_artist(artist_id)?;
+        Ok(top_tracks)
+    }
+
+    async fn artist_albums(
+        &self,
+        artist_id: &str,
+        _market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Collection>, ApiError> {
+        self.call("artist_albums", None)?;
+        let (_, _, album_ids) = self.find_artist(artist_id)?;
+        let state = self.state();
+        let albums = album_ids
+            .iter()
+            .filter_map(|id| state.albums.iter().find(|(album, _)| album.id == *id))
+            .map(|(album, _)| album.clone())
+            .collect();
+        Ok(page(albums, limit, offset))
+    }
+
+    async fn saved_tracks(
+        &self,
+        _market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.call("saved_tracks", None)?;
+        Ok(page(self.state().saved.clone(), limit, offset))
+    }
+
+    async fn show(&self, show_id: &str, _market: Option<Country>) -> Result<Collection, ApiError> {
+        self.call("show", None)?;
+        let (show, _) = find(&self.state().shows, show_id)?;
+        Ok(show)
+    }
+
+    async fn show_episodes(
+        &self,
+        show_id: &str,
+        _market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.call("show_episodes", None)?;
+        let (_, episodes) = find(&self.state().shows, show_id)?;
+        Ok(page(episodes, limit, offset))
+    }
+
+    async fn devices(&self) -> Result<Vec<Device>, ApiError> {
+        self.call("devices", None)?;
+        Ok(self.state().devices.clone())
+    }
+
+    async fn playing(&self) -> Result<Option<Playing>, ApiError> {
+        self.call("playing", None)?;
+        let mut state = self.state();
+        state.play_on();
+        Ok(state.playing.clone())
+    }
+
+    async fn transfer_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        self.call(
+            "transfer_playback",
+            Some(format!("transfer to {}", device_id)),
+        )?;
+        let mut state = self.state();
+        for device in &mut state.devices {
+            device.is_active = device.id.as_deref() == Some(device_id);
+        }
+        Ok(())
+    }
+
+    async fn start_playback(&self, track: &Track, device_id: &str) -> Result<(), ApiError> {
+        let id = track.id.as_deref().unwrap_or("nothing");
+        self.call(
+            "start_playback",
+            Some(format!("play {} on {}", id, device_id)),
+        )?;
+        let mut state = self.state();
+        for device in &mut state.devices {
+            device.is_active = device.id.as_deref() == Some(device_id);
+        }
+        state.playing = Some(Playing {
+            is_playing: true,
+            track_id: track.id.clone(),
+        });
+        state.track_end = Some(Instant::now() + track.duration);
+        state.paused_left = None;
+        Ok(())
+    }
+
+    async fn add_to_queue(&self, track: &Track, device_id: &str) -> Result<(), ApiError> {
+        let id = track.id.as_deref().unwrap_or("nothing");
+        self.call(
+            "add_to_queue",
+            Some(format!("queue {} on {}", id, device_id)),
+        )?;
+        self.state().queue.push_back(track.clone());
+        Ok(())
+    }
+
+    async fn next_track(&self, device_id: &str) -> Result<(), ApiError> {
+        self.call("next_track", Some(format!("next on {}", device_id)))?;
+        let mut state = self.state();
+        state.play_on();
+        state.next_in_queue(Instant::now());
+        Ok(())
+    }
+
+    async fn pause_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        self.call("pause_playback", Some(format!("pause on {}", device_id)))?;
+        let mut state = self.state();
+        state.play_on();
+        if let Some(end) = state.track_end.take() {
+            state.paused_left = Some(end - Instant::now());
+        }
+        if let Some(playing) = &mut state.playing {
+            playing.is_playing = false;
+        }
+        Ok(())
+    }
+
+    async fn resume_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        self.call("resume_playback", Some(format!("resume on {}", device_id)))?;
+        let mut state = self.state();
+        if let Some(left) = state.paused_left.take() {
+            state.track_end = Some(Instant::now() + left);
+        }
+        if let Some(playing) = &mut state.playing {
+            playing.is_playing = true;
+        }
+        Ok(())
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Slice of items like the Spotify API pages them
+fn page<T: Clone>(items: Vec<T>, limit: u32, offset: u32) -> Page<T> {
+    let start = (offset as usize).min(items.len());
+    let end = (start + limit as usize).min(items.len());
+    Page {
+        items: items[start..end].to_vec(),
+        has_next: end < items.len(),
+    }
+}
+
+// Album or show and its tracks by id
+fn find(
+    collections: &[(Collection, Vec<Track>)],
+    id: &str,
+) -> Result<(Collection, Vec<Track>), ApiError> {
+    collections
+        .iter()
+        .find(|(collection, _)| collection.id == id)
+        .cloned()
+        .ok_or_else(not_found)
+}
+
+// The 404 Spotify sends for unknown ids
+fn not_found() -> ApiError {
+    ApiError {
+        status: Some(404),
+        message: String::from("not found"),
+        retry_after: None,
+    }
+}
+
+// Album, artist or show named `name`, its id numbered after the ones before it
+fn collection(kind: &str, before: usize, name: &str) -> Collection {
+    Collection {
+        id: format!("{}-{}", kind, before + 1),
+        name: String::from(name),
+        publisher: None,
+    }
+}
+
+// Tracks named after their ids, episodes of a show come without artists like on Spotify
+fn tracks(ids: &[&str], kind: TrackKind) -> Vec<Track> {
+    ids.iter()
+        .map(|id| Track {
+            id: Some(String::from(*id)),
+            name: String::from(*id),
+            duration: Duration::from_secs(180),
+            kind,
+            artists: match kind {
+                TrackKind::Music => vec![String::from("Artist")],
+                TrackKind::Episode => Vec::new(),
+            },
+            playable: true,
+            ..Track::default()
+        })
+        .collect()
+}
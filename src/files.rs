@@ -0,0 +1,60 @@
+/////////////
+// Imports //
+/////////////
+
+use std::{
+    fs,
+    io::{self, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::Path,
+};
+
+///////////////
+// Constants //
+///////////////
+
+// Only the owner may read or write the files we keep
+const PRIVATE_MODE: u32 = 0o600;
+
+///////////////
+// Functions //
+///////////////
+
+// Write next to the file and move it over, so the old file is never half written.
+// Only the owner can read the result, also when the old file was readable by others
+pub fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(PRIVATE_MODE)
+        .open(&temporary)?;
+    file.set_permissions(fs::Permissions::from_mode(PRIVATE_MODE))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_file_for_the_owner_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache.json");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_private(&path, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, PRIVATE_MODE);
+        assert!(!path.with_extension("tmp").exists());
+    }
+}
@@ -0,0 +1,231 @@
+/////////////
+// Imports //
+/////////////
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
+
+// Self made files
+use crate::{config::FilterConfig, spotify_api::Track};
+
+///////////
+// Types //
+///////////
+
+// Why a track was left out of the pool
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FilterReason {
+    Local,
+    Removed,
+    NotInMarket,
+    TooShort,
+    TooLong,
+    Explicit,
+    ExcludedArtist,
+    NotIncludedArtist,
+    Duplicate,
+}
+
+// Tracks left out per reason
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterSummary {
+    counts: BTreeMap<FilterReason, usize>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl FilterSummary {
+    // Tracks left out for any reason
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+}
+
+impl fmt::Display for FilterReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterReason::Local => write!(f, "local file"),
+            FilterReason::Removed => write!(f, "removed"),
+            FilterReason::NotInMarket => write!(f, "not in your country"),
+            FilterReason::TooShort => write!(f, "too short"),
+            FilterReason::TooLong => write!(f, "too long"),
+            FilterReason::Explicit => write!(f, "explicit"),
+            FilterReason::ExcludedArtist => write!(f, "by an excluded artist"),
+            FilterReason::NotIncludedArtist => write!(f, "not by an included artist"),
+            FilterReason::Duplicate => write!(f, "duplicate"),
+        }
+    }
+}
+
+impl fmt::Display for FilterSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reasons: Vec<String> = self
+            .counts
+            .iter()
+            .map(|(reason, count)| format!("{}: {}", reason, count))
+            .collect();
+        write!(f, "{}", reasons.join(", "))
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Keep the tracks that can and may be played, in their original order
+pub fn filter_tracks(tracks: Vec<Track>, filter: &FilterConfig) -> (Vec<Track>, FilterSummary) {
+    let mut summary = FilterSummary::default();
+    let mut seen = HashSet::new();
+    let mut kept = Vec::new();
+    for track in tracks {
+        match reason(&track, filter, &mut seen) {
+            Some(reason) => *summary.counts.entry(reason).or_insert(0) += 1,
+            None => kept.push(track),
+        }
+    }
+    (kept, summary)
+}
+
+// First rule the track breaks, None when it may be played
+fn reason(
+    track: &Track,
+    filter: &FilterConfig,
+    seen: &mut HashSet<String>,
+) -> Option<FilterReason> {
+    let id = match (&track.id, track.local) {
+        (_, true) => return Some(FilterReason::Local),
+        (None, false) => return Some(FilterReason::Removed),
+        (Some(id), false) => id,
+    };
+    let by = |artists: &[String]| {
+        track
+            .artists
+            .iter()
+            .any(|artist| artists.contains(&artist.to_lowercase()))
+    };
+
+    if !track.playable {
+        Some(FilterReason::NotInMarket)
+    } else if filter.min_duration.is_some_and(|min| track.duration < min) {
+        Some(FilterReason::TooShort)
+    } else if filter.max_duration.is_some_and(|max| track.duration > max) {
+        Some(FilterReason::TooLong)
+    } else if filter.exclude_explicit && track.explicit {
+        Some(FilterReason::Explicit)
+    } else if by(&filter.exclude_artists) {
+        Some(FilterReason::ExcludedArtist)
+    } else if !filter.include_artists.is_empty() && !by(&filter.include_artists) {
+        Some(FilterReason::NotIncludedArtist)
+    } else if filter.dedupe && !seen.insert(id.clone()) {
+        Some(FilterReason::Duplicate)
+    } else {
+        None
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn track(id: &str, artist: &str, seconds: u64) -> Track {
+        Track {
+            id: Some(String::from(id)),
+            name: String::from(id),
+            duration: Duration::from_secs(seconds),
+            artists: vec![String::from(artist)],
+            playable: true,
+            ..Track::default()
+        }
+    }
+
+    fn ids(tracks: &[Track]) -> Vec<&str> {
+        tracks
+            .iter()
+            .filter_map(|track| track.id.as_deref())
+            .collect()
+    }
+
+    #[test]
+    fn leaves_out_what_can_not_be_played() {
+        let local = Track {
+            local: true,
+            ..track("local", "Me", 180)
+        };
+        let removed = Track::default();
+        let elsewhere = Track {
+            playable: false,
+            ..track("elsewhere", "Band", 180)
+        };
+        let tracks = vec![track("a", "Band", 180), local, removed, elsewhere];
+
+        let (kept, summary) = filter_tracks(tracks, &FilterConfig::default());
+        assert_eq!(ids(&kept), ["a"]);
+        assert_eq!(summary.total(), 3);
+        assert_eq!(
+            summary.to_string(),
+            "local file: 1, removed: 1, not in your country: 1"
+        );
+    }
+
+    #[test]
+    fn applies_the_configured_rules() {
+        let filter = FilterConfig {
+            min_duration: Some(Duration::from_secs(60)),
+            max_duration: Some(Duration::from_secs(600)),
+            exclude_explicit: true,
+            exclude_artists: vec![String::from("noise")],
+            ..FilterConfig::default()
+        };
+        let explicit = Track {
+            explicit: true,
+            ..track("explicit", "Band", 180)
+        };
+        let tracks = vec![
+            track("short", "Band", 30),
+            track("a", "Band", 180),
+            track("long", "Band", 3600),
+            explicit,
+            track("loud", "Noise", 180),
+        ];
+
+        let (kept, summary) = filter_tracks(tracks, &filter);
+        assert_eq!(ids(&kept), ["a"]);
+        for reason in [
+            FilterReason::TooShort,
+            FilterReason::TooLong,
+            FilterReason::Explicit,
+            FilterReason::ExcludedArtist,
+        ] {
+            assert_eq!(summary.counts[&reason], 1, "{}", reason);
+        }
+    }
+
+    #[test]
+    fn keeps_only_included_artists_once() {
+        let filter = FilterConfig {
+            include_artists: vec![String::from("band")],
+            dedupe: true,
+            ..FilterConfig::default()
+        };
+        let tracks = vec![
+            track("a", "Band", 180),
+            track("b", "Other", 180),
+            track("a", "Band", 180),
+            track("c", "BAND", 180),
+        ];
+
+        let (kept, summary) = filter_tracks(tracks, &filter);
+        assert_eq!(ids(&kept), ["a", "c"]);
+        assert_eq!(summary.counts[&FilterReason::NotIncludedArtist], 1);
+        assert_eq!(summary.counts[&FilterReason::Duplicate], 1);
+    }
+}
@@ -3,86 +3,37 @@
 /////////////
 
 // Extern imports
-use online::sync::check;
-use rspotify::{
-    model::{Market, PlaylistId, PlaylistItem, SimplifiedPlaylist},
-    prelude::*,
-    AuthCodeSpotify,
+use rspotify::model::Country;
+// Self made files
+use crate::{
+    error::SpotiAfkError,
+    spotify_api::{Device, Playlist, SpotifyApi, Track},
 };
-use std::env;
 
 ///////////////
 // Functions //
 ///////////////
 
-// Get if connected to internet
-pub fn online() -> bool {
-    match check(None) {
-        Ok(_) => true,
-        Err(_) => false,
-    }
-}
-
-// Get playlist to play
-pub async fn get_playlist(
-    client: &rspotify::AuthCodeSpotify,
-) -> Result<SimplifiedPlaylist, String> {
-    // Get playlist to play
-    match online() {
-        true => {
-            let playlists = match get_playlists(&client).await {
-                Ok(playlists) => playlists,
-                Err(_) => return Err(String::from("Failed to connect to the internet")),
-            };
-            let mut playlist_found = false;
-            let mut afk_playlist = playlists.first().unwrap();
-            match env::var("PLAYLIST_NAME") {
-                Ok(_) => {
-                    for playlist in &playlists {
-                        if playlist.name == env::var("PLAYLIST_NAME").unwrap() {
-                            afk_playlist = &playlist;
-                            playlist_found = true;
-                            break;
-                        }
-                    }
-                }
-                Err(_) => return Err(String::from("Failed parsing playing settings")),
-            }
-            match playlist_found {
-                true => (),
-                false => return Err(String::from("Failed parsing playing settings")),
-            }
-            Ok(afk_playlist.clone())
-        }
-        false => Err(String::from("Failed to connect to the internet")),
-    }
-}
-
 // Get playlists
-async fn get_playlists(client: &AuthCodeSpotify) -> Result<Vec<SimplifiedPlaylist>, String> {
+pub async fn get_playlists(api: &impl SpotifyApi) -> Result<Vec<Playlist>, SpotiAfkError> {
     // Make buffer variables
     let mut playlists = Vec::new();
 
     // Get all playlists in a vector
-    let limit = client.config.pagination_chunks;
+    let limit = api.page_size();
     let mut offset = 0;
     loop {
         // Request next playlists
-        let response = match online() {
-            true => client
-                .current_user_playlists_manual(Some(limit), Some(offset))
-                .await
-                .unwrap(),
-            false => return Err(String::from("Failed to connect to the internet")),
-        };
+        let response = api
+            .playlists(limit, offset)
+            .await
+            .map_err(SpotiAfkError::spotify("getting playlists"))?;
 
         // Put received playlists in vector
-        for playlist in response.items {
-            playlists.push(playlist)
-        }
+        playlists.extend(response.items);
 
         // If none playlist are left break
-        if response.next.is_none() {
+        if !response.has_next {
             break;
         }
 
@@ -91,73 +42,73 @@ async fn get_playlists(client: &AuthCodeSpotify) -> Result<Vec<SimplifiedPlaylis
     Ok(playlists)
 }
 
+// Find the device spotifyd registered as
+pub async fn get_device(api: &impl SpotifyApi, device_name: &str) -> Result<Device, SpotiAfkError> {
+    api.devices()
+        .await
+        .map_err(SpotiAfkError::spotify("getting devices"))?
+        .into_iter()
+        .find(|device| device.name == device_name && device.id.is_some())
+        .ok_or_else(|| SpotiAfkError::DeviceNotFound(String::from(device_name)))
+}
+
 // Can i play?
-pub async fn is_playing(client: &AuthCodeSpotify) -> Result<bool, String> {
-    match online() {
+pub async fn is_playing(api: &impl SpotifyApi, device_name: &str) -> Result<bool, SpotiAfkError> {
+    let is_playing = match api
+        .playing()
+        .await
+        .map_err(SpotiAfkError::spotify("getting the playing item"))?
+    {
+        Some(playing) => playing.is_playing,
+        None => false,
+    };
+
+    match is_playing {
         true => {
-            let is_playing = match client.current_user_playing_item().await.unwrap() {
-                Some(playing) => playing.is_playing,
-                None => false,
-            };
-
-            match is_playing {
-                true => {
-                    let devices = client.device().await.unwrap();
-                    if devices.len() == 0 {
-                        return Err(String::from("Failed finding devices"));
-                    }
-                    for device in devices {
-                        if device.name == env::var("SPOTIFYD_DEVICE_NAME").unwrap() {
-                            if device.is_active {
-                                return Ok(true);
-                            } else {
-                                return Ok(false);
-                            }
-                        } else {
-                            if device.is_active {
-                                return Ok(false);
-                            } else {
-                            }
-                        }
-                    }
-                    Ok(false)
+            let devices = api
+                .devices()
+                .await
+                .map_err(SpotiAfkError::spotify("getting devices"))?;
+            if devices.is_empty() {
+                return Err(SpotiAfkError::DeviceNotFound(String::from(device_name)));
+            }
+            for device in devices {
+                if device.name == device_name {
+                    return Ok(device.is_active);
+                } else if device.is_active {
+                    return Ok(false);
                 }
-                false => return Ok(true),
             }
+            Ok(false)
         }
-        false => Err(String::from("Failed to connect to the internet")),
+        false => Ok(true),
     }
 }
 
 // Get tracks from playlist
 pub async fn get_tracks(
-    client: &AuthCodeSpotify,
-    playlist: &PlaylistId,
-    market: &Market,
-) -> Result<Vec<PlaylistItem>, String> {
+    api: &impl SpotifyApi,
+    playlist_id: &str,
+    market: Option<Country>,
+) -> Result<Vec<Track>, SpotiAfkError> {
     // Make buffer variables
     let mut tracks = Vec::new();
 
     // Get all tracks in a vector
-    let limit = client.config.pagination_chunks;
+    let limit = api.page_size();
     let mut offset = 0;
     loop {
         // Request next tracks
-        let response = match online() {
-            true => client
-                .playlist_items_manual(&playlist, None, Some(market), Some(limit), Some(offset))
-                .await
-                .unwrap(),
-            false => return Err(String::from("Failed to connect to the internet")),
-        };
+        let response = api
+            .playlist_tracks(playlist_id, market, limit, offset)
+            .await
+            .map_err(SpotiAfkError::spotify("getting playlist tracks"))?;
 
         // Put received tracks in vector
-        for track in response.items {
-            tracks.push(track);
-        }
+        tracks.extend(response.items);
 
         // If none tracks are left break
-        if response.next.is_none() {
+        if !response.has_next {
             break;
         }
 
@@ -167,25 +118,84 @@ pub async fn get_tracks(
     Ok(tracks)
 }
 
-// Parse playing settings
-pub fn parse_playing_settings() -> Result<(), String> {
-    // Make buffer variables
-    let mut found_settings = 0;
-
-    // Loop over all environment variables
-    for (key, _) in env::vars() {
-        match key.as_str() {
-            // Set client prefix from .env
-            "CHECKS_BEFORE_PLAYING"
-            | "PLAYLIST_NAME"
-            | "SKIP_TRACKS"
-            | "WAIT_TILL_SKIP"
-            | "TIME_BETWEEN_CHECKS" => found_settings += 1,
-            _ => (),
-        }
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake_spotify::FakeSpotify;
+
+    #[tokio::test]
+    async fn gets_all_tracks_of_a_playlist() {
+        let api = FakeSpotify::new().with_playlist("AFK", &["a", "b", "c", "d", "e"]);
+
+        let ids: Vec<String> = get_tracks(&api, "playlist-1", None)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|track| track.id)
+            .collect();
+        assert_eq!(ids, ["a", "b", "c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn finds_device_by_name() {
+        let api = FakeSpotify::new()
+            .with_device("phone", "dev-1", true)
+            .with_device("spotifyd", "dev-2", false);
+
+        let device = get_device(&api, "spotifyd").await.unwrap();
+        assert_eq!(device.id.as_deref(), Some("dev-2"));
+        assert!(matches!(
+            get_device(&api, "laptop").await,
+            Err(SpotiAfkError::DeviceNotFound(name)) if name == "laptop"
+        ));
+    }
+
+    #[tokio::test]
+    async fn device_without_id_is_not_found() {
+        let api = FakeSpotify::new();
+        api.state().devices.push(Device {
+            id: None,
+            name: String::from("spotifyd"),
+            kind: String::from("Computer"),
+            is_active: false,
+        });
+
+        assert!(matches!(
+            get_device(&api, "spotifyd").await,
+            Err(SpotiAfkError::DeviceNotFound(_))
+        ));
     }
-    match found_settings {
-        5 => Ok(()),
-        _ => Err(String::from("Failed parsing playing settings")),
+
+    #[tokio::test]
+    async fn can_play_when_nothing_plays() {
+        let api = FakeSpotify::new().with_device("spotifyd", "dev-1", false);
+        assert!(is_playing(&api, "spotifyd").await.unwrap());
+
+        let api = api.with_playing(false);
+        assert!(is_playing(&api, "spotifyd").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn can_play_when_our_device_plays() {
+        let api = FakeSpotify::new()
+            .with_device("spotifyd", "dev-1", true)
+            .with_device("phone", "dev-2", false)
+            .with_playing(true);
+
+        assert!(is_playing(&api, "spotifyd").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn can_not_play_when_another_device_plays() {
+        let api = FakeSpotify::new()
+            .with_device("phone", "dev-1", true)
+            .with_device("spotifyd", "dev-2", false)
+            .with_playing(true);
+
+        assert!(!is_playing(&api, "spotifyd").await.unwrap());
     }
 }
@@ -0,0 +1,127 @@
+/////////////
+// Imports //
+/////////////
+
+use qrcode::{render::unicode::Dense1x2, QrCode};
+use std::{io, thread};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, Mutex},
+};
+use url::Url;
+
+// Self made files
+use crate::{auth::AuthClient, callback, config::AppConfig, error::SpotiAfkError, log};
+
+///////////////
+// Constants //
+///////////////
+
+// Lines pasted on stdin, read by one thread for every login asking for the url
+static PASTED: Mutex<Option<mpsc::Receiver<io::Result<String>>>> = Mutex::const_new(None);
+
+///////////////
+// Functions //
+///////////////
+
+// Log in from another device, by scanning a QR code and catching or pasting the redirect
+pub async fn get_code(
+    client: &impl AuthClient,
+    config: &AppConfig,
+    url: &str,
+    listener: Option<TcpListener>,
+) -> Result<String, SpotiAfkError> {
+    print_qr_code(url);
+    print_forwarding_help(config, listener.is_some());
+
+    // Whichever comes first, the forwarded redirect or the pasted url
+    match listener {
+        Some(listener) => tokio::select! {
+            code = callback::wait_for_code(
+                listener,
+                &config.spotify.redirect_uri,
+                &client.get_oauth().state,
+                config.auth.listener_timeout,
+            ) => code,
+            // Keep listening when stdin is closed, like when running as a service
+            Ok(code) = pasted_code(client) => Ok(code),
+        },
+        None => pasted_code(client).await,
+    }
+}
+
+// Print the url as a QR code in the terminal
+fn print_qr_code(url: &str) {
+    match QrCode::new(url) {
+        // Inverted so the code reads as dark on light on a dark terminal
+        Ok(code) => println!(
+            "{}",
+            code.render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build()
+        ),
+        Err(e) => log!("Can not show the url as a QR code ({})", e),
+    }
+    log!(
+        "Scan the QR code or open this url on another device: {}",
+        url
+    );
+}
+
+// Explain how the redirect gets back to this machine
+fn print_forwarding_help(config: &AppConfig, listening: bool) {
+    let redirect_uri = &config.spotify.redirect_uri;
+    let port = Url::parse(redirect_uri)
+        .ok()
+        .and_then(|url| url.port_or_known_default())
+        .unwrap_or(80);
+
+    log!("After logging in Spotify redirects to {}", redirect_uri);
+    match (listening, config.auth.listener_bind) {
+        (true, Some(address)) => log!(
+            "Listening on {}:{}, make sure that url reaches this machine",
+            address, port
+        ),
+        (true, None) => log!(
+            "To catch it from another computer, forward the port to this machine first: ssh -L {}:localhost:{} <this machine>",
+            port, port
+        ),
+        (false, _) => {}
+    }
+    log!("Or copy the url from the address bar when the page does not load and paste it here:");
+}
+
+// Wait for the user to paste the url they were redirected to, every login shares one reader
+pub async fn pasted_code(client: &impl AuthClient) -> Result<String, SpotiAfkError> {
+    // The reader outlives a login the listener won, so the next one keeps using it
+    let mut pasted = PASTED.lock().await;
+    let lines = pasted.get_or_insert_with(read_stdin);
+
+    while let Some(line) = lines.recv().await {
+        match line
+            .ok()
+            .and_then(|line| client.parse_response_code(line.trim()))
+        {
+            Some(code) => return Ok(code),
+            None => log!("That is not the url Spotify redirected to, please try again:"),
+        }
+    }
+    Err(SpotiAfkError::AuthorizationCallback(String::from(
+        "no url was pasted",
+    )))
+}
+
+// Read stdin line by line in the background
+fn read_stdin() -> mpsc::Receiver<io::Result<String>> {
+    // Stdin blocks, a plain thread does not keep the program alive when the listener wins
+    let (sender, lines) = mpsc::channel(1);
+    thread::spawn(move || {
+        for line in io::stdin().lines() {
+            if sender.blocking_send(line).is_err() {
+                break;
+            }
+        }
+    });
+    lines
+}
@@ -0,0 +1,295 @@
+/////////////
+// Imports //
+/////////////
+
+use rand::{distributions::uniform::SampleUniform, Rng};
+use std::{collections::VecDeque, fmt, time::Duration};
+
+// Self made files
+use crate::{
+    config::{Bounds, ListenTime, PlaybackConfig},
+    spotify_api::Track,
+};
+
+///////////
+// Types //
+///////////
+
+// How a track is listened to, picked when it starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListenPlan {
+    // Time the track plays, pauses not counted
+    pub listen: Duration,
+
+    // Skipped after `listen` instead of played to the end
+    pub skip: bool,
+    pub pause: Option<Pause>,
+}
+
+// Pause once the track played this long
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pause {
+    pub after: Duration,
+    pub length: Duration,
+}
+
+// Part of listening to a track, waited out one after the other
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Listen(Duration),
+    Pause(Duration),
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl ListenPlan {
+    // Pick the listen time and pause of a track from the settings
+    pub fn new(playback: &PlaybackConfig, track: &Track, rng: &mut impl Rng) -> Self {
+        let to_end = ListenPlan {
+            listen: track.duration,
+            skip: false,
+            pause: None,
+        };
+        // The end of a track of unknown length can not be waited for, so it is always skipped
+        let unknown_length = track.duration.is_zero();
+        let skipping = playback.skip_tracks && !chance(playback.play_to_end, rng);
+        let mut plan = match skipping || unknown_length {
+            true => {
+                let listen = listen_time(playback, track, rng);
+                // Skipping a track that already ended would skip the queued one
+                match listen < track.duration || unknown_length {
+                    true => ListenPlan {
+                        listen,
+                        skip: true,
+                        pause: None,
+                    },
+                    false => to_end,
+                }
+            }
+            false => to_end,
+        };
+        if chance(playback.pause_chance, rng) {
+            let played = Bounds {
+                min: Duration::ZERO,
+                max: plan.listen,
+            };
+            plan.pause = Some(Pause {
+                after: seconds_between(played, rng),
+                length: seconds_between(playback.pause_length, rng),
+            });
+        }
+        plan
+    }
+
+    // What to wait out, in order
+    pub fn phases(&self) -> VecDeque<Phase> {
+        match self.pause {
+            Some(pause) => VecDeque::from([
+                Phase::Listen(pause.after),
+                Phase::Pause(pause.length),
+                Phase::Listen(self.listen - pause.after),
+            ]),
+            None => VecDeque::from([Phase::Listen(self.listen)]),
+        }
+    }
+}
+
+impl fmt::Display for ListenPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.skip {
+            true => write!(f, "skipping after {}s", self.listen.as_secs())?,
+            false => write!(f, "playing all {}s", self.listen.as_secs())?,
+        }
+        match self.pause {
+            Some(pause) => write!(
+                f,
+                ", pausing {}s after {}s",
+                pause.length.as_secs(),
+                pause.after.as_secs()
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Time between checks made up to `jitter` percent longer or shorter
+pub fn jittered(time: Duration, jitter: u32, rng: &mut impl Rng) -> Duration {
+    match jitter == 0 || time.is_zero() {
+        true => time,
+        false => {
+            let jitter = f64::from(jitter);
+            time.mul_f64(1.0 + rng.gen_range(-jitter..=jitter) / 100.0)
+        }
+    }
+}
+
+// Time to listen to a track before skipping it
+fn listen_time(playback: &PlaybackConfig, track: &Track, rng: &mut impl Rng) -> Duration {
+    match playback.listen_time {
+        ListenTime::Seconds(seconds) => seconds_between(seconds, rng),
+        // A part of nothing is nothing, WAIT_TILL_SKIP stands in for it
+        ListenTime::Percent(_) if track.duration.is_zero() => playback.wait_till_skip,
+        ListenTime::Percent(percent) => track.duration * between(percent, rng) / 100,
+    }
+}
+
+// True `percent` out of 100 times, the generator is only used when it is a real chance
+fn chance(percent: u32, rng: &mut impl Rng) -> bool {
+    match percent {
+        0 => false,
+        100.. => true,
+        _ => rng.gen_range(0..100) < percent,
+    }
+}
+
+// Random whole seconds between the bounds
+fn seconds_between(bounds: Bounds<Duration>, rng: &mut impl Rng) -> Duration {
+    let seconds = Bounds {
+        min: bounds.min.as_secs(),
+        max: bounds.max.as_secs(),
+    };
+    Duration::from_secs(between(seconds, rng))
+}
+
+// Random value between the bounds, the generator is only used when there is a choice
+fn between<T: SampleUniform + PartialOrd + Copy>(bounds: Bounds<T>, rng: &mut impl Rng) -> T {
+    match bounds.min < bounds.max {
+        true => rng.gen_range(bounds.min..=bounds.max),
+        false => bounds.min,
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RotationMode;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn playback(listen_time: ListenTime) -> PlaybackConfig {
+        PlaybackConfig {
+            sources: Vec::new(),
+            rotation: RotationMode::Weighted,
+            order: Default::default(),
+            seed: None,
+            poll_interval: Duration::ZERO,
+            checks_before_playing: 1,
+            time_between_checks: Duration::from_secs(30),
+            check_jitter: 0,
+            stop_when_taken_over: false,
+            skip_tracks: true,
+            listen_time,
+            wait_till_skip: Duration::from_secs(35),
+            play_to_end: 0,
+            pause_chance: 0,
+            pause_length: Bounds {
+                min: Duration::from_secs(10),
+                max: Duration::from_secs(60),
+            },
+        }
+    }
+
+    fn track(seconds: u64) -> Track {
+        Track {
+            duration: Duration::from_secs(seconds),
+            ..Track::default()
+        }
+    }
+
+    fn seconds(min: u64, max: u64) -> ListenTime {
+        ListenTime::Seconds(Bounds {
+            min: Duration::from_secs(min),
+            max: Duration::from_secs(max),
+        })
+    }
+
+    #[test]
+    fn listens_for_seconds_or_a_part_of_the_track() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            let plan = ListenPlan::new(&playback(seconds(30, 90)), &track(200), &mut rng);
+            assert!(plan.skip);
+            assert!((30..=90).contains(&plan.listen.as_secs()), "{}", plan);
+
+            let percent = ListenTime::Percent(Bounds { min: 40, max: 80 });
+            let plan = ListenPlan::new(&playback(percent), &track(200), &mut rng);
+            assert!((80..=160).contains(&plan.listen.as_secs()), "{}", plan);
+        }
+
+        // A fixed time longer than the track plays it to the end
+        let plan = ListenPlan::new(&playback(seconds(35, 35)), &track(20), &mut rng);
+        assert_eq!(plan.to_string(), "playing all 20s");
+    }
+
+    #[test]
+    fn plays_some_tracks_to_the_end() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut settings = playback(seconds(35, 35));
+        settings.play_to_end = 25;
+
+        let to_end = (0..1_000)
+            .filter(|_| !ListenPlan::new(&settings, &track(200), &mut rng).skip)
+            .count();
+        assert!((180..=320).contains(&to_end), "{}", to_end);
+
+        settings.skip_tracks = false;
+        settings.play_to_end = 0;
+        assert!(!ListenPlan::new(&settings, &track(200), &mut rng).skip);
+    }
+
+    #[test]
+    fn skips_tracks_of_unknown_length_after_the_listen_time() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut settings = playback(seconds(30, 90));
+        settings.skip_tracks = false;
+        let plan = ListenPlan::new(&settings, &track(0), &mut rng);
+        assert!(plan.skip);
+        assert!((30..=90).contains(&plan.listen.as_secs()), "{}", plan);
+
+        let percent = ListenTime::Percent(Bounds { min: 40, max: 80 });
+        let plan = ListenPlan::new(&playback(percent), &track(0), &mut rng);
+        assert_eq!(plan.to_string(), "skipping after 35s");
+    }
+
+    #[test]
+    fn pauses_within_the_listen_time() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut settings = playback(seconds(35, 35));
+        settings.pause_chance = 100;
+
+        let plan = ListenPlan::new(&settings, &track(200), &mut rng);
+        let pause = plan.pause.unwrap();
+        assert!(pause.after <= plan.listen);
+        assert!((10..=60).contains(&pause.length.as_secs()));
+        assert_eq!(
+            plan.phases(),
+            [
+                Phase::Listen(pause.after),
+                Phase::Pause(pause.length),
+                Phase::Listen(plan.listen - pause.after)
+            ]
+        );
+    }
+
+    #[test]
+    fn jitters_the_time_between_checks() {
+        let mut rng = StdRng::seed_from_u64(4);
+        let time = Duration::from_secs(100);
+        assert_eq!(jittered(time, 0, &mut rng), time);
+
+        let times: Vec<Duration> = (0..100).map(|_| jittered(time, 20, &mut rng)).collect();
+        assert!(times
+            .iter()
+            .all(|time| (80.0..=120.0).contains(&time.as_secs_f64())));
+        assert!(times.iter().any(|jittered| *jittered != time));
+    }
+}
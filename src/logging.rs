@@ -0,0 +1,63 @@
+/////////////
+// Imports //
+/////////////
+
+use std::{fmt, future::Future};
+use tokio::{
+    sync::Mutex,
+    task::{self, JoinHandle},
+};
+
+///////////////
+// Constants //
+///////////////
+
+// Only one account at a time may ask the user something in the terminal
+pub static TERMINAL: Mutex<()> = Mutex::const_new(());
+
+tokio::task_local! {
+    // Name of the account the current task works for
+    static ACCOUNT: String;
+}
+
+////////////
+// Macros //
+////////////
+
+// Print a line, labeled with the account when running several
+#[macro_export]
+macro_rules! log {
+    ($($arg:tt)*) => {
+        $crate::logging::print(format_args!($($arg)*))
+    };
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Print a line with the account label of the current task, if any
+pub fn print(line: fmt::Arguments) {
+    match ACCOUNT.try_with(|account| account.clone()) {
+        Ok(account) => println!("[{}] {}", account, line),
+        Err(_) => println!("{}", line),
+    }
+}
+
+// Run a future with every line it logs labeled with the account
+pub async fn labeled<F: Future>(account: Option<String>, future: F) -> F::Output {
+    match account {
+        Some(account) => ACCOUNT.scope(account, future).await,
+        None => future.await,
+    }
+}
+
+// Spawn a task that keeps the account label of the current task
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let account = ACCOUNT.try_with(|account| account.clone()).ok();
+    task::spawn(labeled(account, future))
+}
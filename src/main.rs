@@ -2,239 +2,243 @@
 // DOCS // https://docs.rs/rspotify/latest/rspotify
 //////////
 
-fn print_type_of<T>(_: &T) {
-    println!("{}", std::any::type_name::<T>())
-}
-
 /////////////
 // Imports //
 /////////////
 
 // Extern imports
-use dotenv;
-use rspotify::{
-    model::{AdditionalType, Country, Market},
-    prelude::*,
-};
-use std::{env, process::exit, thread, time::Duration};
+use clap::Parser;
+use rspotify::{AuthCodePkceSpotify, AuthCodeSpotify};
+use std::{future::Future, process::exit};
 
 // Self made files
 mod auth;
+mod callback;
+mod cli;
+mod commands;
+mod config;
+mod connectivity;
+mod error;
+#[cfg(test)]
+mod fake_spotify;
+mod files;
+mod filters;
 mod functions;
+mod headless;
+mod listening;
+mod logging;
+mod playback;
+mod playlist_cache;
+mod pool;
+mod retry;
+mod rotation;
+mod shortener;
+mod sources;
+mod spotify_api;
 mod spotifyd;
+mod token_cache;
+mod token_supervisor;
 use auth::*;
-use functions::*;
+use cli::{Cli, Command};
+use config::{Account, AppConfig, AuthFlow, ConfigIssue};
+use connectivity::ConnectivityMonitor;
+use error::SpotiAfkError;
+use playback::{Session, Step};
+use playlist_cache::PlaylistCache;
+use retry::Retrying;
+use spotify_api::SpotifyApi;
 use spotifyd::*;
+use token_supervisor::TokenSupervisor;
 
 /////////////
 // Program //
 /////////////
 
 // Real entry point
-async fn real_main() -> Result<String, String> {
-    // Check for internet connection
-    if !online() {
-        return Err(String::from("Failed to connect to the internet"));
-    }
-
+async fn real_main() -> Result<(), SpotiAfkError> {
     // Get config variables
-    dotenv::from_filename(".env").ok();
-
-    // Check spotifyd
-    match init_spotifyd() {
-        Ok(_) => (),
-        Err(e) => match e.as_str() {
-            "Failed parsing spotifyd settings" => {
-                return Err(String::from("Failed parsing spotifyd settings"))
-            }
-            "Failed to make spotifyd config file" => {
-                return Err(String::from("Failed to make spotifyd config file"))
+    let cli = Cli::parse();
+    let accounts = AppConfig::load_accounts(&cli.config_sources())?;
+
+    match &cli.command {
+        None | Some(Command::Run) => run_accounts(accounts).await,
+        Some(Command::CheckConfig) => {
+            for account in &accounts {
+                if let Some(name) = &account.name {
+                    println!("Account {}", name);
+                }
+                commands::check_config(&account.config)?;
             }
-            "Failed to start spotifyd" => return Err(String::from("Failed to start spotifyd")),
-            _ => return Err(String::from("Unexpected exit_code")),
+            Ok(())
+        }
+        // The other commands work on a single account
+        Some(_) => match accounts.as_slice() {
+            [account] => match account.config.auth.flow {
+                AuthFlow::Code => run_command::<AuthCodeSpotify>(&cli, &account.config).await,
+                AuthFlow::Pkce => run_command::<AuthCodePkceSpotify>(&cli, &account.config).await,
+            },
+            _ => Err(SpotiAfkError::Config(vec![ConfigIssue::AccountRequired(
+                accounts
+                    .iter()
+                    .filter_map(|account| account.name.clone())
+                    .collect(),
+            )])),
         },
-    };
+    }
+}
 
-    // First authorization and checks if everything works
-    let client = match auth_client().await {
-        Ok(client) => client,
-        Err(e) => match e.as_str() {
-            "Authorization failed" => return Err(String::from("Authorization failed")),
-            "Failed parsing spotify client" => {
-                return Err(String::from("Failed parsing spotify client"))
-            }
-            "Failed parsing spotify api" => return Err(String::from("Failed parsing spotify api")),
-            _ => return Err(String::from("Unexpected exit_code")),
-        },
-    };
+// Run the chosen single account command
+async fn run_command<C: AuthClient>(cli: &Cli, config: &AppConfig) -> Result<(), SpotiAfkError> {
+    match &cli.command {
+        Some(Command::Auth) => commands::auth::<C>(config).await,
+        Some(Command::ListPlaylists) => commands::list_playlists::<C>(config).await,
+        Some(Command::ListDevices) => commands::list_devices::<C>(config).await,
+        Some(Command::Spotifyd { action }) => commands::spotifyd(config, action),
+        Some(Command::Cache { action }) => commands::cache(config, action),
+        // Handled for every account in real_main
+        None | Some(Command::Run) | Some(Command::CheckConfig) => Ok(()),
+    }
+}
 
-    // Getting data of current user
-    let mut user_country = Country::Netherlands;
-    #[allow(unused_assignments)]
-    let mut user_market = Market::Country(user_country.clone());
-    let content_types = [AdditionalType::Track, AdditionalType::Episode];
-    match online() {
-        true => {
-            match client.me().await {
-                Ok(me) => {
-                    user_country = me.country.unwrap();
-                    user_market = Market::Country(user_country.clone());
+// AFK with every account at the same time, each in its own task
+async fn run_accounts(accounts: Vec<Account>) -> Result<(), SpotiAfkError> {
+    let sessions: Vec<_> = accounts
+        .into_iter()
+        .map(|account| {
+            let name = account.name.clone();
+            let task = tokio::spawn(logging::labeled(name.clone(), async move {
+                let result = match account.config.auth.flow {
+                    AuthFlow::Code => afk::<AuthCodeSpotify>(&account.config).await,
+                    AuthFlow::Pkce => afk::<AuthCodePkceSpotify>(&account.config).await,
+                };
+                // Report right away, the other accounts can run for hours
+                if let (Err(e), Some(_)) = (&result, &account.name) {
+                    log!("{}", e);
                 }
-                // Check client prefix is correct in .env
-                Err(_) => return Err(String::from("Failed parsing spotify client")),
-            }
+                result
+            }));
+            (name, task)
+        })
+        .collect();
+
+    // A failing account does not stop the others
+    let mut failed = Vec::new();
+    for (name, task) in sessions {
+        let result = match task.await {
+            Ok(result) => result,
+            Err(e) => Err(SpotiAfkError::Crashed(e.to_string())),
+        };
+        match (result, name) {
+            (Ok(_), _) => {}
+            (Err(e), Some(name)) => failed.push((name, e)),
+            (Err(e), None) => return Err(e),
         }
-        false => return Err(String::from("Failed to connect to the internet")),
     }
 
-    // Get playlist to play
-    let playlist = match get_playlist(&client).await {
-        Ok(playlist) => playlist,
-        Err(e) => match e.as_str() {
-            "Failed parsing playing settings" => {
-                return Err(String::from("Failed parsing playing settings"))
-            }
-            "Failed to connect to the internet" => {
-                return Err(String::from("Failed to connect to the internet"))
-            }
-            _ => return Err(String::from("Unexpected exit_code")),
-        },
-    };
+    match failed.is_empty() {
+        true => Ok(()),
+        false => Err(SpotiAfkError::Accounts(failed)),
+    }
+}
 
-    // Check playing settings
-    match parse_playing_settings() {
-        Ok(_) => (),
-        Err(_) => return Err(String::from("Failed parsing playing settings")),
+// AFK on the configured playlist until stopped
+async fn afk<C: AuthClient>(config: &AppConfig) -> Result<(), SpotiAfkError> {
+    // Check for internet connection, and keep checking during the session
+    let mut network = ConnectivityMonitor::start(&config.network).await;
+    if !network.is_online() {
+        log!("No connection to {}, waiting for it", config.network.probe);
+        network.online().await;
     }
 
-    let mut device_id = None;
-    for device in client.device().await.unwrap() {
-        if device.name == env::var("SPOTIFYD_DEVICE_NAME").unwrap() {
-            device_id = device.id;
-            break;
-        }
+    // Check spotifyd, it is stopped again on every way out of the session
+    let spotifyd = SpotifydGuard::start(&config.spotifyd)?;
+
+    // First authorization and checks if everything works
+    let (client, cache) = auth_session::<C>(config).await?;
+
+    // Keep the token fresh for the whole session
+    let mut supervisor = TokenSupervisor::spawn(&client, cache, config);
+
+    // Throttled and failed requests are retried for a while before giving up
+    let api = Retrying::new(&client, config.spotify.retries);
+
+    // Getting data of current user
+    let user_country = when_online(&mut network, || async {
+        api.user_country()
+            .await
+            .map_err(SpotiAfkError::spotify("getting the current user"))
+    })
+    .await?;
+
+    // Without a country Spotify can not say which tracks are playable for the user
+    if user_country.is_none() {
+        log!("Spotify gave no country for the user, tracks are not checked against a market");
     }
 
-    let mut played = false;
-    let mut can_i_play_counter = 0;
-    let skip_wait_time = env::var("WAIT_TILL_SKIP").unwrap().parse::<u64>().unwrap();
-    let check_wait_time = env::var("TIME_BETWEEN_CHECKS")
-        .unwrap()
-        .parse::<u64>()
-        .unwrap();
-    let mut tracks = get_tracks(&client, &playlist.id, &user_market)
-        .await
-        .unwrap();
+    // Get playlist, device and tracks to play
+    let mut session = when_online(&mut network, || {
+        Session::start(
+            &api,
+            &config.playback,
+            &config.filter,
+            PlaylistCache::open(&config.cache),
+            &config.spotifyd,
+            user_country,
+        )
+    })
+    .await?;
     loop {
-        played = false;
-        match is_playing(&client).await {
-            Ok(bool) => match bool {
-                true => can_i_play_counter += 1,
-                false => {
-                    can_i_play_counter = 0;
-                    break; // DEBUG
-                }
-            },
-            Err(_) => (),
-        }
-        thread::sleep(Duration::from_secs(check_wait_time));
-        while can_i_play_counter
-            >= env::var("CHECKS_BEFORE_PLAYING")
-                .unwrap()
-                .parse::<i32>()
-                .unwrap()
-        {
-            if !played {
-                match client
-                    .transfer_playback(&device_id.as_ref().unwrap(), Some(false))
-                    .await
-                {
-                    Ok(_) => played = true,
-                    Err(_) => continue,
-                }
+        // Stop cleanly instead of failing every request when the login is gone
+        supervisor.authorized().await?;
+
+        // Pause while offline, the monitor reports when the connection comes and goes
+        network.online().await;
+
+        match session.step().await {
+            Ok(Step::Continue) => {}
+            // Someone is listening again and STOP_WHEN_TAKEN_OVER leaves the playback to them
+            Ok(Step::Stop) => {
+                log!("Another device took playback over, stopping");
+                break;
             }
-            if tracks.len() == 0 {
-                tracks = get_tracks(&client, &playlist.id, &user_market)
-                    .await
-                    .unwrap();
-            }
-            let current_track = tracks.pop();
-            let track_id = current_track.unwrap().track.unwrap().id();
-
-            client.add_item_to_queue(&track_id.unwrap(), Some(device_id.unwrap().as_str()));
-            // TODO play track somehow in spotifyd by putting it in the queue before any api interaction check is_playing()
+            // Keep spotifyd and our place in the tracks, and sync again once back online
+            Err(_) if !network.check().await => session.lost_connection(),
+            Err(e) => return Err(e),
         }
     }
 
     // End of program
-    match stop_spotifyd() {
-        Ok(_) => (),
-        Err(e) => match e.as_str() {
-            "Failed getting pid of spotifyd" => {
-                return Err(String::from("Failed getting pid of spotifyd"))
-            }
-            "Failed stopping spotifyd" => return Err(String::from("Failed stopping spotifyd")),
-            _ => return Err(String::from("Unexpected exit_code")),
-        },
+    spotifyd.stop()?;
+    log!("Program finished successfully");
+    Ok(())
+}
+
+// Run a request again once the connection is back when it failed because we went offline
+async fn when_online<T, F, Fut>(
+    network: &mut ConnectivityMonitor,
+    mut request: F,
+) -> Result<T, SpotiAfkError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, SpotiAfkError>>,
+{
+    loop {
+        match request().await {
+            Err(_) if !network.check().await => network.online().await,
+            result => return result,
+        }
     }
-    Ok(String::from("Program finished successfully"))
 }
 
 // Entry point
 #[tokio::main]
 async fn main() {
-    dotenv::from_filename(".env").ok();
-    // Run application and match on exit codes
-    exit(match real_main().await.unwrap().as_str() {
-        "Program finished successfully" => {
-            println!("Program finished successfully");
-            0
-        }
-        "Authorization failed" => {
-            println!("Authorization failed; please try again");
-            1
-        }
-        "Failed parsing spotify client" => {
-            println!("Failed parsing spotify client. Please check your .env file");
-            1
-        }
-        "Failed to connect to the internet" => {
-            println!("Failed to connect to the internet, please check your connection");
-            1
-        }
-        "Failed parsing playing settings" => {
-            println!("Failed parsing playing settings. Please check your .env file");
-            1
-        }
-        "Failed parsing spotify api" => {
-            println!("Failed parsing spotify api, Please check your .env file");
-            1
-        }
-        "Failed parsing spotifyd settings" => {
-            println!("Failed parsing spotifyd settings, Please check your .env file");
-            1
-        }
-        "Failed to make spotifyd config file" => {
-            println!("Failed to make spotifyd config file, please try again");
-            1
-        }
-        "Failed to start spotifyd" => {
-            println!(
-                "Failed to start spotifyd, make sure spotifyd is installed and added to your PATH"
-            );
-            1
-        }
-        "Failed getting pid of spotifyd" => {
-            println!("Failed getting pid of spotifyd, make sure pgrep is installed");
-            1
-        }
-        "Failed stopping spotifyd" => {
-            println!("Failed stopping spotifyd, make sure kill is installed");
-            1
-        }
-        _ => {
-            println!("Unexpected exit_code");
-            -1
+    // Run application and exit with the code belonging to the error
+    exit(match real_main().await {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            e.exit_code()
         }
     });
 }
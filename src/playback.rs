@@ -0,0 +1,1217 @@
+/////////////
+// Imports //
+/////////////
+
+use rand::{rngs::StdRng, SeedableRng};
+use rspotify::model::Country;
+use std::{collections::VecDeque, time::Duration};
+use tokio::time::{sleep, Instant};
+
+// Self made files
+use crate::{
+    config::{FilterConfig, PlaybackConfig, SpotifydConfig},
+    error::SpotiAfkError,
+    filters::filter_tracks,
+    functions::{get_device, is_playing},
+    listening::{jittered, ListenPlan, Phase},
+    log,
+    playlist_cache::PlaylistCache,
+    pool::TrackPool,
+    rotation::Rotation,
+    sources::{get_source, get_source_tracks, tracks_at_snapshot, TrackSource},
+    spotify_api::{Device, Playlist, SpotifyApi, Track},
+};
+
+///////////////
+// Constants //
+///////////////
+
+// Time between looking for spotifyd in the devices while it connects
+const DEVICE_POLL: Duration = Duration::from_secs(1);
+
+///////////
+// Types //
+///////////
+
+// What the AFK loop should do after a step
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Continue,
+
+    // Another device took playback over and STOP_WHEN_TAKEN_OVER is set
+    Stop,
+}
+
+// Where the AFK loop of a session is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    // Checking nobody else plays before moving playback to our device
+    Checking,
+
+    // Playback is on our device, listening to tracks with the next one queued
+    Playing,
+
+    // The connection was lost, the device and playback are synced again first
+    WaitingForNetwork,
+}
+
+// State of the AFK loop of one account
+pub struct Session<'a, A: SpotifyApi> {
+    api: &'a A,
+    playback: &'a PlaybackConfig,
+    filter: &'a FilterConfig,
+    cache: PlaylistCache,
+    device_name: String,
+    device_id: String,
+    sources: Vec<Source>,
+    rotation: Rotation,
+    rng: StdRng,
+    market: Option<Country>,
+    state: SessionState,
+    checks: u32,
+
+    // When the playlists are checked for changes next
+    next_poll: Instant,
+
+    // Track queued after the one on the device
+    queued: Option<Picked>,
+
+    // Id of the track on the device, and of the one before it
+    playing: Option<String>,
+    played: Option<String>,
+
+    // What is left of listening to the track on the device, and if it is skipped after
+    phases: VecDeque<Phase>,
+    skip: bool,
+}
+
+// Source of the rotation with the tracks to play from it
+struct Source {
+    source: TrackSource,
+    pool: TrackPool,
+}
+
+// Track picked to play, with the index of its source
+struct Picked {
+    track: Track,
+    source: usize,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl<'a, A: SpotifyApi> Session<'a, A> {
+    // Look up the sources, the device and the tracks to play
+    pub async fn start(
+        api: &'a A,
+        playback: &'a PlaybackConfig,
+        filter: &'a FilterConfig,
+        mut cache: PlaylistCache,
+        spotifyd: &SpotifydConfig,
+        market: Option<Country>,
+    ) -> Result<Session<'a, A>, SpotiAfkError> {
+        // The seed is logged so a run can be played again the same way with SHUFFLE_SEED
+        let seed = playback.seed.unwrap_or_else(rand::random);
+        log!("Using seed {} for the random choices", seed);
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let mut sources = Vec::new();
+        for weighted in &playback.sources {
+            let source = get_source(api, &weighted.selector, market, &mut cache).await?;
+            let tracks = playable_tracks(api, &source, filter, market, &mut cache).await?;
+            log!("Playing from the {} ({} tracks)", source, tracks.len());
+            let mut pool = TrackPool::new(playback.order);
+            pool.fill(tracks, &mut rng);
+            sources.push(Source { source, pool });
+        }
+        let device_id = wait_for_device(api, spotifyd).await?.id.unwrap_or_default();
+        let weights: Vec<u32> = playback.sources.iter().map(|p| p.weight).collect();
+
+        Ok(Session {
+            api,
+            playback,
+            filter,
+            cache,
+            device_name: spotifyd.device_name.clone(),
+            device_id,
+            sources,
+            rotation: Rotation::new(playback.rotation, &weights),
+            rng,
+            market,
+            state: SessionState::Checking,
+            checks: 0,
+            next_poll: Instant::now() + playback.poll_interval,
+            queued: None,
+            playing: None,
+            played: None,
+            phases: VecDeque::new(),
+            skip: false,
+        })
+    }
+
+    // Stop where we are until the connection is back, keeping the place in the tracks
+    pub fn lost_connection(&mut self) {
+        self.state = SessionState::WaitingForNetwork;
+    }
+
+    // Check if we can play, and once that was true long enough play tracks on our device
+    pub async fn step(&mut self) -> Result<Step, SpotiAfkError> {
+        // spotifyd may have registered again while offline, and playback may have moved
+        if self.state == SessionState::WaitingForNetwork {
+            match get_device(self.api, &self.device_name).await {
+                Ok(device) => self.device_id = device.id.unwrap_or_default(),
+                // spotifyd takes a moment to connect again after the network is back
+                Err(SpotiAfkError::DeviceNotFound(_)) => {
+                    log!(
+                        "Waiting for {} to connect to Spotify again",
+                        self.device_name
+                    );
+                    sleep(self.between_checks()).await;
+                    return Ok(Step::Continue);
+                }
+                Err(e) => return Err(e),
+            }
+            self.state = SessionState::Checking;
+        }
+
+        // Someone else plays, the checks start over once they stop
+        if !is_playing(self.api, &self.device_name).await? {
+            self.checks = 0;
+            if self.state == SessionState::Playing {
+                if self.playback.stop_when_taken_over {
+                    return Ok(Step::Stop);
+                }
+                log!("Another device started playing, waiting for it to stop");
+                self.state = SessionState::Checking;
+                self.phases.clear();
+            }
+            sleep(self.between_checks()).await;
+            return Ok(Step::Continue);
+        }
+        self.checks += 1;
+        match self.state {
+            SessionState::Playing => self.listen().await,
+            _ => self.start_playing().await,
+        }
+    }
+
+    // Once nobody else played long enough, move playback to our device and start a track
+    async fn start_playing(&mut self) -> Result<Step, SpotiAfkError> {
+        sleep(self.between_checks()).await;
+        if self.checks < self.playback.checks_before_playing {
+            return Ok(Step::Continue);
+        }
+        self.api
+            .transfer_playback(&self.device_id)
+            .await
+            .map_err(SpotiAfkError::spotify("transferring playback"))?;
+
+        // A track queued before the connection was lost goes first
+        let next = match self.queued.take() {
+            Some(next) => next,
+            None => self.pick().await?,
+        };
+        if let Err(e) = self.api.start_playback(&next.track, &self.device_id).await {
+            self.queued = Some(next);
+            return Err(SpotiAfkError::spotify("starting playback")(e));
+        }
+        self.state = SessionState::Playing;
+
+        // What played before was replaced, so it is not expected on the device anymore
+        self.playing = None;
+        self.now_playing(next);
+        self.top_up().await?;
+        Ok(Step::Continue)
+    }
+
+    // Listen to the track on the device, checking in between, then move on to the queued one
+    async fn listen(&mut self) -> Result<Step, SpotiAfkError> {
+        // The device may have stalled, then playback is started again like at the start
+        if !self.follow_device().await? {
+            log!(
+                "{} is not playing the expected track, starting playback again",
+                self.device_name
+            );
+            self.state = SessionState::Checking;
+            return Ok(Step::Continue);
+        }
+
+        let between_checks = self.between_checks();
+        if let Some(Phase::Listen(left) | Phase::Pause(left)) = self.phases.front_mut() {
+            let wait = match between_checks.is_zero() {
+                true => *left,
+                false => (*left).min(between_checks),
+            };
+            sleep(wait).await;
+            *left -= wait;
+            if !left.is_zero() {
+                return Ok(Step::Continue);
+            }
+
+            let finished = self.phases.pop_front();
+            match (finished, self.phases.front()) {
+                (Some(Phase::Listen(_)), Some(Phase::Pause(length))) => {
+                    log!("Pausing for {}s", length.as_secs());
+                    self.api
+                        .pause_playback(&self.device_id)
+                        .await
+                        .map_err(SpotiAfkError::spotify("pausing playback"))?;
+                }
+                (Some(Phase::Pause(_)), _) => self
+                    .api
+                    .resume_playback(&self.device_id)
+                    .await
+                    .map_err(SpotiAfkError::spotify("resuming playback"))?,
+                _ => {}
+            }
+            if !self.phases.is_empty() {
+                return Ok(Step::Continue);
+            }
+        }
+
+        // A track that is not skipped ends by itself and the device goes on with the queue
+        if self.skip {
+            self.api
+                .next_track(&self.device_id)
+                .await
+                .map_err(SpotiAfkError::spotify("skipping a track"))?;
+        }
+        match self.queued.take() {
+            Some(next) => {
+                self.now_playing(next);
+                self.top_up().await?;
+            }
+            // Nothing was queued, so playback stopped and is started again
+            None => self.state = SessionState::Checking,
+        }
+        Ok(Step::Continue)
+    }
+
+    // Check the device plays the track listened to, going on with it when it was ahead of us
+    async fn follow_device(&mut self) -> Result<bool, SpotiAfkError> {
+        let playing = self
+            .api
+            .playing()
+            .await
+            .map_err(SpotiAfkError::spotify("getting the playing item"))?;
+        let pausing = matches!(self.phases.front(), Some(Phase::Pause(_)));
+        let track_id = match playing {
+            Some(playing) if playing.is_playing || pausing => playing.track_id,
+            _ => None,
+        };
+
+        // Nothing plays while it should, like when the queue ran out
+        if track_id.is_none() {
+            return Ok(false);
+        }
+
+        // Spotify can still answer with the track before for a moment after moving on
+        if track_id == self.playing || track_id == self.played {
+            return Ok(true);
+        }
+
+        // Time spent on requests adds up, so the device can reach the queued track first
+        let queued_id = self.queued.as_ref().and_then(|next| next.track.id.clone());
+        if track_id != queued_id {
+            return Ok(false);
+        }
+        if let Some(next) = self.queued.take() {
+            self.now_playing(next);
+            self.top_up().await?;
+        }
+        Ok(true)
+    }
+
+    // Keep the track after the current one in the queue of the device
+    async fn top_up(&mut self) -> Result<(), SpotiAfkError> {
+        if self.queued.is_some() {
+            return Ok(());
+        }
+        let next = self.pick().await?;
+        self.api
+            .add_to_queue(&next.track, &self.device_id)
+            .await
+            .map_err(SpotiAfkError::spotify("queueing a track"))?;
+        self.queued = Some(next);
+        Ok(())
+    }
+
+    // Report the track the device moved on to and pick how long to listen to it
+    fn now_playing(&mut self, next: Picked) {
+        let plan = ListenPlan::new(self.playback, &next.track, &mut self.rng);
+        log!(
+            "Playing {} from the {}, {}",
+            describe(&next.track),
+            self.sources[next.source].source,
+            plan
+        );
+        self.phases = plan.phases();
+        self.skip = plan.skip;
+        self.played = std::mem::replace(&mut self.playing, next.track.id);
+    }
+
+    // Time until the next check, CHECK_JITTER makes it differ a bit every time
+    fn between_checks(&mut self) -> Duration {
+        jittered(
+            self.playback.time_between_checks,
+            self.playback.check_jitter,
+            &mut self.rng,
+        )
+    }
+
+    // Next track of the source the rotation picks
+    async fn pick(&mut self) -> Result<Picked, SpotiAfkError> {
+        // Someone may have changed a playlist since its tracks were fetched
+        if !self.playback.poll_interval.is_zero() && Instant::now() >= self.next_poll {
+            self.poll_playlists().await;
+            self.next_poll = Instant::now() + self.playback.poll_interval;
+        }
+
+        let index = self.rotation.next(&mut self.rng);
+        let source = &mut self.sources[index];
+        if source.pool.is_empty() {
+            let tracks = playable_tracks(
+                self.api,
+                &source.source,
+                self.filter,
+                self.market,
+                &mut self.cache,
+            )
+            .await?;
+            source.pool.fill(tracks, &mut self.rng);
+        }
+        match source.pool.next(&mut self.rng) {
+            Some(track) => Ok(Picked {
+                track,
+                source: index,
+            }),
+            None => Err(SpotiAfkError::EmptySource {
+                source: source.source.to_string(),
+                filtered: None,
+            }),
+        }
+    }
+
+    // Update the tracks still to play of playlists with a new snapshot, failures wait for the next poll
+    async fn poll_playlists(&mut self) {
+        for source in &mut self.sources {
+            let TrackSource::Playlist(playlist) = &source.source else {
+                continue;
+            };
+            let playlist = playlist.clone();
+            let snapshot_id = match self.api.playlist_snapshot(&playlist.id).await {
+                Ok(snapshot_id) if snapshot_id == playlist.snapshot_id => continue,
+                Ok(snapshot_id) => snapshot_id,
+                Err(e) => {
+                    log!("Failed to check the {} for changes ({})", source.source, e);
+                    continue;
+                }
+            };
+            // Load the tracks at the snapshot just checked instead of asking for it again
+            let tracks = tracks_at_snapshot(
+                self.api,
+                &playlist,
+                &snapshot_id,
+                self.market,
+                &mut self.cache,
+            )
+            .await
+            .and_then(|tracks| playable(tracks, &source.source, self.filter));
+            let tracks = match tracks {
+                Ok(tracks) => tracks,
+                Err(e) => {
+                    log!("Failed to get the changes of the {} ({})", source.source, e);
+                    continue;
+                }
+            };
+
+            let diff = source.pool.update(tracks, &mut self.rng);
+            if !diff.added.is_empty() || !diff.removed.is_empty() {
+                log!(
+                    "The {} changed, added {} ({}), removed {} ({})",
+                    source.source,
+                    diff.added.len(),
+                    names(&diff.added),
+                    diff.removed.len(),
+                    names(&diff.removed)
+                );
+            }
+            source.source = TrackSource::Playlist(Playlist {
+                snapshot_id,
+                ..playlist
+            });
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Find our device, spotifyd takes a moment to connect to Spotify after it was started
+async fn wait_for_device(
+    api: &impl SpotifyApi,
+    spotifyd: &SpotifydConfig,
+) -> Result<Device, SpotiAfkError> {
+    let deadline = Instant::now() + spotifyd.connect_timeout;
+    let mut waiting = false;
+    loop {
+        match get_device(api, &spotifyd.device_name).await {
+            Err(SpotiAfkError::DeviceNotFound(_)) if Instant::now() < deadline => {
+                if !waiting {
+                    log!("Waiting for {} to connect to Spotify", spotifyd.device_name);
+                    waiting = true;
+                }
+                sleep(DEVICE_POLL.min(deadline - Instant::now())).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Tracks of a source that pass the filters
+async fn playable_tracks(
+    api: &impl SpotifyApi,
+    source: &TrackSource,
+    filter: &FilterConfig,
+    market: Option<Country>,
+    cache: &mut PlaylistCache,
+) -> Result<Vec<Track>, SpotiAfkError> {
+    let tracks = get_source_tracks(api, source, market, cache).await?;
+    playable(tracks, source, filter)
+}
+
+// Tracks that pass the filters, saying what was left out and why
+fn playable(
+    tracks: Vec<Track>,
+    source: &TrackSource,
+    filter: &FilterConfig,
+) -> Result<Vec<Track>, SpotiAfkError> {
+    let (tracks, summary) = filter_tracks(tracks, filter);
+    if summary.total() > 0 {
+        log!(
+            "Left out {} tracks of the {} ({})",
+            summary.total(),
+            source,
+            summary
+        );
+    }
+    match tracks.is_empty() {
+        true => Err(SpotiAfkError::EmptySource {
+            source: source.to_string(),
+            filtered: (summary.total() > 0).then(|| summary.to_string()),
+        }),
+        false => Ok(tracks),
+    }
+}
+
+// Track and its artists for the log
+fn describe(track: &Track) -> String {
+    match track.artists.is_empty() {
+        true => track.name.clone(),
+        false => format!("{} by {}", track.name, track.artists.join(", ")),
+    }
+}
+
+// Names of tracks for the log
+fn names(tracks: &[Track]) -> String {
+    let names: Vec<&str> = tracks.iter().map(|track| track.name.as_str()).collect();
+    names.join(", ")
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{
+            Bounds, ListenTime, NameMatch, RotationMode, SourceSelector, TrackOrder, WeightedSource,
+        },
+        fake_spotify::FakeSpotify,
+    };
+    use std::{path::PathBuf, time::Duration};
+
+    fn spotifyd() -> SpotifydConfig {
+        SpotifydConfig {
+            config_path: PathBuf::from(".spotifyd.conf"),
+            username: String::from("user"),
+            password: String::from("password"),
+            device_name: String::from("spotifyd"),
+            connect_timeout: Duration::from_secs(30),
+        }
+    }
+
+    fn playback(checks_before_playing: u32, skip_tracks: bool) -> PlaybackConfig {
+        PlaybackConfig {
+            sources: vec![WeightedSource {
+                selector: SourceSelector::PlaylistName {
+                    name: String::from("AFK"),
+                    matching: NameMatch::Exact,
+                },
+                weight: 1,
+            }],
+            rotation: RotationMode::Weighted,
+            order: TrackOrder::Reverse,
+            seed: Some(1),
+            poll_interval: Duration::ZERO,
+            checks_before_playing,
+            time_between_checks: Duration::from_secs(10),
+            check_jitter: 0,
+            stop_when_taken_over: false,
+            skip_tracks,
+            listen_time: ListenTime::Seconds(Bounds {
+                min: Duration::from_secs(30),
+                max: Duration::from_secs(30),
+            }),
+            wait_till_skip: Duration::from_secs(30),
+            play_to_end: 0,
+            pause_chance: 0,
+            pause_length: Bounds::default(),
+        }
+    }
+
+    // Step until the fake saw this many calls that change playback
+    async fn step_until(session: &mut Session<'_, FakeSpotify>, api: &FakeSpotify, calls: usize) {
+        for _ in 0..100 {
+            if api.state().calls.len() >= calls {
+                return;
+            }
+            session.step().await.unwrap();
+        }
+        panic!("{:?}", api.state().calls);
+    }
+
+    // Session on the fake without a cache or market, tests only differ in these
+    async fn session<'a>(
+        api: &'a FakeSpotify,
+        playback: &'a PlaybackConfig,
+        filter: &'a FilterConfig,
+    ) -> Result<Session<'a, FakeSpotify>, SpotiAfkError> {
+        Session::start(
+            api,
+            playback,
+            filter,
+            PlaylistCache::default(),
+            &spotifyd(),
+            None,
+        )
+        .await
+    }
+
+    fn account() -> FakeSpotify {
+        FakeSpotify::new()
+            .with_playlist("AFK", &["a", "b"])
+            .with_device("spotifyd", "dev-1", false)
+            .with_device("phone", "dev-2", false)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_before_playing() {
+        let api = account();
+        let playback = playback(3, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        for _ in 0..2 {
+            assert_eq!(session.step().await.unwrap(), Step::Continue);
+            assert!(api.state().calls.is_empty());
+        }
+        assert_eq!(session.step().await.unwrap(), Step::Continue);
+        assert_eq!(
+            api.state().calls,
+            ["transfer to dev-1", "play b on dev-1", "queue a on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_spotifyd_to_connect() {
+        let api = account();
+        let device = api.state().devices.remove(0);
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let spotifyd = spotifyd();
+
+        let started = Instant::now();
+        let connect = async {
+            sleep(Duration::from_millis(2500)).await;
+            api.state().devices.push(device);
+        };
+        let (session, _) = tokio::join!(
+            Session::start(
+                &api,
+                &playback,
+                &filter,
+                PlaylistCache::default(),
+                &spotifyd,
+                None,
+            ),
+            connect
+        );
+        assert_eq!(session.unwrap().device_id, "dev-1");
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fails_when_spotifyd_does_not_connect_in_time() {
+        let api = account();
+        api.state().devices.remove(0);
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut spotifyd = spotifyd();
+        spotifyd.connect_timeout = Duration::from_millis(4500);
+
+        let started = Instant::now();
+        let error = Session::start(
+            &api,
+            &playback,
+            &filter,
+            PlaylistCache::default(),
+            &spotifyd,
+            None,
+        )
+        .await
+        .err()
+        .unwrap();
+        assert!(matches!(error, SpotiAfkError::DeviceNotFound(_)));
+        assert_eq!(started.elapsed(), Duration::from_millis(4500));
+    }
+
+    // Let the phone play, or stop it again
+    fn phone_plays(api: &FakeSpotify, is_playing: bool) {
+        let mut state = api.state();
+        state.devices[0].is_active = false;
+        state.devices[1].is_active = is_playing;
+        state.playing = Some(crate::spotify_api::Playing {
+            is_playing,
+            track_id: None,
+        });
+        state.track_end = None;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_while_another_device_plays() {
+        let api = account();
+        let playback = playback(2, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        // A quiet check, then the phone plays for a while
+        session.step().await.unwrap();
+        phone_plays(&api, true);
+        for _ in 0..3 {
+            assert_eq!(session.step().await.unwrap(), Step::Continue);
+        }
+        assert_eq!(session.checks, 0);
+
+        // Playback only starts after two quiet checks in a row once the phone stopped
+        phone_plays(&api, false);
+        session.step().await.unwrap();
+        assert!(api.state().calls.is_empty());
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls,
+            ["transfer to dev-1", "play b on dev-1", "queue a on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_when_another_device_takes_over() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+        session.step().await.unwrap();
+
+        phone_plays(&api, true);
+        assert_eq!(session.step().await.unwrap(), Step::Continue);
+        assert_eq!(session.state, SessionState::Checking);
+
+        // The track that was queued goes first once the phone stopped
+        phone_plays(&api, false);
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls[3..],
+            ["transfer to dev-1", "play a on dev-1", "queue b on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stops_when_another_device_takes_over_if_set() {
+        let api = account();
+        let mut playback = playback(1, false);
+        playback.stop_when_taken_over = true;
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        // Before our device played there is nothing taken over yet
+        phone_plays(&api, true);
+        assert_eq!(session.step().await.unwrap(), Step::Continue);
+        phone_plays(&api, false);
+        session.step().await.unwrap();
+
+        phone_plays(&api, true);
+        assert_eq!(session.step().await.unwrap(), Step::Stop);
+        assert_eq!(api.state().calls.len(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_playing_on_our_device() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(session.step().await.unwrap(), Step::Continue);
+        }
+        assert_eq!(session.state, SessionState::Playing);
+        assert_eq!(
+            api.state().calls,
+            ["transfer to dev-1", "play b on dev-1", "queue a on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refills_tracks_when_all_are_played() {
+        let api = account();
+        let playback = playback(1, true);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        step_until(&mut session, &api, 5).await;
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "next on dev-1",
+                "queue b on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rotates_over_the_playlists() {
+        let api = account().with_playlist("Chill", &["c", "d", "e"]);
+        let mut playback = playback(1, true);
+        playback.sources.push(WeightedSource {
+            selector: SourceSelector::PlaylistName {
+                name: String::from("Chill"),
+                matching: NameMatch::Exact,
+            },
+            weight: 1,
+        });
+        playback.rotation = RotationMode::RoundRobin(2);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        step_until(&mut session, &api, 11).await;
+        let calls: Vec<String> = api
+            .state()
+            .calls
+            .iter()
+            .filter(|call| !call.starts_with("next"))
+            .cloned()
+            .collect();
+        assert_eq!(
+            calls[1..],
+            [
+                "play b on dev-1",
+                "queue a on dev-1",
+                "queue e on dev-1",
+                "queue d on dev-1",
+                "queue b on dev-1",
+                "queue a on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn skips_to_the_queued_track() {
+        let api = account();
+        let playback = playback(1, true);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        step_until(&mut session, &api, 4).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(40));
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "next on dev-1",
+                "queue b on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn plays_tracks_to_the_end_without_skipping() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        step_until(&mut session, &api, 4).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(10 + 180));
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "queue b on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pauses_and_plays_on_within_the_listen_time() {
+        let api = account();
+        let mut playback = playback(1, true);
+        playback.pause_chance = 100;
+        playback.pause_length = Bounds {
+            min: Duration::from_secs(20),
+            max: Duration::from_secs(20),
+        };
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        let started = tokio::time::Instant::now();
+        step_until(&mut session, &api, 7).await;
+        assert_eq!(started.elapsed(), Duration::from_secs(10 + 30 + 20));
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "pause on dev-1",
+                "resume on dev-1",
+                "next on dev-1",
+                "queue b on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn starts_playback_again_when_the_device_stalls() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+        session.step().await.unwrap();
+
+        // spotifyd stops in the middle of "b" without going on with the queue
+        {
+            let mut state = api.state();
+            state.playing.as_mut().unwrap().is_playing = false;
+            state.track_end = None;
+        }
+        session.step().await.unwrap();
+        assert_eq!(session.state, SessionState::Checking);
+
+        session.step().await.unwrap();
+        assert_eq!(session.state, SessionState::Playing);
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "transfer to dev-1",
+                "play a on dev-1",
+                "queue b on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn goes_on_with_the_queued_track_the_device_reached_first() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+        session.step().await.unwrap();
+
+        // "b" ends before the session expects it to
+        api.state().track_end = Some(Instant::now());
+        session.step().await.unwrap();
+        assert_eq!(session.state, SessionState::Playing);
+        assert_eq!(session.playing.as_deref(), Some("a"));
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "queue b on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_transfer_is_an_error() {
+        let api = account();
+        api.state().failing.push(("transfer_playback", 502));
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        assert!(matches!(
+            session.step().await,
+            Err(SpotiAfkError::Spotify {
+                action: "transferring playback",
+                ..
+            })
+        ));
+        assert!(api.state().calls.is_empty());
+
+        // The next step transfers again
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls,
+            ["transfer to dev-1", "play b on dev-1", "queue a on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resyncs_after_losing_the_connection() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+        session.step().await.unwrap();
+
+        // spotifyd registers again with a new id once the connection is back
+        session.lost_connection();
+        api.state().devices[0].id = Some(String::from("dev-3"));
+        assert_eq!(session.step().await.unwrap(), Step::Continue);
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1",
+                "transfer to dev-3",
+                "play a on dev-3",
+                "queue b on dev-3"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_for_spotifyd_to_connect_again() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+        session.step().await.unwrap();
+
+        session.lost_connection();
+        let spotifyd = api.state().devices.remove(0);
+        assert_eq!(session.step().await.unwrap(), Step::Continue);
+        assert_eq!(api.state().calls.len(), 3);
+
+        api.state().devices.push(spotifyd);
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls[3..],
+            ["transfer to dev-1", "play a on dev-1", "queue b on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_when_another_device_played_while_offline() {
+        let api = account();
+        let playback = playback(2, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+        session.step().await.unwrap();
+        session.step().await.unwrap();
+
+        session.lost_connection();
+        phone_plays(&api, true);
+        assert_eq!(session.step().await.unwrap(), Step::Continue);
+        assert_eq!(session.checks, 0);
+
+        // Two quiet checks again before the queued track is played
+        phone_plays(&api, false);
+        session.step().await.unwrap();
+        assert_eq!(api.state().calls.len(), 3);
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls[3..],
+            ["transfer to dev-1", "play a on dev-1", "queue b on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_queue_is_an_error() {
+        let api = account();
+        api.state().failing.push(("add_to_queue", 404));
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        assert!(matches!(
+            session.step().await,
+            Err(SpotiAfkError::Spotify {
+                action: "queueing a track",
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn failed_playing_check_is_an_error() {
+        let api = account();
+        api.state().failing.push(("playing", 500));
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        assert!(matches!(
+            session.step().await,
+            Err(SpotiAfkError::Spotify {
+                action: "getting the playing item",
+                ..
+            })
+        ));
+        assert!(api.state().calls.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn starts_the_same_track_after_a_failed_start() {
+        let api = account();
+        api.state().failing.push(("start_playback", 502));
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        assert!(matches!(
+            session.step().await,
+            Err(SpotiAfkError::Spotify {
+                action: "starting playback",
+                ..
+            })
+        ));
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls,
+            [
+                "transfer to dev-1",
+                "transfer to dev-1",
+                "play b on dev-1",
+                "queue a on dev-1"
+            ]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queues_only_tracks_that_pass_the_filters() {
+        let api = account();
+        api.state().playlists[0].1[0].local = true;
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls[1..],
+            ["play b on dev-1", "queue b on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn plays_episodes_of_a_show_by_an_included_artist() {
+        let api = account().with_show("Podcast", &["e1", "e2"]);
+        let mut playback = playback(1, false);
+        playback.sources[0].selector = SourceSelector::Show(String::from("show-1"));
+        let filter = FilterConfig {
+            include_artists: vec![String::from("publisher")],
+            ..FilterConfig::default()
+        };
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        session.step().await.unwrap();
+        assert_eq!(
+            api.state().calls[1..],
+            ["play e2 on dev-1", "queue e1 on dev-1"]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn source_without_playable_tracks_is_an_error() {
+        let api = account();
+        let playback = playback(1, false);
+        let filter = FilterConfig {
+            exclude_artists: vec![String::from("artist")],
+            ..FilterConfig::default()
+        };
+
+        let error = session(&api, &playback, &filter).await.err().unwrap();
+        assert_eq!(error.exit_code(), 16);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn empty_playlist_is_an_error() {
+        let api = FakeSpotify::new()
+            .with_playlist("AFK", &[])
+            .with_device("spotifyd", "dev-1", false);
+        let playback = playback(1, false);
+        let filter = FilterConfig::default();
+
+        let error = session(&api, &playback, &filter).await.err().unwrap();
+        assert!(matches!(
+            error,
+            SpotiAfkError::EmptySource { filtered: None, .. }
+        ));
+        assert_eq!(error.exit_code(), 16);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn picks_up_changes_to_the_playlist() {
+        let api = account().with_playlist("AFK", &["a", "b", "c"]);
+        api.state().playlists.remove(0);
+        let mut playback = playback(1, false);
+        playback.poll_interval = Duration::from_secs(15);
+        let filter = FilterConfig::default();
+        let mut session = session(&api, &playback, &filter).await.unwrap();
+
+        session.step().await.unwrap();
+        let changed = FakeSpotify::new().with_playlist("AFK", &["b", "c", "d"]);
+        let tracks = changed.state().playlists[0].1.clone();
+        {
+            let mut state = api.state();
+            state.playlists[0].0.snapshot_id = String::from("snapshot-2");
+            state.playlists[0].1 = tracks;
+        }
+        step_until(&mut session, &api, 4).await;
+
+        // "a" was removed before its turn, "d" was added to the tracks still to play
+        assert_eq!(
+            api.state().calls[1..],
+            ["play c on dev-1", "queue b on dev-1", "queue d on dev-1"]
+        );
+
+        // The tracks are loaded at the snapshot the poll found, without asking for it again
+        let requests = &api.state().requests;
+        let changed = requests
+            .windows(2)
+            .position(|pair| pair == ["playlist_snapshot", "playlist_tracks"]);
+        assert!(changed.is_some(), "{:?}", requests);
+        assert!(!requests
+            .windows(2)
+            .any(|pair| pair == ["playlist_snapshot", "playlist_snapshot"]));
+    }
+}
@@ -0,0 +1,222 @@
+/////////////
+// Imports //
+/////////////
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+// Self made files
+use crate::{
+    config::CacheConfig,
+    error::SpotiAfkError,
+    files::write_private,
+    log,
+    spotify_api::{Playlist, Track},
+};
+
+///////////
+// Types //
+///////////
+
+// Playlists of the user and tracks of playlists, kept on disk at PLAYLIST_CACHE_PATH
+#[derive(Debug, Default)]
+pub struct PlaylistCache {
+    // None when the cache is turned off, it then only lives as long as the session
+    path: Option<PathBuf>,
+    file: CacheFile,
+
+    // How long Spotify may take when a cached copy can stand in, zero waits for the answer
+    wait: Duration,
+}
+
+// What the cache file looks like
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CacheFile {
+    playlists: Vec<Playlist>,
+    tracks: BTreeMap<String, CachedTracks>,
+}
+
+// Tracks of a playlist at the snapshot they were fetched at
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedTracks {
+    pub playlist: Playlist,
+    pub tracks: Vec<Track>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl PlaylistCache {
+    // Read the cache, a missing or damaged one starts empty
+    pub fn open(config: &CacheConfig) -> Self {
+        if !config.enabled {
+            return PlaylistCache {
+                wait: config.wait,
+                ..PlaylistCache::default()
+            };
+        }
+        let file = match fs::read_to_string(&config.path) {
+            Ok(contents) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                log!(
+                    "Ignoring the damaged playlist cache {} ({})",
+                    config.path.display(),
+                    e
+                );
+                CacheFile::default()
+            }),
+            Err(_) => CacheFile::default(),
+        };
+        PlaylistCache {
+            path: Some(config.path.clone()),
+            file,
+            wait: config.wait,
+        }
+    }
+
+    // How long to wait for Spotify before using what is cached
+    pub fn wait(&self) -> Duration {
+        self.wait
+    }
+
+    // Playlists of the user from the last time they were listed
+    pub fn playlists(&self) -> &[Playlist] {
+        &self.file.playlists
+    }
+
+    // Tracks of a playlist, only when they are of this snapshot unless it is None
+    pub fn tracks(&self, playlist_id: &str, snapshot_id: Option<&str>) -> Option<&CachedTracks> {
+        self.file
+            .tracks
+            .get(playlist_id)
+            .filter(|cached| snapshot_id.is_none_or(|id| cached.playlist.snapshot_id == id))
+    }
+
+    // Every playlist with cached tracks
+    pub fn cached(&self) -> impl Iterator<Item = &CachedTracks> {
+        self.file.tracks.values()
+    }
+
+    // Remember the playlists of the user
+    pub fn store_playlists(&mut self, playlists: &[Playlist]) {
+        self.file.playlists = playlists.to_vec();
+        self.save();
+    }
+
+    // Remember the tracks of a playlist at its current snapshot
+    pub fn store_tracks(&mut self, playlist: &Playlist, tracks: &[Track]) {
+        self.file.tracks.insert(
+            playlist.id.clone(),
+            CachedTracks {
+                playlist: playlist.clone(),
+                tracks: tracks.to_vec(),
+            },
+        );
+        self.save();
+    }
+
+    // Write the cache, a failed write only costs requests next time so it is logged
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        if let Err(e) = write(path, &self.file) {
+            log!(
+                "Failed to write the playlist cache {} ({})",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Delete the cache file, false when there was none
+pub fn clear(config: &CacheConfig) -> Result<bool, SpotiAfkError> {
+    match fs::remove_file(&config.path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(SpotiAfkError::PlaylistCache {
+            path: config.path.clone(),
+            reason: e.to_string(),
+        }),
+    }
+}
+
+// Write the cache in one go
+fn write(path: &Path, file: &CacheFile) -> io::Result<()> {
+    write_private(path, serde_json::to_string(file)?.as_bytes())
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playlist(snapshot_id: &str) -> Playlist {
+        Playlist {
+            id: String::from("playlist-1"),
+            name: String::from("AFK"),
+            track_count: 1,
+            snapshot_id: String::from(snapshot_id),
+        }
+    }
+
+    #[test]
+    fn keeps_tracks_of_a_snapshot_between_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CacheConfig {
+            enabled: true,
+            path: dir.path().join("playlists.json"),
+            wait: Duration::from_secs(10),
+        };
+        let track = Track {
+            id: Some(String::from("a")),
+            ..Track::default()
+        };
+
+        let mut cache = PlaylistCache::open(&config);
+        cache.store_playlists(&[playlist("snapshot-1")]);
+        cache.store_tracks(&playlist("snapshot-1"), std::slice::from_ref(&track));
+
+        let cache = PlaylistCache::open(&config);
+        assert_eq!(cache.playlists(), [playlist("snapshot-1")]);
+        let cached = cache.tracks("playlist-1", Some("snapshot-1")).unwrap();
+        assert_eq!(cached.tracks, [track]);
+        assert!(cache.tracks("playlist-1", Some("snapshot-2")).is_none());
+        assert!(cache.tracks("playlist-1", None).is_some());
+
+        assert!(clear(&config).unwrap());
+        assert!(!clear(&config).unwrap());
+        assert!(PlaylistCache::open(&config).playlists().is_empty());
+    }
+
+    #[test]
+    fn damaged_cache_starts_empty() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = CacheConfig {
+            enabled: true,
+            path: dir.path().join("playlists.json"),
+            wait: Duration::from_secs(10),
+        };
+        fs::write(&config.path, "{ not json").unwrap();
+
+        let mut cache = PlaylistCache::open(&config);
+        assert!(cache.playlists().is_empty());
+
+        // The next write replaces the damaged file
+        cache.store_playlists(&[playlist("snapshot-1")]);
+        assert_eq!(PlaylistCache::open(&config).playlists().len(), 1);
+    }
+}
@@ -0,0 +1,309 @@
+/////////////
+// Imports //
+/////////////
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, seq::SliceRandom, Rng};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Self made files
+use crate::{config::TrackOrder, spotify_api::Track};
+
+///////////
+// Types //
+///////////
+
+// Tracks of one source and the order they are played in
+pub struct TrackPool {
+    order: TrackOrder,
+    tracks: Vec<Track>,
+
+    // Indexes into `tracks` still to play this cycle, the next one last
+    upcoming: Vec<usize>,
+
+    // Ids of the last played tracks, newest last, and how often every id was played
+    recent: VecDeque<String>,
+    plays: HashMap<String, u32>,
+}
+
+// Tracks added to and removed from a source while it was played
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TrackDiff {
+    pub added: Vec<Track>,
+    pub removed: Vec<Track>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl TrackPool {
+    pub fn new(order: TrackOrder) -> Self {
+        TrackPool {
+            order,
+            tracks: Vec::new(),
+            upcoming: Vec::new(),
+            recent: VecDeque::new(),
+            plays: HashMap::new(),
+        }
+    }
+
+    // True when the tracks have to be fetched again before the next one
+    pub fn is_empty(&self) -> bool {
+        match self.order {
+            TrackOrder::InOrder | TrackOrder::Reverse | TrackOrder::Shuffle => {
+                self.upcoming.is_empty()
+            }
+            TrackOrder::NoRepeat(_) | TrackOrder::LeastPlayed => self.tracks.is_empty(),
+        }
+    }
+
+    // Start a new cycle over these tracks, play counts and recent tracks are kept
+    pub fn fill(&mut self, tracks: Vec<Track>, rng: &mut impl Rng) {
+        self.tracks = tracks;
+        self.upcoming = (0..self.tracks.len()).collect();
+        match self.order {
+            // Taken from the back, so in order is the list reversed
+            TrackOrder::InOrder => self.upcoming.reverse(),
+            TrackOrder::Shuffle => self.upcoming.shuffle(rng),
+            _ => {}
+        }
+    }
+
+    // Swap in the current tracks of the source without starting a new cycle,
+    // removed tracks leave the cycle and added ones join it
+    pub fn update(&mut self, tracks: Vec<Track>, rng: &mut impl Rng) -> TrackDiff {
+        let old: HashSet<&Option<String>> = self.tracks.iter().map(|track| &track.id).collect();
+        let new: HashSet<&Option<String>> = tracks.iter().map(|track| &track.id).collect();
+        let diff = TrackDiff {
+            added: tracks
+                .iter()
+                .filter(|track| !old.contains(&track.id))
+                .cloned()
+                .collect(),
+            removed: self
+                .tracks
+                .iter()
+                .filter(|track| !new.contains(&track.id))
+                .cloned()
+                .collect(),
+        };
+
+        // Indexes of the new tracks by id, to find the tracks still to play among them
+        let mut positions: HashMap<&Option<String>, Vec<usize>> = HashMap::new();
+        for (index, track) in tracks.iter().enumerate().rev() {
+            positions.entry(&track.id).or_default().push(index);
+        }
+        let mut upcoming: Vec<usize> = self
+            .upcoming
+            .iter()
+            .filter_map(|index| positions.get_mut(&self.tracks[*index].id)?.pop())
+            .collect();
+        let added = (0..tracks.len()).filter(|index| !old.contains(&tracks[*index].id));
+        match self.order {
+            TrackOrder::Shuffle => {
+                for index in added {
+                    upcoming.insert(rng.gen_range(0..=upcoming.len()), index);
+                }
+            }
+            // Keep the order of the source, the next track last
+            TrackOrder::InOrder | TrackOrder::Reverse => {
+                upcoming.extend(added);
+                upcoming.sort_unstable();
+                if self.order == TrackOrder::InOrder {
+                    upcoming.reverse();
+                }
+            }
+            TrackOrder::NoRepeat(_) | TrackOrder::LeastPlayed => {}
+        }
+
+        self.upcoming = upcoming;
+        self.tracks = tracks;
+        diff
+    }
+
+    // Track to play next, None when the pool is empty
+    pub fn next(&mut self, rng: &mut impl Rng) -> Option<Track> {
+        let index = match self.order {
+            TrackOrder::InOrder | TrackOrder::Reverse | TrackOrder::Shuffle => {
+                self.upcoming.pop()?
+            }
+            TrackOrder::NoRepeat(_) => self.not_recent(rng)?,
+            TrackOrder::LeastPlayed => self.least_played(rng)?,
+        };
+        let track = self.tracks[index].clone();
+        if let Some(id) = &track.id {
+            *self.plays.entry(id.clone()).or_insert(0) += 1;
+            if let TrackOrder::NoRepeat(window) = self.order {
+                self.recent.push_back(id.clone());
+                if self.recent.len() > window {
+                    self.recent.pop_front();
+                }
+            }
+        }
+        Some(track)
+    }
+
+    // Random track not played lately, the window shrinks when the pool is smaller
+    fn not_recent(&self, rng: &mut impl Rng) -> Option<usize> {
+        let window = self.recent.len().min(self.tracks.len().checked_sub(1)?);
+        let recent: Vec<&String> = self.recent.iter().rev().take(window).collect();
+        let allowed: Vec<usize> = (0..self.tracks.len())
+            .filter(|index| match &self.tracks[*index].id {
+                Some(id) => !recent.contains(&id),
+                None => true,
+            })
+            .collect();
+        allowed.choose(rng).copied()
+    }
+
+    // Random track where one played less often is more likely
+    fn least_played(&self, rng: &mut impl Rng) -> Option<usize> {
+        let weights = self.tracks.iter().map(|track| {
+            let plays = track
+                .id
+                .as_ref()
+                .and_then(|id| self.plays.get(id))
+                .copied()
+                .unwrap_or(0);
+            1.0 / f64::from(plays + 1)
+        });
+        WeightedIndex::new(weights)
+            .ok()
+            .map(|index| index.sample(rng))
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn tracks(ids: &[&str]) -> Vec<Track> {
+        ids.iter()
+            .map(|id| Track {
+                id: Some(String::from(*id)),
+                ..Track::default()
+            })
+            .collect()
+    }
+
+    fn play(order: TrackOrder, ids: &[&str], count: usize, seed: u64) -> Vec<String> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut pool = TrackPool::new(order);
+        let mut played = Vec::new();
+        for _ in 0..count {
+            if pool.is_empty() {
+                pool.fill(tracks(ids), &mut rng);
+            }
+            played.extend(pool.next(&mut rng).and_then(|track| track.id));
+        }
+        played
+    }
+
+    #[test]
+    fn plays_in_order_or_reverse_every_cycle() {
+        let ids = ["a", "b", "c"];
+        assert_eq!(play(TrackOrder::InOrder, &ids, 4, 1), ["a", "b", "c", "a"]);
+        assert_eq!(play(TrackOrder::Reverse, &ids, 4, 1), ["c", "b", "a", "c"]);
+    }
+
+    #[test]
+    fn shuffles_every_cycle_the_same_with_a_seed() {
+        let ids = ["a", "b", "c", "d", "e"];
+        let played = play(TrackOrder::Shuffle, &ids, 10, 7);
+        assert_eq!(played, play(TrackOrder::Shuffle, &ids, 10, 7));
+        assert_ne!(played, play(TrackOrder::Shuffle, &ids, 10, 8));
+
+        // Every cycle plays every track once
+        for cycle in played.chunks(5) {
+            let mut cycle = cycle.to_vec();
+            cycle.sort();
+            assert_eq!(cycle, ids);
+        }
+    }
+
+    #[test]
+    fn does_not_repeat_recent_tracks() {
+        let ids = ["a", "b", "c", "d"];
+        let played = play(TrackOrder::NoRepeat(2), &ids, 200, 3);
+        for window in played.windows(3) {
+            assert_ne!(window[0], window[1]);
+            assert_ne!(window[0], window[2]);
+            assert_ne!(window[1], window[2]);
+        }
+
+        // A window as large as the pool still leaves a track to play
+        assert_eq!(play(TrackOrder::NoRepeat(5), &["a", "b"], 4, 3).len(), 4);
+    }
+
+    #[test]
+    fn updates_the_tracks_still_to_play() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for (order, played, rest) in [
+            (TrackOrder::InOrder, "a", ["b", "d", "e"]),
+            (TrackOrder::Reverse, "d", ["e", "b", "a"]),
+        ] {
+            let mut pool = TrackPool::new(order);
+            pool.fill(tracks(&["a", "b", "c", "d"]), &mut rng);
+            assert_eq!(pool.next(&mut rng).unwrap().id.as_deref(), Some(played));
+
+            let diff = pool.update(tracks(&["a", "b", "d", "e"]), &mut rng);
+            assert_eq!(diff.added, tracks(&["e"]));
+            assert_eq!(diff.removed, tracks(&["c"]));
+
+            let next: Vec<String> = std::iter::from_fn(|| pool.next(&mut rng))
+                .take(3)
+                .filter_map(|track| track.id)
+                .collect();
+            assert_eq!(next, rest);
+            assert!(pool.is_empty());
+        }
+    }
+
+    #[test]
+    fn shuffles_added_tracks_into_the_cycle() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut pool = TrackPool::new(TrackOrder::Shuffle);
+        pool.fill(tracks(&["a", "b", "c"]), &mut rng);
+        let played = pool.next(&mut rng).and_then(|track| track.id).unwrap();
+
+        let removed = if played == "a" { "b" } else { "a" };
+        let remaining: Vec<&str> = ["a", "b", "c", "x", "y"]
+            .into_iter()
+            .filter(|id| *id != removed)
+            .collect();
+        pool.update(tracks(&remaining), &mut rng);
+
+        let mut rest: Vec<String> = std::iter::from_fn(|| pool.next(&mut rng))
+            .take(3)
+            .filter_map(|track| track.id)
+            .collect();
+        rest.sort();
+        let mut expected: Vec<&str> = remaining.into_iter().filter(|id| *id != played).collect();
+        expected.sort();
+        assert_eq!(rest, expected);
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn prefers_tracks_played_less_often() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut pool = TrackPool::new(TrackOrder::LeastPlayed);
+        pool.fill(tracks(&["a", "b"]), &mut rng);
+        pool.plays.insert(String::from("a"), 99);
+
+        let b = (0..1_000)
+            .filter(|_| {
+                let track = pool.next(&mut rng).unwrap();
+                pool.plays.clear();
+                pool.plays.insert(String::from("a"), 99);
+                track.id.as_deref() == Some("b")
+            })
+            .count();
+        assert!(b > 950, "{}", b);
+    }
+}
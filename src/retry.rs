@@ -0,0 +1,360 @@
+/////////////
+// Imports //
+/////////////
+
+use async_trait::async_trait;
+use rand::Rng;
+use rspotify::model::Country;
+use std::{future::Future, time::Duration};
+use tokio::time::sleep;
+
+// Self made files
+use crate::{
+    log,
+    spotify_api::{ApiError, Collection, Device, Page, Playing, Playlist, SpotifyApi, Track},
+};
+
+///////////////
+// Constants //
+///////////////
+
+// Wait before the first retry of a failed request, doubled every retry
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+
+// Longest wait between retries of a failed request
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+// Longest wait when Spotify asks to come back later, it can ask for hours
+const RETRY_AFTER_MAX: Duration = Duration::from_secs(2 * 60);
+
+///////////
+// Types //
+///////////
+
+// Spotify API that retries throttled and failed requests
+pub struct Retrying<'a, A: SpotifyApi> {
+    api: &'a A,
+    retries: u32,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl<'a, A: SpotifyApi> Retrying<'a, A> {
+    pub fn new(api: &'a A, retries: u32) -> Self {
+        Retrying { api, retries }
+    }
+
+    // Send the request until it succeeds, fails for good or the retries run out
+    async fn retry<T, F, Fut>(&self, request: F) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T, ApiError>> + Send,
+        T: Send,
+    {
+        self.retry_when(transient, request).await
+    }
+
+    // Only retry requests Spotify refused to handle, for requests that can not be sent twice:
+    // a server error can come after the track was already queued or skipped
+    async fn retry_throttled<T, F, Fut>(&self, request: F) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T, ApiError>> + Send,
+        T: Send,
+    {
+        self.retry_when(throttled, request).await
+    }
+
+    async fn retry_when<T, F, Fut>(
+        &self,
+        retryable: fn(&ApiError) -> bool,
+        mut request: F,
+    ) -> Result<T, ApiError>
+    where
+        F: FnMut() -> Fut + Send,
+        Fut: Future<Output = Result<T, ApiError>> + Send,
+        T: Send,
+    {
+        let mut retry = 0;
+        loop {
+            match request().await {
+                Err(e) if retry < self.retries && retryable(&e) => {
+                    let wait = match e.retry_after {
+                        Some(retry_after) => retry_after.min(RETRY_AFTER_MAX),
+                        None => backoff(retry),
+                    };
+                    match e.status {
+                        Some(429) => log!("Spotify is rate limiting, retrying in {:?}", wait),
+                        _ => log!("Spotify request failed ({}), retrying in {:?}", e, wait),
+                    }
+                    sleep(wait).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<A: SpotifyApi> SpotifyApi for Retrying<'_, A> {
+    fn page_size(&self) -> u32 {
+        self.api.page_size()
+    }
+
+    async fn user_country(&self) -> Result<Option<Country>, ApiError> {
+        self.retry(|| self.api.user_country()).await
+    }
+
+    async fn playlists(&self, limit: u32, offset: u32) -> Result<Page<Playlist>, ApiError> {
+        self.retry(|| self.api.playlists(limit, offset)).await
+    }
+
+    async fn playlist(&self, playlist_id: &str) -> Result<Playlist, ApiError> {
+        self.retry(|| self.api.playlist(playlist_id)).await
+    }
+
+    async fn playlist_snapshot(&self, playlist_id: &str) -> Result<String, ApiError> {
+        self.retry(|| self.api.playlist_snapshot(playlist_id)).await
+    }
+
+    async fn playlist_tracks(
+        &self,
+        playlist_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.retry(|| self.api.playlist_tracks(playlist_id, market, limit, offset))
+            .await
+    }
+
+    async fn album(&self, album_id: &str) -> Result<Collection, ApiError> {
+        self.retry(|| self.api.album(album_id)).await
+    }
+
+    async fn album_tracks(
+        &self,
+        album_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.retry(|| self.api.album_tracks(album_id, market, limit, offset))
+            .await
+    }
+
+    async fn artist(&self, artist_id: &str) -> Result<Collection, ApiError> {
+        self.retry(|| self.api.artist(artist_id)).await
+    }
+
+    async fn artist_top_tracks(
+        &self,
+        artist_id: &str,
+        market: Option<Country>,
+    ) -> Result<Vec<Track>, ApiError> {
+        self.retry(|| self.api.artist_top_tracks(artist_id, market))
+            .await
+    }
+
+    async fn artist_albums(
+        &self,
+        artist_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Collection>, ApiError> {
+        self.retry(|| self.api.artist_albums(artist_id, market, limit, offset))
+            .await
+    }
+
+    async fn saved_tracks(
+        &self,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.retry(|| self.api.saved_tracks(market, limit, offset))
+            .await
+    }
+
+    async fn show(&self, show_id: &str, market: Option<Country>) -> Result<Collection, ApiError> {
+        self.retry(|| self.api.show(show_id, market)).await
+    }
+
+    async fn show_episodes(
+        &self,
+        show_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        self.retry(|| self.api.show_episodes(show_id, market, limit, offset))
+            .await
+    }
+
+    async fn devices(&self) -> Result<Vec<Device>, ApiError> {
+        self.retry(|| self.api.devices()).await
+    }
+
+    async fn playing(&self) -> Result<Option<Playing>, ApiError> {
+        self.retry(|| self.api.playing()).await
+    }
+
+    async fn transfer_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        self.retry(|| self.api.transfer_playback(device_id)).await
+    }
+
+    async fn start_playback(&self, track: &Track, device_id: &str) -> Result<(), ApiError> {
+        self.retry(|| self.api.start_playback(track, device_id))
+            .await
+    }
+
+    async fn add_to_queue(&self, track: &Track, device_id: &str) -> Result<(), ApiError> {
+        self.retry_throttled(|| self.api.add_to_queue(track, device_id))
+            .await
+    }
+
+    async fn next_track(&self, device_id: &str) -> Result<(), ApiError> {
+        self.retry_throttled(|| self.api.next_track(device_id))
+            .await
+    }
+
+    async fn pause_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        self.retry(|| self.api.pause_playback(device_id)).await
+    }
+
+    async fn resume_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        self.retry(|| self.api.resume_playback(device_id)).await
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Check if trying the same request again later can succeed
+fn transient(error: &ApiError) -> bool {
+    matches!(error.status, Some(429 | 500 | 502 | 503 | 504))
+}
+
+// Check if Spotify refused the request before handling it
+fn throttled(error: &ApiError) -> bool {
+    error.status == Some(429)
+}
+
+// Exponential wait before a retry, randomized so clients do not retry in step
+fn backoff(retry: u32) -> Duration {
+    let wait = BACKOFF_MIN
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(BACKOFF_MAX);
+    wait.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fake_spotify::FakeSpotify, spotify_api::TrackKind};
+    use tokio::time::Instant;
+
+    fn track() -> Track {
+        Track {
+            id: Some(String::from("track-1")),
+            name: String::from("track-1"),
+            duration: Duration::from_secs(180),
+            kind: TrackKind::Music,
+            ..Track::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_as_long_as_spotify_asks() {
+        let fake = FakeSpotify::new().with_device("spotifyd", "dev-1", false);
+        fake.state().failing.push(("devices", 429));
+        fake.state().retry_after = Some(Duration::from_secs(7));
+        let api = Retrying::new(&fake, 3);
+
+        let started = Instant::now();
+        assert_eq!(api.devices().await.unwrap().len(), 1);
+        assert_eq!(started.elapsed(), Duration::from_secs(7));
+
+        // Not for hours though
+        fake.state().failing.push(("devices", 429));
+        fake.state().retry_after = Some(Duration::from_secs(3 * 60 * 60));
+        let started = Instant::now();
+        assert_eq!(api.devices().await.unwrap().len(), 1);
+        assert_eq!(started.elapsed(), RETRY_AFTER_MAX);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn backs_off_on_server_errors() {
+        let fake = FakeSpotify::new();
+        fake.state()
+            .failing
+            .extend([("transfer_playback", 502), ("transfer_playback", 503)]);
+        let api = Retrying::new(&fake, 3);
+
+        let started = Instant::now();
+        api.transfer_playback("dev-1").await.unwrap();
+        assert_eq!(fake.state().calls, ["transfer to dev-1"]);
+
+        // 1 and 2 seconds, both cut in half at most by the randomness
+        let waited = started.elapsed();
+        assert!(waited >= Duration::from_millis(1500), "{:?}", waited);
+        assert!(waited <= Duration::from_secs(3), "{:?}", waited);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_when_the_retries_run_out() {
+        let fake = FakeSpotify::new();
+        fake.state().failing.extend([("add_to_queue", 429); 3]);
+        let api = Retrying::new(&fake, 2);
+
+        let error = api.add_to_queue(&track(), "dev-1").await.unwrap_err();
+        assert_eq!(error.status, Some(429));
+        assert!(fake.state().failing.is_empty());
+        assert!(fake.state().calls.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn queues_and_skips_once_after_server_errors() {
+        let fake = FakeSpotify::new();
+        fake.state()
+            .failing
+            .extend([("add_to_queue", 500), ("next_track", 503)]);
+        let api = Retrying::new(&fake, 3);
+
+        let error = api.add_to_queue(&track(), "dev-1").await.unwrap_err();
+        assert_eq!(error.status, Some(500));
+        let error = api.next_track("dev-1").await.unwrap_err();
+        assert_eq!(error.status, Some(503));
+
+        fake.state().failing.push(("next_track", 429));
+        api.next_track("dev-1").await.unwrap();
+        assert_eq!(fake.state().calls, ["next on dev-1"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_other_errors() {
+        let fake = FakeSpotify::new();
+        fake.state().failing.extend([("transfer_playback", 404); 2]);
+        let api = Retrying::new(&fake, 5);
+
+        let error = api.transfer_playback("dev-1").await.unwrap_err();
+        assert_eq!(error.status, Some(404));
+        assert_eq!(fake.state().failing.len(), 1);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert!(backoff(0) <= BACKOFF_MIN);
+        assert!(backoff(40) <= BACKOFF_MAX);
+        assert!(backoff(40) >= BACKOFF_MAX / 2);
+    }
+}
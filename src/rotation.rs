@@ -0,0 +1,89 @@
+/////////////
+// Imports //
+/////////////
+
+use rand::{distributions::WeightedIndex, prelude::Distribution, Rng};
+
+// Self made files
+use crate::config::RotationMode;
+
+///////////
+// Types //
+///////////
+
+// Picks the source every next track is taken from
+pub struct Rotation {
+    mode: RotationMode,
+    weights: WeightedIndex<u32>,
+    sources: usize,
+
+    // Source of the current round robin turn and the tracks it played so far
+    turn: usize,
+    played: u32,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl Rotation {
+    // Rotation over sources with these weights, the config makes sure there is at least one
+    // and that their total fits
+    pub fn new(mode: RotationMode, weights: &[u32]) -> Self {
+        Rotation {
+            mode,
+            weights: WeightedIndex::new(weights).expect("weights are validated by the config"),
+            sources: weights.len(),
+            turn: 0,
+            played: 0,
+        }
+    }
+
+    // Index of the source to take the next track from
+    pub fn next(&mut self, rng: &mut impl Rng) -> usize {
+        match self.mode {
+            RotationMode::Weighted => self.weights.sample(rng),
+            RotationMode::RoundRobin(tracks) => {
+                if self.played == tracks {
+                    self.turn = (self.turn + 1) % self.sources;
+                    self.played = 0;
+                }
+                self.played += 1;
+                self.turn
+            }
+        }
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn draws_by_weight() {
+        let mut rotation = Rotation::new(RotationMode::Weighted, &[60, 30, 10]);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let mut drawn = [0i32; 3];
+        for _ in 0..10_000 {
+            drawn[rotation.next(&mut rng)] += 1;
+        }
+        for (count, share) in drawn.into_iter().zip([6_000, 3_000, 1_000]) {
+            assert!((count - share).abs() < 300, "{:?}", drawn);
+        }
+    }
+
+    #[test]
+    fn takes_turns_every_few_tracks() {
+        let mut rotation = Rotation::new(RotationMode::RoundRobin(2), &[1, 5, 1]);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        let picks: Vec<usize> = (0..7).map(|_| rotation.next(&mut rng)).collect();
+        assert_eq!(picks, [0, 0, 1, 1, 2, 2, 0]);
+    }
+}
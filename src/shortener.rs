@@ -0,0 +1,341 @@
+/////////////
+// Imports //
+/////////////
+
+use async_trait::async_trait;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde_json::{json, Value};
+use std::{error::Error, fmt};
+
+// Self made files
+use crate::{
+    config::{ShortenerBackend, ShortenerConfig},
+    log,
+};
+
+///////////////
+// Constants //
+///////////////
+
+const BITLY_API_URL: &str = "https://api-ssl.bitly.com/v4/shorten";
+
+////////////
+// Traits //
+////////////
+
+// Service that turns the long authorize url into something easier to type
+#[async_trait]
+pub trait LinkShortener: Send + Sync {
+    async fn shorten(&self, long_url: &str) -> Result<String, ShortenError>;
+}
+
+///////////
+// Types //
+///////////
+
+// Why a url could not be shortened
+#[derive(Debug)]
+pub enum ShortenError {
+    // The service did not answer within SHORTENER_TIMEOUT
+    Timeout,
+
+    // The service could not be reached
+    Request(reqwest::Error),
+
+    // The service answered with an error, like an invalid token or a used up quota
+    Rejected { status: u16, reason: String },
+
+    // The service answered with something we do not understand
+    InvalidResponse(String),
+}
+
+// Keeps the long url
+pub struct NoShortener;
+
+// bit.ly API v4
+pub struct Bitly {
+    client: Client,
+    token: String,
+}
+
+// YOURLS API, `url` points at yourls-api.php
+pub struct Yourls {
+    client: Client,
+    url: String,
+    signature: Option<String>,
+}
+
+// Shlink REST API, `url` is the base url of the instance
+pub struct Shlink {
+    client: Client,
+    url: String,
+    api_key: String,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+#[async_trait]
+impl LinkShortener for NoShortener {
+    async fn shorten(&self, long_url: &str) -> Result<String, ShortenError> {
+        Ok(String::from(long_url))
+    }
+}
+
+#[async_trait]
+impl LinkShortener for Bitly {
+    async fn shorten(&self, long_url: &str) -> Result<String, ShortenError> {
+        let request = self
+            .client
+            .post(BITLY_API_URL)
+            .bearer_auth(&self.token)
+            .json(&json!({ "long_url": long_url }));
+        let (status, body) = send(request).await?;
+        match status.is_success() {
+            true => field(&body, "link"),
+            false => Err(rejected(status, &body, "message")),
+        }
+    }
+}
+
+#[async_trait]
+impl LinkShortener for Yourls {
+    async fn shorten(&self, long_url: &str) -> Result<String, ShortenError> {
+        let mut query = vec![
+            ("action", "shorturl"),
+            ("format", "json"),
+            ("url", long_url),
+        ];
+        if let Some(signature) = &self.signature {
+            query.push(("signature", signature));
+        }
+
+        // YOURLS fails with the existing short url when the url was shortened before
+        let (status, body) = send(self.client.get(&self.url).query(&query)).await?;
+        match (status.is_success(), field(&body, "shorturl")) {
+            (_, Ok(short_url)) => Ok(short_url),
+            (true, Err(e)) => Err(e),
+            (false, Err(_)) => Err(rejected(status, &body, "message")),
+        }
+    }
+}
+
+#[async_trait]
+impl LinkShortener for Shlink {
+    async fn shorten(&self, long_url: &str) -> Result<String, ShortenError> {
+        let request = self
+            .client
+            .post(format!(
+                "{}/rest/v3/short-urls",
+                self.url.trim_end_matches('/')
+            ))
+            .header("X-Api-Key", &self.api_key)
+            .json(&json!({ "longUrl": long_url, "findIfExists": true }));
+        let (status, body) = send(request).await?;
+        match status.is_success() {
+            true => field(&body, "shortUrl"),
+            false => Err(rejected(status, &body, "detail")),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ShortenError {
+    fn from(e: reqwest::Error) -> Self {
+        match e.is_timeout() {
+            true => ShortenError::Timeout,
+            false => ShortenError::Request(e),
+        }
+    }
+}
+
+impl fmt::Display for ShortenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShortenError::Timeout => write!(f, "the shortener did not answer in time"),
+            ShortenError::Request(e) => write!(f, "can not reach the shortener ({})", e),
+            ShortenError::Rejected { status, reason } => {
+                write!(f, "the shortener refused with {} ({})", status, reason)
+            }
+            ShortenError::InvalidResponse(reason) => {
+                write!(f, "the shortener sent an invalid response ({})", reason)
+            }
+        }
+    }
+}
+
+impl Error for ShortenError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ShortenError::Request(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Make the shortener selected in the config
+pub fn link_shortener(config: &ShortenerConfig) -> Result<Box<dyn LinkShortener>, ShortenError> {
+    let client = Client::builder().timeout(config.timeout).build()?;
+    Ok(match &config.backend {
+        ShortenerBackend::Disabled => Box::new(NoShortener),
+        ShortenerBackend::Bitly { token } => Box::new(Bitly {
+            client,
+            token: token.clone(),
+        }),
+        ShortenerBackend::Yourls { url, signature } => Box::new(Yourls {
+            client,
+            url: url.clone(),
+            signature: signature.clone(),
+        }),
+        ShortenerBackend::Shlink { url, api_key } => Box::new(Shlink {
+            client,
+            url: url.clone(),
+            api_key: api_key.clone(),
+        }),
+    })
+}
+
+// Shorten the url with the service selected in the config,
+// a failing shortener should not stop the login so it falls back to the long url
+pub async fn shorten_or_keep(config: &ShortenerConfig, long_url: &str) -> String {
+    let short_url = match link_shortener(config) {
+        Ok(link_shortener) => link_shortener.shorten(long_url).await,
+        Err(e) => Err(e),
+    };
+    match short_url {
+        Ok(short_url) => short_url,
+        Err(e) => {
+            log!("Could not shorten the login url, {}", e);
+            String::from(long_url)
+        }
+    }
+}
+
+// Send a request and get the status with the JSON body, which is null when it is not JSON
+async fn send(request: RequestBuilder) -> Result<(StatusCode, Value), ShortenError> {
+    let response = request.send().await?;
+    let status = response.status();
+    let body = response.text().await?;
+    Ok((status, serde_json::from_str(&body).unwrap_or(Value::Null)))
+}
+
+// Error for a refused request, with the reason the service gave
+fn rejected(status: StatusCode, body: &Value, reason_field: &str) -> ShortenError {
+    ShortenError::Rejected {
+        status: status.as_u16(),
+        reason: String::from(
+            body[reason_field]
+                .as_str()
+                .or_else(|| status.canonical_reason())
+                .unwrap_or("no reason given"),
+        ),
+    }
+}
+
+// String field of a JSON response
+fn field(body: &Value, name: &str) -> Result<String, ShortenError> {
+    match body[name].as_str() {
+        Some(value) => Ok(String::from(value)),
+        None => Err(ShortenError::InvalidResponse(format!("no {} field", name))),
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    const LONG_URL: &str = "https://accounts.spotify.com/authorize?client_id=id";
+
+    // Server answering every request with the same status and body, after a delay
+    async fn server(status: u16, body: &'static str, delay: Duration) -> String {
+        let app = Router::new().fallback(move || async move {
+            tokio::time::sleep(delay).await;
+            (axum::http::StatusCode::from_u16(status).unwrap(), body)
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        url
+    }
+
+    fn shlink(url: String, timeout: Duration) -> ShortenerConfig {
+        ShortenerConfig {
+            backend: ShortenerBackend::Shlink {
+                url,
+                api_key: String::from("key"),
+            },
+            timeout,
+        }
+    }
+
+    async fn shorten(config: &ShortenerConfig) -> Result<String, ShortenError> {
+        link_shortener(config).unwrap().shorten(LONG_URL).await
+    }
+
+    #[tokio::test]
+    async fn reads_the_short_url_from_the_response() {
+        let body = r#"{"shortUrl": "https://s.test/abc"}"#;
+        let url = server(200, body, Duration::ZERO).await;
+        let short_url = shorten(&shlink(url, Duration::from_secs(5))).await;
+        assert_eq!(short_url.unwrap(), "https://s.test/abc");
+
+        // YOURLS refuses urls it shortened before but still sends the short url
+        let body = r#"{"message": "already exists", "shorturl": "https://y.test/1"}"#;
+        let url = server(400, body, Duration::ZERO).await;
+        let yourls = ShortenerConfig {
+            backend: ShortenerBackend::Yourls {
+                url: format!("{}/yourls-api.php", url),
+                signature: Some(String::from("signature")),
+            },
+            timeout: Duration::from_secs(5),
+        };
+        assert_eq!(shorten(&yourls).await.unwrap(), "https://y.test/1");
+    }
+
+    #[tokio::test]
+    async fn reports_the_reason_of_a_refused_request() {
+        let body = r#"{"detail": "Invalid API key"}"#;
+        let url = server(401, body, Duration::ZERO).await;
+        assert!(matches!(
+            shorten(&shlink(url, Duration::from_secs(5))).await,
+            Err(ShortenError::Rejected { status: 401, reason }) if reason == "Invalid API key"
+        ));
+
+        let url = server(503, "<html>down</html>", Duration::ZERO).await;
+        assert!(matches!(
+            shorten(&shlink(url, Duration::from_secs(5))).await,
+            Err(ShortenError::Rejected { status: 503, reason }) if reason == "Service Unavailable"
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_a_malformed_response() {
+        for body in ["not json", r#"{"short": "https://s.test/abc"}"#] {
+            let url = server(200, body, Duration::ZERO).await;
+            assert!(matches!(
+                shorten(&shlink(url, Duration::from_secs(5))).await,
+                Err(ShortenError::InvalidResponse(reason)) if reason == "no shortUrl field"
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn falls_back_to_the_long_url_after_the_timeout() {
+        let body = r#"{"shortUrl": "https://s.test/abc"}"#;
+        let url = server(200, body, Duration::from_secs(5)).await;
+        let config = shlink(url, Duration::from_millis(100));
+        assert!(matches!(shorten(&config).await, Err(ShortenError::Timeout)));
+        assert_eq!(shorten_or_keep(&config, LONG_URL).await, LONG_URL);
+    }
+}
@@ -0,0 +1,510 @@
+/////////////
+// Imports //
+/////////////
+
+use rspotify::model::Country;
+use std::{fmt, future::Future, time::Duration};
+use tokio::time::timeout;
+
+// Self made files
+use crate::{
+    config::SourceSelector,
+    error::SpotiAfkError,
+    functions::{get_playlists, get_tracks},
+    log,
+    playlist_cache::PlaylistCache,
+    spotify_api::{ApiError, Collection, Page, Playlist, SpotifyApi, Track},
+};
+
+///////////
+// Types //
+///////////
+
+// Source looked up on Spotify, it gives the tracks to play
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrackSource {
+    Playlist(Playlist),
+    Album(Collection),
+    TopTracks(Collection),
+    Discography(Collection),
+    LikedSongs,
+    Show(Collection),
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl fmt::Display for TrackSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrackSource::Playlist(playlist) => write!(f, "playlist {}", playlist.name),
+            TrackSource::Album(album) => write!(f, "album {}", album.name),
+            TrackSource::TopTracks(artist) => write!(f, "top tracks of {}", artist.name),
+            TrackSource::Discography(artist) => write!(f, "discography of {}", artist.name),
+            TrackSource::LikedSongs => write!(f, "Liked Songs"),
+            TrackSource::Show(show) => write!(f, "show {}", show.name),
+        }
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Look up the source to play
+pub async fn get_source(
+    api: &impl SpotifyApi,
+    selector: &SourceSelector,
+    market: Option<Country>,
+    cache: &mut PlaylistCache,
+) -> Result<TrackSource, SpotiAfkError> {
+    // Sources by id are fetched directly, so they do not have to be in the user's library
+    let found = match selector {
+        SourceSelector::Playlist(id) => match (
+            within(cached_wait(cache, id), api.playlist(id), |e| e).await,
+            cache.tracks(id, None),
+        ) {
+            // Starting does not have to wait for Spotify when the playlist is cached
+            (Err(e), Some(cached)) if e.status != Some(404) => {
+                log!("Using the cached playlist {} ({})", cached.playlist.name, e);
+                Ok(TrackSource::Playlist(cached.playlist.clone()))
+            }
+            (found, _) => found.map(TrackSource::Playlist),
+        },
+        SourceSelector::Album(id) => api.album(id).await.map(TrackSource::Album),
+        SourceSelector::TopTracks(id) => api.artist(id).await.map(TrackSource::TopTracks),
+        SourceSelector::Discography(id) => api.artist(id).await.map(TrackSource::Discography),
+        SourceSelector::Show(id) => api.show(id, market).await.map(TrackSource::Show),
+        SourceSelector::LikedSongs => Ok(TrackSource::LikedSongs),
+        SourceSelector::PlaylistName { name, matching } => {
+            // A name has to point at a single playlist
+            let wait = match cache.playlists().is_empty() {
+                true => Duration::ZERO,
+                false => cache.wait(),
+            };
+            let request = get_playlists(api);
+            let playlists =
+                match within(wait, request, SpotiAfkError::spotify("getting playlists")).await {
+                    Ok(playlists) => {
+                        cache.store_playlists(&playlists);
+                        playlists
+                    }
+                    Err(e) if !cache.playlists().is_empty() => {
+                        log!("Using the cached playlists ({})", e);
+                        cache.playlists().to_vec()
+                    }
+                    Err(e) => return Err(e),
+                };
+            let mut matches: Vec<Playlist> = playlists
+                .into_iter()
+                .filter(|playlist| matching.matches(name, &playlist.name))
+                .collect();
+            return match matches.len() {
+                0 => Err(SpotiAfkError::SourceNotFound(selector.to_string())),
+                1 => Ok(TrackSource::Playlist(matches.remove(0))),
+                _ => Err(SpotiAfkError::PlaylistAmbiguous {
+                    wanted: selector.to_string(),
+                    matches: matches
+                        .iter()
+                        .map(|playlist| format!("{} ({})", playlist.id, playlist.name))
+                        .collect(),
+                }),
+            };
+        }
+    };
+    match found {
+        Ok(source) => Ok(source),
+        Err(e) if e.status == Some(404) => Err(SpotiAfkError::SourceNotFound(selector.to_string())),
+        Err(e) => Err(SpotiAfkError::spotify("getting the source to play")(e)),
+    }
+}
+
+// Get every track of a source
+pub async fn get_source_tracks(
+    api: &impl SpotifyApi,
+    source: &TrackSource,
+    market: Option<Country>,
+    cache: &mut PlaylistCache,
+) -> Result<Vec<Track>, SpotiAfkError> {
+    let limit = api.page_size();
+    match source {
+        TrackSource::Playlist(playlist) => playlist_tracks(api, playlist, market, cache).await,
+        TrackSource::Album(album) => {
+            all_pages(limit, "getting album tracks", |offset| {
+                api.album_tracks(&album.id, market, limit, offset)
+            })
+            .await
+        }
+        TrackSource::TopTracks(artist) => api
+            .artist_top_tracks(&artist.id, market)
+            .await
+            .map_err(SpotiAfkError::spotify("getting top tracks")),
+        TrackSource::Discography(artist) => {
+            let albums = all_pages(limit, "getting albums of the artist", |offset| {
+                api.artist_albums(&artist.id, market, limit, offset)
+            })
+            .await?;
+            let mut tracks = Vec::new();
+            for album in albums {
+                tracks.extend(
+                    all_pages(limit, "getting album tracks", |offset| {
+                        api.album_tracks(&album.id, market, limit, offset)
+                    })
+                    .await?,
+                );
+            }
+            Ok(tracks)
+        }
+        TrackSource::LikedSongs => {
+            all_pages(limit, "getting Liked Songs", |offset| {
+                api.saved_tracks(market, limit, offset)
+            })
+            .await
+        }
+        TrackSource::Show(show) => {
+            let mut episodes = all_pages(limit, "getting show episodes", |offset| {
+                api.show_episodes(&show.id, market, limit, offset)
+            })
+            .await?;
+
+            // Like episodes in a playlist they are by the publisher, so artist filters see them
+            for episode in &mut episodes {
+                episode.artists.extend(show.publisher.clone());
+            }
+            Ok(episodes)
+        }
+    }
+}
+
+// Tracks of a playlist, only fetched again when its snapshot changed
+async fn playlist_tracks(
+    api: &impl SpotifyApi,
+    playlist: &Playlist,
+    market: Option<Country>,
+    cache: &mut PlaylistCache,
+) -> Result<Vec<Track>, SpotiAfkError> {
+    let wait = cached_wait(cache, &playlist.id);
+    let request = async {
+        match api.playlist_snapshot(&playlist.id).await {
+            Ok(snapshot_id) => tracks_at_snapshot(api, playlist, &snapshot_id, market, cache).await,
+            Err(e) => Err(SpotiAfkError::spotify("getting the playlist snapshot")(e)),
+        }
+    };
+    let fetched = within(
+        wait,
+        request,
+        SpotiAfkError::spotify("getting playlist tracks"),
+    )
+    .await;
+
+    // Older tracks are better than none when Spotify does not answer
+    match (fetched, cache.tracks(&playlist.id, None)) {
+        (Ok(tracks), _) => Ok(tracks),
+        (Err(e), Some(cached)) => {
+            log!("Using the cached tracks of {} ({})", playlist.name, e);
+            Ok(cached.tracks.clone())
+        }
+        (Err(e), None) => Err(e),
+    }
+}
+
+// Tracks of a playlist at the snapshot Spotify just gave, from the cache when it has them
+pub async fn tracks_at_snapshot(
+    api: &impl SpotifyApi,
+    playlist: &Playlist,
+    snapshot_id: &str,
+    market: Option<Country>,
+    cache: &mut PlaylistCache,
+) -> Result<Vec<Track>, SpotiAfkError> {
+    if let Some(cached) = cache.tracks(&playlist.id, Some(snapshot_id)) {
+        return Ok(cached.tracks.clone());
+    }
+    let tracks = get_tracks(api, &playlist.id, market).await?;
+    let playlist = Playlist {
+        snapshot_id: String::from(snapshot_id),
+        ..playlist.clone()
+    };
+    cache.store_tracks(&playlist, &tracks);
+    Ok(tracks)
+}
+
+// How long to wait for a playlist, only limited when the cache can stand in for it
+fn cached_wait(cache: &PlaylistCache, playlist_id: &str) -> Duration {
+    match cache.tracks(playlist_id, None) {
+        Some(_) => cache.wait(),
+        None => Duration::ZERO,
+    }
+}
+
+// Give up on a request after `wait`, with the error `timed_out` makes, zero waits for the answer
+async fn within<T, E>(
+    wait: Duration,
+    request: impl Future<Output = Result<T, E>>,
+    timed_out: impl FnOnce(ApiError) -> E,
+) -> Result<T, E> {
+    if wait.is_zero() {
+        return request.await;
+    }
+    match timeout(wait, request).await {
+        Ok(result) => result,
+        Err(_) => Err(timed_out(ApiError {
+            status: None,
+            message: format!("no answer within {}s", wait.as_secs()),
+            retry_after: None,
+        })),
+    }
+}
+
+// Request pages until the last one and put their items together
+async fn all_pages<T, F, Fut>(
+    limit: u32,
+    action: &'static str,
+    mut request: F,
+) -> Result<Vec<T>, SpotiAfkError>
+where
+    F: FnMut(u32) -> Fut,
+    Fut: Future<Output = Result<Page<T>, ApiError>>,
+{
+    let mut items = Vec::new();
+    let mut offset = 0;
+    loop {
+        let page = request(offset)
+            .await
+            .map_err(SpotiAfkError::spotify(action))?;
+        items.extend(page.items);
+        if !page.has_next {
+            return Ok(items);
+        }
+        offset += limit;
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::NameMatch, fake_spotify::FakeSpotify, spotify_api::TrackKind};
+
+    fn named(name: &str, matching: NameMatch) -> SourceSelector {
+        SourceSelector::PlaylistName {
+            name: String::from(name),
+            matching,
+        }
+    }
+
+    async fn get_playlist(
+        api: &FakeSpotify,
+        selector: &SourceSelector,
+    ) -> Result<Playlist, SpotiAfkError> {
+        match get_source(api, selector, None, &mut PlaylistCache::default()).await? {
+            TrackSource::Playlist(playlist) => Ok(playlist),
+            source => panic!("{} is not a playlist", source),
+        }
+    }
+
+    async fn count(api: &FakeSpotify, source: &TrackSource, cache: &mut PlaylistCache) -> usize {
+        get_source_tracks(api, source, None, cache)
+            .await
+            .unwrap()
+            .len()
+    }
+
+    async fn ids(api: &FakeSpotify, selector: SourceSelector) -> Vec<String> {
+        let mut cache = PlaylistCache::default();
+        let source = get_source(api, &selector, None, &mut cache).await.unwrap();
+        get_source_tracks(api, &source, None, &mut cache)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|track| track.id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn finds_playlist_on_a_later_page() {
+        let api = FakeSpotify::new()
+            .with_playlist("Chill", &["a"])
+            .with_playlist("Rock", &["b"])
+            .with_playlist("AFK", &["c", "d"]);
+
+        let playlist = get_playlist(&api, &named("AFK", NameMatch::Exact))
+            .await
+            .unwrap();
+        assert_eq!(playlist.id, "playlist-3");
+        assert_eq!(playlist.track_count, 2);
+    }
+
+    #[tokio::test]
+    async fn missing_playlist_is_an_error() {
+        let api = FakeSpotify::new().with_playlist("Chill", &["a"]);
+
+        let error = get_playlist(&api, &named("AFK", NameMatch::Exact))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, SpotiAfkError::SourceNotFound(name) if name == "AFK"));
+    }
+
+    #[tokio::test]
+    async fn failing_request_names_the_action() {
+        let api = FakeSpotify::new();
+        api.state().failing.push(("playlists", 500));
+
+        let error = get_playlist(&api, &named("AFK", NameMatch::Exact))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            SpotiAfkError::Spotify {
+                action: "getting playlists",
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn fetches_playlist_by_id_without_listing() {
+        let api = FakeSpotify::new()
+            .with_playlist("AFK", &["a"])
+            .with_playlist("AFK", &["b", "c"]);
+        api.state().failing.push(("playlists", 500));
+
+        let selector = SourceSelector::Playlist(String::from("playlist-2"));
+        let playlist = get_playlist(&api, &selector).await.unwrap();
+        assert_eq!(playlist.track_count, 2);
+    }
+
+    #[tokio::test]
+    async fn unknown_id_is_not_found() {
+        let api = FakeSpotify::new().with_playlist("AFK", &["a"]);
+
+        let selector = SourceSelector::Album(String::from("album-9"));
+        let error = get_source(&api, &selector, None, &mut PlaylistCache::default())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, SpotiAfkError::SourceNotFound(name) if name == "spotify:album:album-9")
+        );
+    }
+
+    #[tokio::test]
+    async fn shared_name_is_ambiguous() {
+        let api = FakeSpotify::new()
+            .with_playlist("AFK", &["a"])
+            .with_playlist("Chill", &["b"])
+            .with_playlist("AFK", &["c"]);
+
+        let error = get_playlist(&api, &named("AFK", NameMatch::Exact))
+            .await
+            .unwrap_err();
+        assert_eq!(error.exit_code(), 15);
+        assert!(matches!(
+            error,
+            SpotiAfkError::PlaylistAmbiguous { matches, .. }
+                if matches == ["playlist-1 (AFK)", "playlist-3 (AFK)"]
+        ));
+    }
+
+    #[tokio::test]
+    async fn matches_name_ignoring_case_or_by_regex() {
+        let api = FakeSpotify::new()
+            .with_playlist("Chill", &["a"])
+            .with_playlist("afk mix", &["b"]);
+
+        let playlist = get_playlist(&api, &named("AFK MIX", NameMatch::IgnoreCase))
+            .await
+            .unwrap();
+        assert_eq!(playlist.id, "playlist-2");
+
+        let regex = NameMatch::Regex(regex::Regex::new("^afk").unwrap());
+        let playlist = get_playlist(&api, &named("^afk", regex)).await.unwrap();
+        assert_eq!(playlist.id, "playlist-2");
+    }
+
+    #[tokio::test]
+    async fn gets_tracks_of_albums_and_artists() {
+        let api = FakeSpotify::new()
+            .with_album("First", &["a", "b", "c"])
+            .with_album("Second", &["d"])
+            .with_artist("Band", &["c", "a"], &["album-1", "album-2"]);
+
+        let album = ids(&api, SourceSelector::Album(String::from("album-1"))).await;
+        assert_eq!(album, ["a", "b", "c"]);
+
+        let top = ids(&api, SourceSelector::TopTracks(String::from("artist-1"))).await;
+        assert_eq!(top, ["c", "a"]);
+
+        let all = ids(&api, SourceSelector::Discography(String::from("artist-1"))).await;
+        assert_eq!(all, ["a", "b", "c", "d"]);
+    }
+
+    #[tokio::test]
+    async fn album_tracks_say_if_they_are_playable_in_the_market() {
+        let api = FakeSpotify::new()
+            .with_album("First", &["a", "b"])
+            .with_artist("Band", &[], &["album-1"]);
+        api.state().albums[0].1[1].playable = false;
+
+        for selector in [
+            SourceSelector::Album(String::from("album-1")),
+            SourceSelector::Discography(String::from("artist-1")),
+        ] {
+            let mut cache = PlaylistCache::default();
+            let market = Some(Country::Netherlands);
+            let source = get_source(&api, &selector, market, &mut cache)
+                .await
+                .unwrap();
+            let playable: Vec<bool> = get_source_tracks(&api, &source, market, &mut cache)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|track| track.playable)
+                .collect();
+            assert_eq!(playable, [true, false], "{}", selector);
+        }
+    }
+
+    #[tokio::test]
+    async fn gets_liked_songs_and_show_episodes() {
+        let api = FakeSpotify::new()
+            .with_saved(&["a", "b", "c"])
+            .with_show("Podcast", &["e1", "e2"]);
+
+        assert_eq!(ids(&api, SourceSelector::LikedSongs).await, ["a", "b", "c"]);
+
+        let mut cache = PlaylistCache::default();
+        let selector = SourceSelector::Show(String::from("show-1"));
+        let source = get_source(&api, &selector, None, &mut cache).await.unwrap();
+        let episodes = get_source_tracks(&api, &source, None, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(episodes.len(), 2);
+        assert!(episodes
+            .iter()
+            .all(|episode| episode.kind == TrackKind::Episode));
+    }
+
+    #[tokio::test]
+    async fn fetches_playlist_tracks_again_only_for_a_new_snapshot() {
+        let api = FakeSpotify::new().with_playlist("AFK", &["a", "b"]);
+        let mut cache = PlaylistCache::default();
+        let source = get_source(&api, &named("AFK", NameMatch::Exact), None, &mut cache)
+            .await
+            .unwrap();
+        assert_eq!(count(&api, &source, &mut cache).await, 2);
+
+        // Same snapshot, the tracks come from the cache
+        api.state().failing.push(("playlist_tracks", 500));
+        assert_eq!(count(&api, &source, &mut cache).await, 2);
+        assert_eq!(api.state().failing.len(), 1);
+
+        // A new snapshot is fetched, the cached tracks stand in while that fails
+        api.state().playlists[0].0.snapshot_id = String::from("snapshot-2");
+        api.state().playlists[0].1.pop();
+        assert_eq!(count(&api, &source, &mut cache).await, 2);
+        assert_eq!(count(&api, &source, &mut cache).await, 1);
+    }
+}
@@ -0,0 +1,627 @@
+/////////////
+// Imports //
+/////////////
+
+use async_trait::async_trait;
+use rspotify::{
+    http::{HttpError, Query},
+    model::{
+        AdditionalType, AlbumId, ArtistId, Country, EpisodeId, FullTrack, Market, PlayableItem,
+        PlaylistId, ShowId, SimplifiedTrack, TrackId,
+    },
+    prelude::*,
+    ClientError,
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, fmt, time::Duration};
+
+// Self made files
+use crate::auth::AuthClient;
+
+////////////
+// Traits //
+////////////
+
+// The Spotify Web API calls the program makes, so they can be faked in tests
+#[async_trait]
+pub trait SpotifyApi: Send + Sync {
+    // Items per page when paging through playlists and tracks
+    fn page_size(&self) -> u32;
+
+    // Country of the logged in user, used as market for tracks
+    async fn user_country(&self) -> Result<Option<Country>, ApiError>;
+
+    // A page of the playlists of the logged in user
+    async fn playlists(&self, limit: u32, offset: u32) -> Result<Page<Playlist>, ApiError>;
+
+    // Any playlist by its id, also ones the user does not follow
+    async fn playlist(&self, playlist_id: &str) -> Result<Playlist, ApiError>;
+
+    // Version of a playlist, it changes whenever its tracks change
+    async fn playlist_snapshot(&self, playlist_id: &str) -> Result<String, ApiError>;
+
+    // A page of the tracks of a playlist, tracks not playable in the market are left out
+    async fn playlist_tracks(
+        &self,
+        playlist_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError>;
+
+    // Album by its id
+    async fn album(&self, album_id: &str) -> Result<Collection, ApiError>;
+
+    // A page of the tracks of an album, only with a market it says which are playable
+    async fn album_tracks(
+        &self,
+        album_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError>;
+
+    // Artist by its id
+    async fn artist(&self, artist_id: &str) -> Result<Collection, ApiError>;
+
+    // The 10 most played tracks of an artist in the market
+    async fn artist_top_tracks(
+        &self,
+        artist_id: &str,
+        market: Option<Country>,
+    ) -> Result<Vec<Track>, ApiError>;
+
+    // A page of the albums and singles of an artist, without the ones they only appear on
+    async fn artist_albums(
+        &self,
+        artist_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Collection>, ApiError>;
+
+    // A page of the Liked Songs of the user
+    async fn saved_tracks(
+        &self,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError>;
+
+    // Podcast show by its id
+    async fn show(&self, show_id: &str, market: Option<Country>) -> Result<Collection, ApiError>;
+
+    // A page of the episodes of a show, newest first
+    async fn show_episodes(
+        &self,
+        show_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError>;
+
+    // Devices Spotify can play on
+    async fn devices(&self) -> Result<Vec<Device>, ApiError>;
+
+    // What the user is playing, None when nothing is loaded
+    async fn playing(&self) -> Result<Option<Playing>, ApiError>;
+
+    // Move playback to the device without starting it
+    async fn transfer_playback(&self, device_id: &str) -> Result<(), ApiError>;
+
+    // Start playing a track or episode on the device, in place of what played before
+    async fn start_playback(&self, track: &Track, device_id: &str) -> Result<(), ApiError>;
+
+    // Put a track or episode in the queue of the device
+    async fn add_to_queue(&self, track: &Track, device_id: &str) -> Result<(), ApiError>;
+
+    // Skip to the next track in the queue of the device
+    async fn next_track(&self, device_id: &str) -> Result<(), ApiError>;
+
+    // Pause playback on the device
+    async fn pause_playback(&self, device_id: &str) -> Result<(), ApiError>;
+
+    // Go on with the paused track on the device
+    async fn resume_playback(&self, device_id: &str) -> Result<(), ApiError>;
+}
+
+///////////
+// Types //
+///////////
+
+// One page of a paged response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub has_next: bool,
+}
+
+// Playlist of the user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Playlist {
+    pub id: String,
+    pub name: String,
+    pub track_count: u32,
+    pub snapshot_id: String,
+}
+
+// Album, artist or show that tracks come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Collection {
+    pub id: String,
+    pub name: String,
+
+    // Publisher of a show, its episodes come without one
+    pub publisher: Option<String>,
+}
+
+// Track or episode out of a playlist, album, library or show
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Track {
+    // None for local files and removed tracks, Spotify can not queue those
+    pub id: Option<String>,
+    pub name: String,
+    pub duration: Duration,
+    pub kind: TrackKind,
+    pub artists: Vec<String>,
+    pub explicit: bool,
+
+    // File on the computer of whoever added it to the playlist
+    pub local: bool,
+
+    // False when it can not be played in the market of the user
+    pub playable: bool,
+}
+
+// Spotify queues music and podcast episodes by different kinds of id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum TrackKind {
+    #[default]
+    Music,
+    Episode,
+}
+
+// Device Spotify can play on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub id: Option<String>,
+    pub name: String,
+    pub kind: String,
+    pub is_active: bool,
+}
+
+// Current playback of the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Playing {
+    pub is_playing: bool,
+
+    // Id of the track or episode on the device, None for local files and ads
+    pub track_id: Option<String>,
+}
+
+// A failed Spotify request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiError {
+    // HTTP status, None when the request did not get an answer
+    pub status: Option<u16>,
+    pub message: String,
+
+    // How long Spotify asked to wait before trying again
+    pub retry_after: Option<Duration>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+#[async_trait]
+impl<C: AuthClient> SpotifyApi for C {
+    fn page_size(&self) -> u32 {
+        self.get_config().pagination_chunks
+    }
+
+    async fn user_country(&self) -> Result<Option<Country>, ApiError> {
+        Ok(self.me().await?.country)
+    }
+
+    async fn playlists(&self, limit: u32, offset: u32) -> Result<Page<Playlist>, ApiError> {
+        let page = self
+            .current_user_playlists_manual(Some(limit), Some(offset))
+            .await?;
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(|playlist| Playlist {
+                    id: String::from(playlist.id.id()),
+                    name: playlist.name,
+                    track_count: playlist.tracks.total,
+                    snapshot_id: playlist.snapshot_id,
+                })
+                .collect(),
+            has_next: page.next.is_some(),
+        })
+    }
+
+    async fn playlist(&self, playlist_id: &str) -> Result<Playlist, ApiError> {
+        let playlist_id = PlaylistId::from_id(playlist_id).map_err(ApiError::invalid_id)?;
+        let playlist = BaseClient::playlist(self, &playlist_id, None, None).await?;
+        Ok(Playlist {
+            id: String::from(playlist.id.id()),
+            name: playlist.name,
+            track_count: playlist.tracks.total,
+            snapshot_id: playlist.snapshot_id,
+        })
+    }
+
+    async fn playlist_snapshot(&self, playlist_id: &str) -> Result<String, ApiError> {
+        // Asking for only the snapshot id leaves out the first page of tracks
+        #[derive(Deserialize)]
+        struct Snapshot {
+            snapshot_id: String,
+        }
+        let playlist_id = PlaylistId::from_id(playlist_id).map_err(ApiError::invalid_id)?;
+        let url = format!("playlists/{}", playlist_id.id());
+        let query = Query::from([("fields", "snapshot_id")]);
+        let body = self.endpoint_get(&url, &query).await?;
+        let snapshot: Snapshot = serde_json::from_str(&body).map_err(ClientError::from)?;
+        Ok(snapshot.snapshot_id)
+    }
+
+    async fn playlist_tracks(
+        &self,
+        playlist_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        let playlist_id = PlaylistId::from_id(playlist_id).map_err(ApiError::invalid_id)?;
+        let market = market.map(Market::Country);
+        let page = self
+            .playlist_items_manual(
+                &playlist_id,
+                None,
+                market.as_ref(),
+                Some(limit),
+                Some(offset),
+            )
+            .await?;
+
+        // Removed tracks come without a track, they are left out by the filters
+        let items = page
+            .items
+            .into_iter()
+            .map(|item| match item.track {
+                Some(PlayableItem::Track(track)) => full_track(track),
+                Some(PlayableItem::Episode(episode)) => Track {
+                    id: Some(String::from(episode.id.id())),
+                    name: episode.name,
+                    duration: episode.duration,
+                    kind: TrackKind::Episode,
+                    artists: vec![episode.show.publisher],
+                    explicit: episode.explicit,
+                    local: false,
+                    playable: episode.is_playable,
+                },
+                None => Track::default(),
+            })
+            .collect();
+        Ok(Page {
+            items,
+            has_next: page.next.is_some(),
+        })
+    }
+
+    async fn album(&self, album_id: &str) -> Result<Collection, ApiError> {
+        let album_id = AlbumId::from_id(album_id).map_err(ApiError::invalid_id)?;
+        let album = BaseClient::album(self, &album_id).await?;
+        Ok(Collection {
+            id: String::from(album.id.id()),
+            name: album.name,
+            publisher: None,
+        })
+    }
+
+    async fn album_tracks(
+        &self,
+        album_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        // rspotify does not send a market for the tracks of an album, so is_playable would be missing
+        let album_id = AlbumId::from_id(album_id).map_err(ApiError::invalid_id)?;
+        let url = format!("albums/{}/tracks", album_id.id());
+        let (limit, offset) = (limit.to_string(), offset.to_string());
+        let market = market.map(Market::Country);
+        let mut query = Query::from([("limit", limit.as_str()), ("offset", offset.as_str())]);
+        if let Some(market) = &market {
+            query.insert("market", market.as_ref());
+        }
+        let body = self.endpoint_get(&url, &query).await?;
+        let page: rspotify::model::Page<SimplifiedTrack> =
+            serde_json::from_str(&body).map_err(ClientError::from)?;
+        Ok(Page {
+            items: page.items.into_iter().map(simplified_track).collect(),
+            has_next: page.next.is_some(),
+        })
+    }
+
+    async fn artist(&self, artist_id: &str) -> Result<Collection, ApiError> {
+        let artist_id = ArtistId::from_id(artist_id).map_err(ApiError::invalid_id)?;
+        let artist = BaseClient::artist(self, &artist_id).await?;
+        Ok(Collection {
+            id: String::from(artist.id.id()),
+            name: artist.name,
+            publisher: None,
+        })
+    }
+
+    async fn artist_top_tracks(
+        &self,
+        artist_id: &str,
+        market: Option<Country>,
+    ) -> Result<Vec<Track>, ApiError> {
+        let artist_id = ArtistId::from_id(artist_id).map_err(ApiError::invalid_id)?;
+        // Top tracks differ per country, the market of the token is the user's
+        let market = market.map(Market::Country).unwrap_or(Market::FromToken);
+        let tracks = BaseClient::artist_top_tracks(self, &artist_id, &market).await?;
+        Ok(tracks.into_iter().map(full_track).collect())
+    }
+
+    async fn artist_albums(
+        &self,
+        artist_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Collection>, ApiError> {
+        let artist_id = ArtistId::from_id(artist_id).map_err(ApiError::invalid_id)?;
+        let market = market.map(Market::Country);
+        let page = self
+            .artist_albums_manual(&artist_id, None, market.as_ref(), Some(limit), Some(offset))
+            .await?;
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .filter(|album| album.album_group.as_deref() != Some("appears_on"))
+                .filter_map(|album| {
+                    Some(Collection {
+                        id: String::from(album.id?.id()),
+                        name: album.name,
+                        publisher: None,
+                    })
+                })
+                .collect(),
+            has_next: page.next.is_some(),
+        })
+    }
+
+    async fn saved_tracks(
+        &self,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        let market = market.map(Market::Country);
+        let page = self
+            .current_user_saved_tracks_manual(market.as_ref(), Some(limit), Some(offset))
+            .await?;
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(|saved| full_track(saved.track))
+                .collect(),
+            has_next: page.next.is_some(),
+        })
+    }
+
+    async fn show(&self, show_id: &str, market: Option<Country>) -> Result<Collection, ApiError> {
+        let show_id = ShowId::from_id(show_id).map_err(ApiError::invalid_id)?;
+        let market = market.map(Market::Country);
+        let show = self.get_a_show(&show_id, market.as_ref()).await?;
+        Ok(Collection {
+            id: String::from(show.id.id()),
+            name: show.name,
+            publisher: Some(show.publisher),
+        })
+    }
+
+    async fn show_episodes(
+        &self,
+        show_id: &str,
+        market: Option<Country>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Page<Track>, ApiError> {
+        let show_id = ShowId::from_id(show_id).map_err(ApiError::invalid_id)?;
+        let market = market.map(Market::Country);
+        let page = self
+            .get_shows_episodes_manual(&show_id, market.as_ref(), Some(limit), Some(offset))
+            .await?;
+        Ok(Page {
+            items: page
+                .items
+                .into_iter()
+                .map(|episode| Track {
+                    id: Some(String::from(episode.id.id())),
+                    name: episode.name,
+                    duration: episode.duration,
+                    kind: TrackKind::Episode,
+                    artists: Vec::new(),
+                    explicit: episode.explicit,
+                    local: false,
+                    playable: episode.is_playable,
+                })
+                .collect(),
+            has_next: page.next.is_some(),
+        })
+    }
+
+    async fn devices(&self) -> Result<Vec<Device>, ApiError> {
+        Ok(self
+            .device()
+            .await?
+            .into_iter()
+            .map(|device| Device {
+                id: device.id,
+                name: device.name,
+                kind: format!("{:?}", device._type),
+                is_active: device.is_active,
+            })
+            .collect())
+    }
+
+    async fn playing(&self) -> Result<Option<Playing>, ApiError> {
+        // Without asking for episodes Spotify leaves out the item while one plays
+        let types = [AdditionalType::Track, AdditionalType::Episode];
+        Ok(self
+            .current_playing(None, Some(&types))
+            .await?
+            .map(|playing| Playing {
+                is_playing: playing.is_playing,
+                track_id: match playing.item {
+                    Some(PlayableItem::Track(track)) => track.id.map(|id| String::from(id.id())),
+                    Some(PlayableItem::Episode(episode)) => Some(String::from(episode.id.id())),
+                    None => None,
+                },
+            }))
+    }
+
+    async fn transfer_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        Ok(OAuthClient::transfer_playback(self, device_id, Some(false)).await?)
+    }
+
+    async fn start_playback(&self, track: &Track, device_id: &str) -> Result<(), ApiError> {
+        let id = playable_id(track)?;
+        match track.kind {
+            TrackKind::Music => {
+                let id = TrackId::from_id(id).map_err(ApiError::invalid_id)?;
+                let uris: [&dyn PlayableId; 1] = [&id];
+                Ok(self
+                    .start_uris_playback(uris, Some(device_id), None, None)
+                    .await?)
+            }
+            TrackKind::Episode => {
+                let id = EpisodeId::from_id(id).map_err(ApiError::invalid_id)?;
+                let uris: [&dyn PlayableId; 1] = [&id];
+                Ok(self
+                    .start_uris_playback(uris, Some(device_id), None, None)
+                    .await?)
+            }
+        }
+    }
+
+    async fn add_to_queue(&self, track: &Track, device_id: &str) -> Result<(), ApiError> {
+        let id = playable_id(track)?;
+        match track.kind {
+            TrackKind::Music => {
+                let id = TrackId::from_id(id).map_err(ApiError::invalid_id)?;
+                Ok(self.add_item_to_queue(&id, Some(device_id)).await?)
+            }
+            TrackKind::Episode => {
+                let id = EpisodeId::from_id(id).map_err(ApiError::invalid_id)?;
+                Ok(self.add_item_to_queue(&id, Some(device_id)).await?)
+            }
+        }
+    }
+
+    async fn next_track(&self, device_id: &str) -> Result<(), ApiError> {
+        Ok(OAuthClient::next_track(self, Some(device_id)).await?)
+    }
+
+    async fn pause_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        Ok(OAuthClient::pause_playback(self, Some(device_id)).await?)
+    }
+
+    async fn resume_playback(&self, device_id: &str) -> Result<(), ApiError> {
+        Ok(OAuthClient::resume_playback(self, Some(device_id), None).await?)
+    }
+}
+
+impl ApiError {
+    // Error for an id Spotify would not accept
+    fn invalid_id(e: impl fmt::Display) -> Self {
+        ApiError {
+            status: None,
+            message: format!("invalid id ({})", e),
+            retry_after: None,
+        }
+    }
+}
+
+impl From<ClientError> for ApiError {
+    fn from(e: ClientError) -> Self {
+        let (status, retry_after) = match &e {
+            ClientError::Http(http_error) => match http_error.as_ref() {
+                HttpError::StatusCode(response) => (
+                    Some(response.status().as_u16()),
+                    // Spotify sends the seconds to wait along with a 429
+                    response
+                        .headers()
+                        .get("Retry-After")
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(|value| value.trim().parse().ok())
+                        .map(Duration::from_secs),
+                ),
+                _ => (None, None),
+            },
+            _ => (None, None),
+        };
+        ApiError {
+            status,
+            message: e.to_string(),
+            retry_after,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ApiError {}
+
+///////////////
+// Functions //
+///////////////
+
+// Track out of a full track, `is_playable` is only sent when asking for a market
+fn full_track(track: FullTrack) -> Track {
+    Track {
+        id: track.id.map(|id| String::from(id.id())),
+        name: track.name,
+        duration: track.duration,
+        kind: TrackKind::Music,
+        artists: track.artists.into_iter().map(|a| a.name).collect(),
+        explicit: track.explicit,
+        local: track.is_local,
+        playable: track.is_playable.unwrap_or(true),
+    }
+}
+
+// Track out of a track without album, like the tracks of an album
+fn simplified_track(track: SimplifiedTrack) -> Track {
+    Track {
+        id: track.id.map(|id| String::from(id.id())),
+        name: track.name,
+        duration: track.duration,
+        kind: TrackKind::Music,
+        artists: track.artists.into_iter().map(|a| a.name).collect(),
+        explicit: track.explicit,
+        local: track.is_local,
+        playable: track.is_playable.unwrap_or(true),
+    }
+}
+
+// Id Spotify can play the track by, local files and removed tracks have none
+fn playable_id(track: &Track) -> Result<&str, ApiError> {
+    track
+        .id
+        .as_deref()
+        .ok_or_else(|| ApiError::invalid_id("local file or removed track"))
+}
@@ -2,76 +2,75 @@
 // Imports //
 /////////////
 
-use std::{env, fs::File, io::prelude::*, path::Path, process::Command};
+use std::{fs::File, io::prelude::*, path::Path, process::Command};
 
-///////////////
-// Functions //
-///////////////
+// Self made files
+use crate::{config::SpotifydConfig, error::SpotiAfkError, log};
 
-// Check spotifyd settings
-pub fn init_spotifyd() -> Result<(), String> {
-    let mut found_settings = 0;
-    for (key, _) in env::vars() {
-        match key.as_str() {
-            "SPOTIFYD_USERNAME" => found_settings += 1,
-            "SPOTIFYD_PASSWORD" => found_settings += 1,
-            "SPOTIFYD_DEVICE_NAME" => found_settings += 1,
-            "SPOTIFYD_CONFIG_PATH" => found_settings += 1,
-            _ => {}
-        }
+///////////
+// Types //
+///////////
+
+// spotifyd started for a session, stopped again however the session ends
+pub struct SpotifydGuard<'a> {
+    config: &'a SpotifydConfig,
+    running: bool,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl<'a> SpotifydGuard<'a> {
+    // Make the config if needed and start spotifyd
+    pub fn start(config: &'a SpotifydConfig) -> Result<Self, SpotiAfkError> {
+        init_spotifyd(config)?;
+        Ok(SpotifydGuard {
+            config,
+            running: true,
+        })
     }
-    match found_settings {
-        4 => {}
-        _ => return Err(String::from("Failed parsing spotifyd settings")),
+
+    // Stop spotifyd at the end of the session, reporting when that fails
+    pub fn stop(mut self) -> Result<(), SpotiAfkError> {
+        self.running = false;
+        stop_spotifyd(self.config)
     }
+}
 
-    if !Path::new(env::var("SPOTIFYD_CONFIG_PATH").unwrap().as_str()).exists() {
-        match make_config() {
-            Ok(_) => (),
-            Err(_) => return Err(String::from("Failed to make spotifyd config file")),
+// The session failed before `stop`, stopping can only be logged on the way out
+impl Drop for SpotifydGuard<'_> {
+    fn drop(&mut self) {
+        if self.running {
+            if let Err(e) = stop_spotifyd(self.config) {
+                log!("Could not stop spotifyd, {}", e);
+            }
         }
     }
+}
 
-    match start_spotifyd() {
-        Ok(_) => (),
-        Err(e) => match e.as_str() {
-            "Failed to start spotifyd" => return Err(String::from("Failed to start spotifyd")),
-            _ => return Err(String::from("Unexpected exit_code")),
-        },
-    };
+///////////////
+// Functions //
+///////////////
 
-    Ok(())
+// Make spotifyd config if needed and start spotifyd
+pub fn init_spotifyd(config: &SpotifydConfig) -> Result<(), SpotiAfkError> {
+    if !config.config_path.exists() {
+        make_config(config).map_err(SpotiAfkError::SpotifydConfig)?;
+    }
+
+    start_spotifyd(config)
 }
 
 // TODO maybe make more settings by matching on SPOTIFYD_{SETTING_NAME} from .env file
-fn make_config() -> std::io::Result<()> {
-    let mut config_file = File::create(env::var("SPOTIFYD_CONFIG_PATH").unwrap().as_str())?;
+fn make_config(config: &SpotifydConfig) -> std::io::Result<()> {
+    let mut config_file = File::create(&config.config_path)?;
 
     config_file.write_all(b"[global]\n")?;
-    config_file.write_all(
-        &[
-            b"username = \"",
-            env::var("SPOTIFYD_USERNAME").unwrap().as_bytes(),
-            b"\"\n",
-        ]
-        .concat(),
-    )?;
-    config_file.write_all(
-        &[
-            b"password = \"",
-            env::var("SPOTIFYD_PASSWORD").unwrap().as_bytes(),
-            b"\"\n",
-        ]
-        .concat(),
-    )?;
-    config_file.write_all(
-        &[
-            b"device_name = \"",
-            env::var("SPOTIFYD_DEVICE_NAME").unwrap().as_bytes(),
-            b"\"\n",
-        ]
-        .concat(),
-    )?;
+    config_file.write_all(&[b"username = \"", config.username.as_bytes(), b"\"\n"].concat())?;
+    config_file.write_all(&[b"password = \"", config.password.as_bytes(), b"\"\n"].concat())?;
+    config_file
+        .write_all(&[b"device_name = \"", config.device_name.as_bytes(), b"\"\n"].concat())?;
 
     // TODO add to .env because they are needed otherwise spotifyd crashes
     config_file.write_all(b"backend = \"pulseaudio\"\n")?;
@@ -80,40 +79,70 @@ fn make_config() -> std::io::Result<()> {
     Ok(())
 }
 
-pub fn start_spotifyd() -> Result<(), String> {
+pub fn start_spotifyd(config: &SpotifydConfig) -> Result<(), SpotiAfkError> {
     match Command::new("spotifyd")
-        .args([
-            "--config-path",
-            env::var("SPOTIFYD_CONFIG_PATH").unwrap().as_str(),
-        ])
+        .arg("--config-path")
+        .arg(&config.config_path)
         .spawn()
     {
         Ok(_) => Ok(()),
-        Err(_) => Err(String::from("Failed to start spotifyd")),
+        Err(e) => Err(SpotiAfkError::SpotifydStart(e)),
     }
 }
 
-pub fn stop_spotifyd() -> Result<(), String> {
-    let pids = match Command::new("pgrep")
-        .args([
-            "-f",
-            format!(
-                "spotifyd --config-path {}",
-                env::var("SPOTIFYD_CONFIG_PATH").unwrap()
-            )
-            .as_str(),
-        ])
-        .output()
-    {
-        Ok(output) => String::from_utf8(output.stdout).unwrap(),
-        Err(_) => return Err(String::from("Failed getting pid of spotifyd")),
-    };
-    for pid in pids.lines() {
+pub fn stop_spotifyd(config: &SpotifydConfig) -> Result<(), SpotiAfkError> {
+    for pid in spotifyd_pids(config)? {
         let kill = Command::new("kill").arg(pid).spawn();
         match kill {
             Ok(_) => (),
-            Err(_) => return Err(String::from("Failed stopping spotifyd")),
+            Err(e) => return Err(SpotiAfkError::SpotifydStop(e)),
         }
     }
     Ok(())
 }
+
+// Get pids of the spotifyd started with this config
+pub fn spotifyd_pids(config: &SpotifydConfig) -> Result<Vec<String>, SpotiAfkError> {
+    // The whole command line has to match, not another config path that starts the same
+    match Command::new("pgrep")
+        .args(["-fx", command_pattern(&config.config_path).as_str()])
+        .output()
+    {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(String::from)
+            .collect()),
+        Err(e) => Err(SpotiAfkError::SpotifydPid(e)),
+    }
+}
+
+// Command line start_spotifyd runs, as a pattern that matches the config path literally
+fn command_pattern(config_path: &Path) -> String {
+    // spotifyd may show up by its full path, or behind an interpreter when it is a script
+    let command = format!("spotifyd --config-path {}", config_path.display());
+    let mut pattern = String::from("(.*/)?");
+    for c in command.chars() {
+        if r"\^$.|?*+()[]{}".contains(c) {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern_matches_the_config_path_literally() {
+        assert_eq!(
+            command_pattern(Path::new("/home/me/.spotifyd (alice).conf")),
+            r"(.*/)?spotifyd --config-path /home/me/\.spotifyd \(alice\)\.conf"
+        );
+    }
+}
@@ -0,0 +1,293 @@
+/////////////
+// Imports //
+/////////////
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use rspotify::Token;
+use serde::{Deserialize, Serialize};
+use std::{env, fs, io, path::PathBuf};
+
+// Self made files
+use crate::{
+    config::{SpotifyConfig, TokenEncryption},
+    error::SpotiAfkError,
+    files::write_private,
+};
+
+///////////////
+// Constants //
+///////////////
+
+// Key derivation and cipher of the encrypted cache, stored so it can change later
+const CIPHER: &str = "argon2id-chacha20poly1305";
+
+///////////
+// Types //
+///////////
+
+// Token cache at RSPOTIFY_CLIENT_CACHE_PATH, encrypted when TOKEN_ENCRYPTION is set
+#[derive(Clone)]
+pub struct TokenCache {
+    path: PathBuf,
+    enabled: bool,
+    passphrase: Option<String>,
+}
+
+// What an encrypted cache file looks like
+#[derive(Serialize, Deserialize)]
+struct EncryptedToken {
+    cipher: String,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl TokenCache {
+    // Get the passphrase once, so the prompt is only shown a single time
+    pub fn new(config: &SpotifyConfig) -> Result<Self, SpotiAfkError> {
+        let path = config.cache_path.clone();
+        let error = |reason: String| SpotiAfkError::TokenCache {
+            path: config.cache_path.clone(),
+            reason,
+        };
+
+        let passphrase = match (config.token_cached, &config.token_encryption) {
+            (false, _) | (true, TokenEncryption::Disabled) => None,
+            (true, TokenEncryption::Env) => Some(
+                env::var("TOKEN_KEY").map_err(|_| error(String::from("TOKEN_KEY is not set")))?,
+            ),
+            (true, TokenEncryption::File(key_file)) => Some(
+                fs::read_to_string(key_file)
+                    .map_err(|e| error(format!("can not read {} ({})", key_file.display(), e)))?
+                    .trim_end()
+                    .to_string(),
+            ),
+            (true, TokenEncryption::Prompt) => Some(
+                rpassword::prompt_password("Token cache passphrase: ")
+                    .map_err(|e| error(format!("can not read the passphrase ({})", e)))?,
+            ),
+        };
+        if passphrase.as_deref() == Some("") {
+            return Err(error(String::from("the passphrase is empty")));
+        }
+
+        Ok(TokenCache {
+            path,
+            enabled: config.token_cached,
+            passphrase,
+        })
+    }
+
+    // Read the cached token, None when there is no usable cache
+    pub fn read(&self) -> Result<Option<Token>, SpotiAfkError> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(self.error(e.to_string())),
+        };
+
+        // A plain token is upgraded to an encrypted one on the next write
+        match (
+            serde_json::from_str::<EncryptedToken>(&contents),
+            &self.passphrase,
+        ) {
+            (Ok(encrypted), Some(passphrase)) => self.decrypt(&encrypted, passphrase).map(Some),
+            (Ok(_), None) => Err(self.error(String::from(
+                "the token is encrypted, set TOKEN_ENCRYPTION to read it",
+            ))),
+            (Err(_), _) => Ok(serde_json::from_str(&contents).ok()),
+        }
+    }
+
+    // Write the token, only readable by the owner
+    pub fn write(&self, token: &Token) -> Result<(), SpotiAfkError> {
+        if !self.enabled {
+            return Ok(());
+        }
+        let json = serde_json::to_string(token).map_err(|e| self.error(e.to_string()))?;
+        let contents = match &self.passphrase {
+            Some(passphrase) => serde_json::to_string(&self.encrypt(&json, passphrase)?)
+                .map_err(|e| self.error(e.to_string()))?,
+            None => json,
+        };
+
+        write_private(&self.path, contents.as_bytes()).map_err(|e| self.error(e.to_string()))
+    }
+
+    // Encrypt the token JSON with a key derived from the passphrase
+    fn encrypt(&self, json: &str, passphrase: &str) -> Result<EncryptedToken, SpotiAfkError> {
+        let mut salt = [0; 16];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = self.cipher(passphrase, &salt)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, json.as_bytes())
+            .map_err(|_| self.error(String::from("encrypting the token failed")))?;
+
+        Ok(EncryptedToken {
+            cipher: String::from(CIPHER),
+            salt: BASE64.encode(salt),
+            nonce: BASE64.encode(nonce),
+            ciphertext: BASE64.encode(ciphertext),
+        })
+    }
+
+    // Decrypt a token written by `encrypt`
+    fn decrypt(
+        &self,
+        encrypted: &EncryptedToken,
+        passphrase: &str,
+    ) -> Result<Token, SpotiAfkError> {
+        if encrypted.cipher != CIPHER {
+            return Err(self.error(format!("unknown cipher {}", encrypted.cipher)));
+        }
+        let damaged = || {
+            self.error(String::from(
+                "the cache is damaged, delete it to log in again",
+            ))
+        };
+        let decode = |value: &str| BASE64.decode(value).map_err(|_| damaged());
+        let salt = decode(&encrypted.salt)?;
+        let nonce = decode(&encrypted.nonce)?;
+        let ciphertext = decode(&encrypted.ciphertext)?;
+        if nonce.len() != 12 {
+            return Err(damaged());
+        }
+
+        let json = self
+            .cipher(passphrase, &salt)?
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| {
+                self.error(String::from(
+                    "can not decrypt the token, is the passphrase right? Delete the cache to log in again",
+                ))
+            })?;
+        serde_json::from_slice(&json).map_err(|e| self.error(e.to_string()))
+    }
+
+    // Cipher keyed by the passphrase and salt
+    fn cipher(&self, passphrase: &str, salt: &[u8]) -> Result<ChaCha20Poly1305, SpotiAfkError> {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| self.error(format!("deriving the key failed ({})", e)))?;
+        Ok(ChaCha20Poly1305::new(&key))
+    }
+
+    // Error about this cache file
+    fn error(&self, reason: String) -> SpotiAfkError {
+        SpotiAfkError::TokenCache {
+            path: self.path.clone(),
+            reason,
+        }
+    }
+}
+
+///////////
+// Tests //
+///////////
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::PermissionsExt, path::Path};
+
+    fn cache(path: &Path, passphrase: Option<&str>) -> TokenCache {
+        TokenCache {
+            path: path.to_path_buf(),
+            enabled: true,
+            passphrase: passphrase.map(String::from),
+        }
+    }
+
+    fn token() -> Token {
+        Token {
+            access_token: String::from("access"),
+            refresh_token: Some(String::from("refresh")),
+            ..Token::default()
+        }
+    }
+
+    #[test]
+    fn reads_back_the_encrypted_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        let cache = cache(&path, Some("passphrase"));
+        cache.write(&token()).unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.contains(CIPHER));
+        assert!(!contents.contains("access") && !contents.contains("refresh"));
+
+        let read = cache.read().unwrap().unwrap();
+        assert_eq!(read.access_token, "access");
+        assert_eq!(read.refresh_token.as_deref(), Some("refresh"));
+    }
+
+    #[test]
+    fn fails_with_the_wrong_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        cache(&path, Some("passphrase")).write(&token()).unwrap();
+
+        match cache(&path, Some("wrong")).read() {
+            Err(SpotiAfkError::TokenCache {
+                path: error_path,
+                reason,
+            }) => {
+                assert_eq!(error_path, path);
+                assert!(
+                    reason.starts_with("can not decrypt the token"),
+                    "{}",
+                    reason
+                );
+            }
+            other => panic!("expected a token cache error, got {:?}", other.map(|_| ())),
+        }
+        assert!(matches!(
+            cache(&path, None).read(),
+            Err(SpotiAfkError::TokenCache { .. })
+        ));
+    }
+
+    #[test]
+    fn encrypts_a_plain_cache_on_the_next_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        cache(&path, None).write(&token()).unwrap();
+        assert!(fs::read_to_string(&path).unwrap().contains("access"));
+
+        let cache = cache(&path, Some("passphrase"));
+        let read = cache.read().unwrap().unwrap();
+        assert_eq!(read.access_token, "access");
+
+        cache.write(&read).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(serde_json::from_str::<EncryptedToken>(&contents).is_ok());
+        assert_eq!(cache.read().unwrap().unwrap().access_token, "access");
+    }
+
+    #[test]
+    fn only_the_owner_can_read_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token.json");
+        fs::write(&path, "{}").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+        cache(&path, None).write(&token()).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
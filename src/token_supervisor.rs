@@ -0,0 +1,174 @@
+/////////////
+// Imports //
+/////////////
+
+use chrono::Utc;
+use std::time::Duration;
+use tokio::{sync::watch, task::JoinHandle, time::sleep};
+
+// Self made files
+use crate::{
+    auth::{login, refresh_rejected, refresh_token, store_token, AuthClient},
+    config::AppConfig,
+    error::SpotiAfkError,
+    log, logging,
+    token_cache::TokenCache,
+};
+
+///////////////
+// Constants //
+///////////////
+
+// Refresh this long before the token expires
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+// First wait after a failed refresh, doubled after every failure up to RETRY_MAX
+const RETRY_MIN: Duration = Duration::from_secs(15);
+const RETRY_MAX: Duration = Duration::from_secs(5 * 60);
+
+///////////
+// Types //
+///////////
+
+// State of the login during a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TokenState {
+    // The token is valid and refreshed before it expires
+    Valid,
+
+    // Refreshing failed for a reason that can pass, like the network being down
+    Retrying(String),
+
+    // The user is logging in again
+    Reauthorizing,
+
+    // The refresh token stopped working and AUTH_REAUTH is off or the login failed
+    ReauthRequired(String),
+}
+
+// Background task keeping the token of a client fresh
+pub struct TokenSupervisor {
+    state: watch::Receiver<TokenState>,
+    task: JoinHandle<()>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl TokenSupervisor {
+    // Start refreshing the token of the client, clients share their token with their clones
+    pub fn spawn<C: AuthClient>(client: &C, cache: TokenCache, config: &AppConfig) -> Self {
+        let (sender, state) = watch::channel(TokenState::Valid);
+        let task = logging::spawn(supervise(client.clone(), cache, config.clone(), sender));
+        TokenSupervisor { state, task }
+    }
+
+    // Wait while the user logs in again, fail when the login can not be recovered
+    pub async fn authorized(&mut self) -> Result<(), SpotiAfkError> {
+        loop {
+            let state = self.state.borrow_and_update().clone();
+            match state {
+                TokenState::Valid | TokenState::Retrying(_) => return Ok(()),
+                TokenState::ReauthRequired(reason) => {
+                    return Err(SpotiAfkError::ReauthRequired(reason))
+                }
+                TokenState::Reauthorizing => {
+                    if self.state.changed().await.is_err() {
+                        return Err(SpotiAfkError::ReauthRequired(String::from(
+                            "the token supervisor stopped",
+                        )));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for TokenSupervisor {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Refresh ahead of expiry, retry passing failures and log in again when allowed
+async fn supervise<C: AuthClient>(
+    mut client: C,
+    cache: TokenCache,
+    config: AppConfig,
+    state: watch::Sender<TokenState>,
+) {
+    // Without refreshing the token just runs out
+    if !config.spotify.token_refreshing {
+        return;
+    }
+
+    let mut retry_delay = RETRY_MIN;
+    loop {
+        let token = match client.get_token().lock().await.unwrap().clone() {
+            Some(token) => token,
+            None => {
+                state.send_replace(TokenState::ReauthRequired(String::from("no token")));
+                return;
+            }
+        };
+
+        // Sleep until shortly before the token expires
+        if let Some(expires_at) = token.expires_at {
+            let valid_for = (expires_at - Utc::now()).to_std().unwrap_or_default();
+            sleep(valid_for.saturating_sub(REFRESH_MARGIN)).await;
+        }
+
+        match refresh_token(&config, &token).await {
+            Ok(new_token) => {
+                // A cache that can not be written should not end the session
+                if let Err(e) = cache.write(&new_token) {
+                    log!("{}, the new token is only kept in memory", e);
+                }
+                *client.get_token().lock().await.unwrap() = Some(new_token);
+                state.send_replace(TokenState::Valid);
+                retry_delay = RETRY_MIN;
+            }
+            Err(e) if refresh_rejected(&e) => match config.auth.reauth {
+                true => {
+                    log!(
+                        "Spotify refused to refresh the login ({}), please log in again",
+                        e
+                    );
+                    state.send_replace(TokenState::Reauthorizing);
+                    let logged_in = match login(&mut client, &config).await {
+                        Ok(token) => store_token(&client, &cache, token).await,
+                        Err(e) => Err(e),
+                    };
+                    match logged_in {
+                        Ok(_) => {
+                            state.send_replace(TokenState::Valid);
+                        }
+                        Err(e) => {
+                            state.send_replace(TokenState::ReauthRequired(e.to_string()));
+                            return;
+                        }
+                    }
+                }
+                false => {
+                    state.send_replace(TokenState::ReauthRequired(e.to_string()));
+                    return;
+                }
+            },
+            Err(e) => {
+                log!(
+                    "Refreshing the Spotify login failed ({}), trying again in {}s",
+                    e,
+                    retry_delay.as_secs()
+                );
+                state.send_replace(TokenState::Retrying(e.to_string()));
+                sleep(retry_delay).await;
+                retry_delay = (retry_delay * 2).min(RETRY_MAX);
+            }
+        }
+    }
+}
@@ -0,0 +1,864 @@
+/////////////
+// Imports //
+/////////////
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{HeaderMap, Method, StatusCode, Uri},
+    response::Response,
+    Router,
+};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    fs,
+    hash::{Hash, Hasher},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Output, Stdio},
+    sync::{Arc, Mutex, MutexGuard},
+    time,
+};
+use tempfile::TempDir;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    process::{Child, Command},
+    time::timeout,
+};
+use url::Url;
+
+///////////////
+// Constants //
+///////////////
+
+// Longest a run of the program may take before the test fails
+const RUN_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+
+// Device name the program looks for, see SPOTIFYD_DEVICE_NAME
+pub const DEVICE_NAME: &str = "AFK_DEVICE";
+
+// Scopes the program asks for, the server grants them all
+const SCOPE: &str = "user-modify-playback-state playlist-read-private user-read-playback-state \
+                     user-library-read user-read-private";
+
+// Stands in for spotifyd, detaches from the output and keeps running until killed like the real one
+const FAKE_SPOTIFYD: &str = "#!/bin/sh\nexec >/dev/null 2>&1\nwhile true; do sleep 1; done\n";
+
+///////////
+// Types //
+///////////
+
+// Local server imitating the Spotify Web API and token endpoint
+pub struct MockSpotify {
+    pub url: String,
+    state: Arc<Mutex<MockState>>,
+}
+
+// Everything the server knows, tests can change it while the program runs
+#[derive(Default)]
+pub struct MockState {
+    pub playlists: Vec<MockPlaylist>,
+    pub devices: Vec<MockDevice>,
+
+    // Liked Songs by track id
+    pub saved: Vec<String>,
+
+    // Podcast shows, their tracks are episodes
+    pub shows: Vec<MockPlaylist>,
+
+    pub is_playing: bool,
+
+    // Country of the user, Spotify leaves it out of the profile without user-read-private
+    pub country: Option<String>,
+
+    // Uri of the track on the device and of the ones queued after it
+    pub current: Option<String>,
+    pub up_next: VecDeque<String>,
+
+    // Tokens the server accepts, requests with another access token get a 401,
+    // access tokens handed out before stay valid like they do on Spotify
+    pub access_token: String,
+    pub refresh_token: String,
+    pub code: String,
+    tokens_issued: u32,
+    old_tokens: Vec<String>,
+
+    // Access token of every accepted API request, in order
+    pub tokens_used: Vec<String>,
+
+    // Requests made, like "GET /v1/me/playlists?limit=2&offset=0"
+    pub requests: Vec<String>,
+
+    // Track ids started or queued, in the order the program sent them
+    pub queued: Vec<String>,
+
+    // Another device starts playing after this many started or queued tracks
+    pub take_over_after: Option<usize>,
+
+    // Responses the next requests to a path fail with
+    pub failures: Vec<Failure>,
+
+    // Paths that only answer after a while
+    pub delays: Vec<(&'static str, time::Duration)>,
+}
+
+pub struct MockPlaylist {
+    pub id: String,
+    pub name: String,
+    pub tracks: Vec<String>,
+}
+
+pub struct MockDevice {
+    pub id: String,
+    pub name: String,
+    pub is_active: bool,
+}
+
+// Fail the next `times` requests to `path` with `status`
+pub struct Failure {
+    pub path: &'static str,
+    pub status: u16,
+    pub times: usize,
+    pub retry_after: Option<u64>,
+}
+
+// The program with settings pointing at the mock server, in its own directory
+pub struct SpotiAfk {
+    dir: TempDir,
+    envs: Vec<(String, String)>,
+}
+
+/////////////////////
+// Implementations //
+/////////////////////
+
+impl MockSpotify {
+    // Start the server with a playlist, our device and a phone
+    pub async fn start() -> MockSpotify {
+        let state = Arc::new(Mutex::new(MockState {
+            playlists: vec![MockPlaylist::new("playlist1", "AFK", 3)],
+            devices: vec![
+                MockDevice::new("device1", DEVICE_NAME),
+                MockDevice::new("device2", "Phone"),
+            ],
+            access_token: String::from("access-1"),
+            refresh_token: String::from("refresh-1"),
+            code: String::from("code-1"),
+            country: Some(String::from("NL")),
+            ..Default::default()
+        }));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let app = Router::new().fallback(handle).with_state(state.clone());
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        MockSpotify { url, state }
+    }
+
+    pub fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    // Let another device take over once `count` requests started with `prefix`,
+    // a moment later so the program can handle the last answer first
+    pub async fn take_over_after_requests(&self, prefix: &str, count: usize) {
+        self.wait_for_requests(prefix, count).await;
+        tokio::time::sleep(time::Duration::from_millis(200)).await;
+        self.state().take_over();
+    }
+
+    // Let the other device stop once `count` requests started with `prefix`,
+    // returns how many there were by then
+    pub async fn stop_playing_after_requests(&self, prefix: &str, count: usize) -> usize {
+        self.wait_for_requests(prefix, count).await;
+        let mut state = self.state();
+        state.stop_playing();
+        state
+            .requests
+            .iter()
+            .filter(|request| request.starts_with(prefix))
+            .count()
+    }
+
+    async fn wait_for_requests(&self, prefix: &str, count: usize) {
+        timeout(RUN_TIMEOUT, async {
+            while self.requests(prefix).len() < count {
+                tokio::time::sleep(time::Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("the requests were not made in time");
+    }
+
+    // Requests made to paths starting with `prefix`
+    pub fn requests(&self, prefix: &str) -> Vec<String> {
+        self.state()
+            .requests
+            .iter()
+            .filter(|request| request.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+impl MockPlaylist {
+    // Playlist with tracks named after the playlist, like "playlist1track1"
+    pub fn new(id: &str, name: &str, tracks: usize) -> Self {
+        MockPlaylist {
+            id: String::from(id),
+            name: String::from(name),
+            tracks: (1..=tracks).map(|i| format!("{}track{}", id, i)).collect(),
+        }
+    }
+}
+
+impl MockDevice {
+    pub fn new(id: &str, name: &str) -> Self {
+        MockDevice {
+            id: String::from(id),
+            name: String::from(name),
+            is_active: false,
+        }
+    }
+}
+
+impl MockState {
+    // Make another device play, like someone picking up their phone
+    pub fn take_over(&mut self) {
+        for device in &mut self.devices {
+            device.is_active = device.name != DEVICE_NAME;
+        }
+        self.is_playing = true;
+    }
+
+    // Stop the other device, nothing plays anymore
+    pub fn stop_playing(&mut self) {
+        for device in &mut self.devices {
+            device.is_active = false;
+        }
+        self.is_playing = false;
+    }
+
+    // Remember a started or queued track, the other device may take over after it
+    fn play(&mut self, uri: &str) {
+        let id = uri.trim_start_matches("spotify:track:");
+        self.queued.push(String::from(id));
+        if Some(self.queued.len()) == self.take_over_after {
+            self.take_over();
+        }
+    }
+
+    fn issue_token(&mut self) -> Value {
+        self.tokens_issued += 1;
+        self.old_tokens.push(self.access_token.clone());
+        self.access_token = format!("access-{}", self.tokens_issued + 1);
+        json!({
+            "access_token": self.access_token,
+            "token_type": "Bearer",
+            "expires_in": 3600,
+            "refresh_token": self.refresh_token,
+            "scope": SCOPE,
+        })
+    }
+}
+
+impl SpotiAfk {
+    // Settings for a fast run against the server, with a fake spotifyd on the PATH
+    pub fn new(mock: &MockSpotify) -> SpotiAfk {
+        let dir = tempfile::tempdir().unwrap();
+        let spotifyd = dir.path().join("spotifyd");
+        fs::write(&spotifyd, FAKE_SPOTIFYD).unwrap();
+        fs::set_permissions(&spotifyd, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = format!(
+            "{}:{}",
+            dir.path().display(),
+            std::env::var("PATH").unwrap_or_default()
+        );
+        let path_of = |name: &str| dir.path().join(name).display().to_string();
+        let envs = [
+            ("PATH", path),
+            ("HOME", dir.path().display().to_string()),
+            ("RSPOTIFY_CLIENT_ID", String::from("client")),
+            ("RSPOTIFY_CLIENT_SECRET", String::from("secret")),
+            (
+                "RSPOTIFY_REDIRECT_URI",
+                String::from("http://localhost:8888/"),
+            ),
+            ("RSPOTIFY_CLIENT_PREFIX", format!("{}/v1/", mock.url)),
+            ("RSPOTIFY_CLIENT_CACHE_PATH", path_of("token.json")),
+            ("RSPOTIFY_CLIENT_PAGINATION_CHUNKS", String::from("2")),
+            ("PLAYLIST_NAME", String::from("AFK")),
+            ("CHECKS_BEFORE_PLAYING", String::from("1")),
+            ("TIME_BETWEEN_CHECKS", String::from("0")),
+            ("SKIP_TRACKS", String::from("true")),
+            ("WAIT_TILL_SKIP", String::from("0")),
+            ("SPOTIFYD_CONFIG_PATH", path_of("spotifyd.conf")),
+            ("SPOTIFYD_USERNAME", String::from("user")),
+            ("SPOTIFYD_PASSWORD", String::from("password")),
+            ("SPOTIFYD_DEVICE_NAME", String::from(DEVICE_NAME)),
+            // Runs end once the other device of the mock takes over
+            ("STOP_WHEN_TAKEN_OVER", String::from("true")),
+            ("SHORTENER_BACKEND", String::from("none")),
+            ("AUTH_TOKEN_URL", format!("{}/api/token", mock.url)),
+            ("AUTH_HEADLESS", String::from("true")),
+            ("AUTH_LISTENER", String::from("false")),
+        ]
+        .into_iter()
+        .map(|(key, value)| (String::from(key), value))
+        .collect();
+
+        SpotiAfk { dir, envs }
+    }
+
+    // Change a setting
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.envs.retain(|(k, _)| k != key);
+        self.envs.push((String::from(key), String::from(value)));
+        self
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    // Cache a token like an earlier login did, negative `expires_in` for an expired one
+    pub fn cache_token(&self, access_token: &str, refresh_token: &str, expires_in: i64) {
+        self.cache_token_with_scope(access_token, refresh_token, expires_in, SCOPE);
+    }
+
+    // Token of a login that was given these scopes
+    pub fn cache_token_with_scope(
+        &self,
+        access_token: &str,
+        refresh_token: &str,
+        expires_in: i64,
+        scope: &str,
+    ) {
+        let token = json!({
+            "access_token": access_token,
+            "expires_in": expires_in.max(0),
+            "expires_at": Utc::now() + Duration::seconds(expires_in),
+            "refresh_token": refresh_token,
+            "scope": scope,
+        });
+        fs::write(self.path("token.json"), token.to_string()).unwrap();
+    }
+
+    // Run the program to the end
+    pub async fn run(&self, args: &[&str]) -> Output {
+        timeout(RUN_TIMEOUT, self.spawn(args).wait_with_output())
+            .await
+            .expect("spoti_afk did not finish in time")
+            .unwrap()
+    }
+
+    // Run the program and log in by pasting the url Spotify would redirect to
+    pub async fn login(&self, args: &[&str], code: &str) -> Output {
+        let mut child = self.spawn(args);
+        let mut stdin = child.stdin.take().unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+
+        let mut stdout = String::new();
+        timeout(RUN_TIMEOUT, async {
+            while let Some(line) = lines.next_line().await.unwrap() {
+                // Headless logins print the url, others open it in the BROWSER
+                let url = match line.split_once("open this url on another device: ") {
+                    Some((_, url)) => Some(url),
+                    None => line
+                        .split_once("Opened ")
+                        .and_then(|(_, rest)| rest.strip_suffix(" in your browser.")),
+                };
+                if let Some(url) = url {
+                    let url = Url::parse(url).unwrap();
+                    let state = url
+                        .query_pairs()
+                        .find(|(key, _)| key == "state")
+                        .map(|(_, state)| state.into_owned())
+                        .unwrap_or_default();
+                    let redirect =
+                        format!("http://localhost:8888/?code={}&state={}\n", code, state);
+                    stdin.write_all(redirect.as_bytes()).await.unwrap();
+                }
+                stdout.push_str(&line);
+                stdout.push('\n');
+            }
+        })
+        .await
+        .expect("spoti_afk did not finish in time");
+
+        let mut output = child.wait_with_output().await.unwrap();
+        output.stdout = stdout.into_bytes();
+        output
+    }
+
+    // Start the program with piped input and output
+    fn spawn(&self, args: &[&str]) -> Child {
+        Command::new(env!("CARGO_BIN_EXE_spoti_afk"))
+            .args(args)
+            .current_dir(self.dir.path())
+            .env_clear()
+            .envs(self.envs.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap()
+    }
+
+    // Check if the fake spotifyd of this run is still running
+    pub fn spotifyd_running(&self) -> bool {
+        std::process::Command::new("pgrep")
+            .args(["-f", &self.spotifyd_pattern()])
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    // Command line of the fake spotifyd, like spotifyd::spotifyd_pids looks for it
+    fn spotifyd_pattern(&self) -> String {
+        format!(
+            "spotifyd --config-path {}",
+            self.path("spotifyd.conf").display()
+        )
+    }
+}
+
+// A failed run leaves its spotifyd running
+impl Drop for SpotiAfk {
+    fn drop(&mut self) {
+        let _ = std::process::Command::new("pkill")
+            .args(["-f", &self.spotifyd_pattern()])
+            .status();
+    }
+}
+
+///////////////
+// Functions //
+///////////////
+
+// Answer a request like Spotify would
+async fn handle(
+    State(state): State<Arc<Mutex<MockState>>>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+) -> Response {
+    // Slow paths wait without holding up the requests to other paths
+    let delay = state
+        .lock()
+        .unwrap()
+        .delays
+        .iter()
+        .find(|(path, _)| *path == uri.path())
+        .map(|(_, delay)| *delay);
+    if let Some(delay) = delay {
+        tokio::time::sleep(delay).await;
+    }
+
+    let mut state = state.lock().unwrap();
+    let path = uri.path();
+    let query: HashMap<String, String> =
+        url::form_urlencoded::parse(uri.query().unwrap_or("").as_bytes())
+            .into_owned()
+            .collect();
+    let mut pairs: Vec<_> = query.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+    pairs.sort();
+    state.requests.push(match pairs.is_empty() {
+        true => format!("{} {}", method, path),
+        false => format!("{} {}?{}", method, path, pairs.join("&")),
+    });
+
+    // Injected failures come first
+    if let Some(failure) = state
+        .failures
+        .iter_mut()
+        .find(|failure| failure.times > 0 && failure.path == path)
+    {
+        failure.times -= 1;
+        let mut response = respond(
+            failure.status,
+            json!({ "error": { "status": failure.status, "message": "Mocked failure" } }),
+        );
+        if let Some(seconds) = failure.retry_after {
+            response
+                .headers_mut()
+                .insert("Retry-After", seconds.to_string().parse().unwrap());
+        }
+        return response;
+    }
+
+    if path == "/api/token" {
+        return token(&mut state, &body);
+    }
+
+    // Everything else needs the current access token
+    let authorization = headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("");
+    let token = authorization.trim_start_matches("Bearer ");
+    if token != state.access_token && !state.old_tokens.iter().any(|old| old == token) {
+        return respond(
+            401,
+            json!({ "error": { "status": 401, "message": "Invalid access token" } }),
+        );
+    }
+    let token = String::from(token);
+    state.tokens_used.push(token);
+
+    let segments: Vec<&str> = path
+        .trim_start_matches("/v1/")
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match (method, segments.as_slice()) {
+        (Method::GET, ["me"]) => respond(200, user(state.country.as_deref())),
+        (Method::GET, ["me", "playlists"]) => {
+            let playlists = state.playlists.iter().map(playlist).collect();
+            respond(200, page(playlists, &query))
+        }
+        (Method::GET, ["playlists", id]) => {
+            match state.playlists.iter().find(|playlist| playlist.id == *id) {
+                Some(playlist)
+                    if query.get("fields").map(String::as_str) == Some("snapshot_id") =>
+                {
+                    respond(200, json!({ "snapshot_id": snapshot(playlist) }))
+                }
+                Some(playlist) => respond(200, full_playlist(playlist, &query)),
+                None => respond(
+                    404,
+                    json!({ "error": { "status": 404, "message": "Not found." } }),
+                ),
+            }
+        }
+        (Method::GET, ["playlists", id, "tracks"]) => {
+            match state.playlists.iter().find(|playlist| playlist.id == *id) {
+                Some(playlist) => {
+                    let items = playlist.tracks.iter().map(|id| playlist_item(id)).collect();
+                    respond(200, page(items, &query))
+                }
+                None => respond(
+                    404,
+                    json!({ "error": { "status": 404, "message": "Not found." } }),
+                ),
+            }
+        }
+        (Method::GET, ["me", "tracks"]) => {
+            let items = state.saved.iter().map(|id| saved_track(id)).collect();
+            respond(200, page(items, &query))
+        }
+        (Method::GET, ["shows", id]) => match state.shows.iter().find(|show| show.id == *id) {
+            Some(show) => respond(200, self::show(show, &query)),
+            None => respond(
+                404,
+                json!({ "error": { "status": 404, "message": "Not found." } }),
+            ),
+        },
+        (Method::GET, ["shows", id, "episodes"]) => {
+            match state.shows.iter().find(|show| show.id == *id) {
+                Some(show) => {
+                    let items = show.tracks.iter().map(|id| episode(id)).collect();
+                    respond(200, page(items, &query))
+                }
+                None => respond(
+                    404,
+                    json!({ "error": { "status": 404, "message": "Not found." } }),
+                ),
+            }
+        }
+        (Method::GET, ["me", "player", "currently-playing"]) => {
+            match (state.current.as_deref(), state.is_playing) {
+                (None, false) => respond(204, Value::Null),
+                (current, is_playing) => respond(200, playing(current, is_playing)),
+            }
+        }
+        (Method::GET, ["me", "player", "devices"]) => {
+            let devices: Vec<Value> = state.devices.iter().map(device).collect();
+            respond(200, json!({ "devices": devices }))
+        }
+        (Method::PUT, ["me", "player"]) => {
+            let body: Value = serde_json::from_str(&body).unwrap_or_default();
+            let id = body["device_ids"][0]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            for device in &mut state.devices {
+                device.is_active = device.id == id;
+            }
+            respond(204, Value::Null)
+        }
+        // Without tracks to start the paused track plays on
+        (Method::PUT, ["me", "player", "play"]) => {
+            let body: Value = serde_json::from_str(&body).unwrap_or_default();
+            let id = query.get("device_id").cloned().unwrap_or_default();
+            for device in &mut state.devices {
+                device.is_active = device.id == id;
+            }
+            state.is_playing = true;
+            if let Some(uri) = body["uris"][0].as_str() {
+                state.current = Some(String::from(uri));
+                state.play(uri);
+            }
+            respond(204, Value::Null)
+        }
+        (Method::PUT, ["me", "player", "pause"]) => {
+            state.is_playing = false;
+            respond(204, Value::Null)
+        }
+        (Method::POST, ["me", "player", "queue"]) => {
+            let uri = query.get("uri").cloned().unwrap_or_default();
+            state.play(&uri);
+            state.up_next.push_back(uri);
+            respond(204, Value::Null)
+        }
+        // Playback stops when nothing is queued
+        (Method::POST, ["me", "player", "next"]) => {
+            state.current = state.up_next.pop_front();
+            state.is_playing = state.current.is_some();
+            respond(204, Value::Null)
+        }
+        _ => respond(
+            404,
+            json!({ "error": { "status": 404, "message": "Service not found" } }),
+        ),
+    }
+}
+
+// Hand out tokens for the authorization code and the refresh token
+fn token(state: &mut MockState, body: &str) -> Response {
+    let form: HashMap<String, String> = url::form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect();
+    let grant = |key: &str, expected: &str| form.get(key).map(String::as_str) == Some(expected);
+    let valid = match form.get("grant_type").map(String::as_str) {
+        Some("authorization_code") => grant("code", &state.code),
+        Some("refresh_token") => grant("refresh_token", &state.refresh_token),
+        _ => false,
+    };
+    match valid {
+        true => respond(200, state.issue_token()),
+        false => respond(400, json!({ "error": "invalid_grant" })),
+    }
+}
+
+fn respond(status: u16, body: Value) -> Response {
+    let body = match body {
+        Value::Null => Body::empty(),
+        body => Body::from(body.to_string()),
+    };
+    Response::builder()
+        .status(StatusCode::from_u16(status).unwrap())
+        .header("Content-Type", "application/json")
+        .body(body)
+        .unwrap()
+}
+
+// Slice of items with a next link while there are more
+fn page(items: Vec<Value>, query: &HashMap<String, String>) -> Value {
+    let number = |key: &str, default: usize| {
+        query
+            .get(key)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    let (limit, offset) = (number("limit", 20), number("offset", 0));
+    let total = items.len();
+    let end = (offset + limit).min(total);
+    json!({
+        "href": "https://api.spotify.com/v1/page",
+        "items": items[offset.min(total)..end],
+        "limit": limit,
+        "next": (end < total).then(|| format!("https://api.spotify.com/v1/page?offset={}", end)),
+        "offset": offset,
+        "previous": Value::Null,
+        "total": total,
+    })
+}
+
+fn user(country: Option<&str>) -> Value {
+    json!({
+        "country": country,
+        "display_name": "AFK",
+        "email": null,
+        "external_urls": {},
+        "explicit_content": null,
+        "followers": null,
+        "href": "https://api.spotify.com/v1/users/afk",
+        "id": "afk",
+        "images": [],
+        "product": "premium",
+    })
+}
+
+fn playlist(playlist: &MockPlaylist) -> Value {
+    json!({
+        "collaborative": false,
+        "external_urls": {},
+        "href": format!("https://api.spotify.com/v1/playlists/{}", playlist.id),
+        "id": playlist.id,
+        "images": [],
+        "name": playlist.name,
+        "owner": {
+            "display_name": "AFK",
+            "external_urls": {},
+            "href": "https://api.spotify.com/v1/users/afk",
+            "id": "afk",
+        },
+        "public": false,
+        "snapshot_id": snapshot(playlist),
+        "tracks": {
+            "href": format!("https://api.spotify.com/v1/playlists/{}/tracks", playlist.id),
+            "total": playlist.tracks.len(),
+        },
+    })
+}
+
+// Snapshot id that changes with the tracks, like Spotify's does
+fn snapshot(playlist: &MockPlaylist) -> String {
+    let mut hasher = DefaultHasher::new();
+    playlist.tracks.hash(&mut hasher);
+    format!("{:x}", hasher.finish())
+}
+
+// Playlist with its first page of tracks, like GET /v1/playlists/{id} sends it
+fn full_playlist(playlist: &MockPlaylist, query: &HashMap<String, String>) -> Value {
+    let items = playlist.tracks.iter().map(|id| playlist_item(id)).collect();
+    let mut full = self::playlist(playlist);
+    full["description"] = Value::Null;
+    full["followers"] = json!({ "href": null, "total": 0 });
+    full["tracks"] = page(items, query);
+    full
+}
+
+fn playlist_item(id: &str) -> Value {
+    json!({
+        "added_at": null,
+        "added_by": null,
+        "is_local": id.starts_with("local"),
+        "track": track(id),
+    })
+}
+
+// Ids starting with "local" are local files without an id, "explicit" ones are explicit
+fn track(id: &str) -> Value {
+    let local = id.starts_with("local");
+    json!({
+        "album": {
+            "album_type": "album",
+            "artists": [],
+            "external_urls": {},
+            "href": null,
+            "id": null,
+            "images": [],
+            "name": "Album",
+        },
+        "artists": [],
+        "disc_number": 1,
+        "duration_ms": 180000,
+        "explicit": id.starts_with("explicit"),
+        "external_ids": {},
+        "external_urls": {},
+        "href": null,
+        "id": if local { Value::Null } else { json!(id) },
+        "is_local": local,
+        "name": id,
+        "popularity": 0,
+        "preview_url": null,
+        "track_number": 1,
+    })
+}
+
+fn saved_track(id: &str) -> Value {
+    json!({
+        "added_at": "2024-01-01T00:00:00Z",
+        "track": track(id),
+    })
+}
+
+// Show with its first page of episodes, like GET /v1/shows/{id} sends it
+fn show(show: &MockPlaylist, query: &HashMap<String, String>) -> Value {
+    let items = show.tracks.iter().map(|id| episode(id)).collect();
+    json!({
+        "available_markets": [],
+        "copyrights": [],
+        "description": "",
+        "explicit": false,
+        "episodes": page(items, query),
+        "external_urls": {},
+        "href": format!("https://api.spotify.com/v1/shows/{}", show.id),
+        "id": show.id,
+        "images": [],
+        "is_externally_hosted": false,
+        "languages": [],
+        "media_type": "audio",
+        "name": show.name,
+        "publisher": "AFK",
+    })
+}
+
+fn episode(id: &str) -> Value {
+    json!({
+        "audio_preview_url": null,
+        "description": "",
+        "duration_ms": 1800000,
+        "explicit": false,
+        "external_urls": {},
+        "href": format!("https://api.spotify.com/v1/episodes/{}", id),
+        "id": id,
+        "images": [],
+        "is_externally_hosted": false,
+        "is_playable": true,
+        "language": "en",
+        "languages": ["en"],
+        "name": id,
+        "release_date": "2024-01-01",
+        "release_date_precision": "day",
+        "resume_point": null,
+    })
+}
+
+// Playback with the track or episode of `uri`, episodes come with their show
+fn playing(uri: Option<&str>, is_playing: bool) -> Value {
+    let (kind, item) = match uri.map(|uri| uri.rsplit_once(':').unwrap_or(("", uri))) {
+        Some(("spotify:episode", id)) => {
+            let mut item = episode(id);
+            item["show"] = show(&MockPlaylist::new("show", "Podcast", 0), &HashMap::new());
+            ("episode", item)
+        }
+        Some((_, id)) => ("track", track(id)),
+        None => ("track", Value::Null),
+    };
+    json!({
+        "context": null,
+        "timestamp": Utc::now().timestamp_millis(),
+        "progress_ms": 0,
+        "is_playing": is_playing,
+        "item": item,
+        "currently_playing_type": kind,
+        "actions": { "disallows": {} },
+    })
+}
+
+fn device(device: &MockDevice) -> Value {
+    json!({
+        "id": device.id,
+        "is_active": device.is_active,
+        "is_private_session": false,
+        "is_restricted": false,
+        "name": device.name,
+        "type": "Computer",
+        "volume_percent": 50,
+    })
+}
+
+// Contents of a file the program wrote
+pub fn read(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_default()
+}
@@ -0,0 +1,639 @@
+// Runs of the program against a local imitation of the Spotify Web API
+
+mod common;
+
+use common::{read, Failure, MockPlaylist, MockSpotify, SpotiAfk};
+use std::time::Duration;
+
+fn stdout(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &std::process::Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[tokio::test]
+async fn lists_playlists_over_several_pages() {
+    let mock = MockSpotify::start().await;
+    mock.state().playlists = (1..=5)
+        .map(|i| MockPlaylist::new(&format!("playlist{}", i), &format!("Mix {}", i), i))
+        .collect();
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&["list-playlists"]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    let lines: Vec<_> = stdout(&output).lines().map(String::from).collect();
+    assert_eq!(lines.len(), 5);
+    assert_eq!(lines[4], "playlist5  Mix 5 (5 tracks)");
+    assert_eq!(
+        mock.requests("GET /v1/me/playlists"),
+        [
+            "GET /v1/me/playlists?limit=2&offset=0",
+            "GET /v1/me/playlists?limit=2&offset=2",
+            "GET /v1/me/playlists?limit=2&offset=4",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn afk_run_plays_until_another_device_takes_over() {
+    let mock = MockSpotify::start().await;
+    mock.state().take_over_after = Some(4);
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Program finished successfully"));
+
+    // Tracks of both pages are played last to first, then again from the cache,
+    // the first one is started and every next one queued while the one before plays
+    assert_eq!(
+        mock.state().queued,
+        [
+            "playlist1track3",
+            "playlist1track2",
+            "playlist1track1",
+            "playlist1track3"
+        ]
+    );
+    assert_eq!(mock.requests("GET /v1/playlists/playlist1/tracks").len(), 2);
+    assert_eq!(
+        mock.requests("GET /v1/playlists/playlist1?"),
+        [
+            "GET /v1/playlists/playlist1?fields=snapshot_id",
+            "GET /v1/playlists/playlist1?fields=snapshot_id"
+        ],
+        "the snapshot is checked before every fetch"
+    );
+    assert_eq!(
+        mock.requests("PUT /v1/me/player"),
+        [
+            "PUT /v1/me/player",
+            "PUT /v1/me/player/play?device_id=device1"
+        ],
+        "playback is transferred and started once"
+    );
+    assert_eq!(
+        mock.requests("POST /v1/me/player/queue").len(),
+        3,
+        "the queue holds the track after the playing one"
+    );
+    assert_eq!(
+        mock.requests("POST /v1/me/player/next").len(),
+        2,
+        "every skip moves on to a queued track"
+    );
+
+    // spotifyd was configured, started and stopped again
+    assert!(read(&afk.path("spotifyd.conf")).contains("device_name = \"AFK_DEVICE\""));
+    assert!(!afk.spotifyd_running());
+}
+
+#[tokio::test]
+async fn afk_run_starts_from_the_playlist_cache_when_spotify_fails() {
+    let mock = MockSpotify::start().await;
+    mock.state().take_over_after = Some(1);
+    let afk = SpotiAfk::new(&mock).env("API_RETRIES", "0");
+    afk.cache_token("access-1", "refresh-1", 3600);
+    let output = afk.run(&[]).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Same playlist again, but Spotify fails to list the playlists
+    {
+        let mut state = mock.state();
+        state.is_playing = false;
+        for device in &mut state.devices {
+            device.is_active = false;
+        }
+        state.queued.clear();
+        state.requests.clear();
+        state.failures.push(Failure {
+            path: "/v1/me/playlists",
+            status: 500,
+            times: 1,
+            retry_after: None,
+        });
+    }
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Using the cached playlists"));
+    assert_eq!(mock.state().queued, ["playlist1track3", "playlist1track2"]);
+    assert!(mock
+        .requests("GET /v1/playlists/playlist1/tracks")
+        .is_empty());
+
+    let output = afk.run(&["cache", "show"]).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("1 playlists listed in"));
+    assert!(stdout(&output).contains("playlist1  AFK (3 tracks, snapshot "));
+
+    let output = afk.run(&["cache", "clear"]).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(!afk.path("token.playlists.json").exists());
+}
+
+#[tokio::test]
+async fn afk_run_starts_from_the_playlist_cache_when_spotify_is_slow() {
+    let mock = MockSpotify::start().await;
+    mock.state().take_over_after = Some(1);
+    let afk = SpotiAfk::new(&mock).env("PLAYLIST_CACHE_WAIT", "1");
+    afk.cache_token("access-1", "refresh-1", 3600);
+    let output = afk.run(&[]).await;
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    // Same playlist again, but listing the playlists takes longer than the run may
+    {
+        let mut state = mock.state();
+        state.is_playing = false;
+        for device in &mut state.devices {
+            device.is_active = false;
+        }
+        state.queued.clear();
+        state.requests.clear();
+        state
+            .delays
+            .push(("/v1/me/playlists", Duration::from_secs(60)));
+    }
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Using the cached playlists"));
+    assert!(stdout(&output).contains("no answer within 1s"));
+    assert_eq!(mock.state().queued, ["playlist1track3", "playlist1track2"]);
+}
+
+#[tokio::test]
+async fn afk_run_does_not_play_while_another_device_plays() {
+    let mock = MockSpotify::start().await;
+    mock.state().take_over();
+    mock.state().take_over_after = Some(1);
+    let afk = SpotiAfk::new(&mock).env("CHECKS_BEFORE_PLAYING", "2");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let checks = "GET /v1/me/player/currently-playing";
+    let (output, stopped_at) =
+        tokio::join!(afk.run(&["run"]), mock.stop_playing_after_requests(checks, 3));
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Another device took playback over, stopping"));
+
+    // Playback only moved after two quiet checks once the other device stopped
+    let requests = mock.state().requests.clone();
+    let transfer = requests
+        .iter()
+        .position(|request| request == "PUT /v1/me/player")
+        .unwrap();
+    let checked = requests[..transfer]
+        .iter()
+        .filter(|request| request.starts_with(checks))
+        .count();
+    assert_eq!(checked, stopped_at + 2);
+    assert_eq!(mock.state().queued[0], "playlist1track3");
+}
+
+#[tokio::test]
+async fn afk_run_asks_for_tracks_in_the_country_of_the_user() {
+    for (country, market) in [(Some("DE"), Some("market=DE")), (None, None)] {
+        let mock = MockSpotify::start().await;
+        mock.state().country = country.map(String::from);
+        mock.state().take_over_after = Some(1);
+        let afk = SpotiAfk::new(&mock);
+        afk.cache_token("access-1", "refresh-1", 3600);
+
+        let output = afk.run(&[]).await;
+
+        assert!(output.status.success(), "{}", stderr(&output));
+        let requests = mock.requests("GET /v1/playlists/playlist1/tracks");
+        assert!(!requests.is_empty());
+        for request in requests {
+            match market {
+                Some(market) => assert!(request.contains(market), "{}", request),
+                None => assert!(!request.contains("market="), "{}", request),
+            }
+        }
+        assert_eq!(
+            stdout(&output).contains("Spotify gave no country for the user"),
+            country.is_none()
+        );
+    }
+}
+
+#[tokio::test]
+async fn afk_run_fails_without_the_spotifyd_device() {
+    let mock = MockSpotify::start().await;
+    mock.state()
+        .devices
+        .retain(|device| device.name != common::DEVICE_NAME);
+    let afk = SpotiAfk::new(&mock).env("SPOTIFYD_CONNECT_TIMEOUT", "2");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let started = std::time::Instant::now();
+    let output = afk.run(&[]).await;
+
+    // spotifyd gets the time to connect before the run gives up on it
+    assert_eq!(output.status.code(), Some(7), "{}", stderr(&output));
+    assert!(started.elapsed() >= std::time::Duration::from_secs(2));
+    assert!(stdout(&output).contains("Waiting for AFK_DEVICE to connect to Spotify"));
+    assert_eq!(mock.requests("GET /v1/me/player/devices").len(), 3);
+    assert!(mock.state().queued.is_empty());
+    assert!(
+        !afk.spotifyd_running(),
+        "spotifyd is stopped when the session fails"
+    );
+}
+
+#[tokio::test]
+async fn afk_run_refuses_a_playlist_name_used_twice() {
+    let mock = MockSpotify::start().await;
+    mock.state()
+        .playlists
+        .push(MockPlaylist::new("37i9dQZF1DXcBWIGoYBM5M", "AFK", 1));
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&[]).await;
+
+    assert_eq!(output.status.code(), Some(15), "{}", stderr(&output));
+    assert!(stderr(&output).contains("playlist1 (AFK)"));
+    assert!(stderr(&output).contains("37i9dQZF1DXcBWIGoYBM5M (AFK)"));
+    assert!(mock.state().queued.is_empty());
+}
+
+#[tokio::test]
+async fn afk_run_plays_the_playlist_from_a_link() {
+    let mock = MockSpotify::start().await;
+    mock.state()
+        .playlists
+        .push(MockPlaylist::new("37i9dQZF1DXcBWIGoYBM5M", "AFK", 1));
+    mock.state().take_over_after = Some(1);
+    let afk = SpotiAfk::new(&mock).env(
+        "PLAYLIST_NAME",
+        "https://open.spotify.com/playlist/37i9dQZF1DXcBWIGoYBM5M?si=afk",
+    );
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    // The only track is started and queued again after itself
+    assert_eq!(
+        mock.state().queued,
+        [
+            "37i9dQZF1DXcBWIGoYBM5Mtrack1",
+            "37i9dQZF1DXcBWIGoYBM5Mtrack1"
+        ]
+    );
+    assert!(mock.requests("GET /v1/me/playlists").is_empty());
+    assert!(mock
+        .state()
+        .requests
+        .contains(&String::from("GET /v1/playlists/37i9dQZF1DXcBWIGoYBM5M")));
+}
+
+#[tokio::test]
+async fn afk_run_plays_the_order_of_the_seed_again() {
+    let mut runs = Vec::new();
+    let mut seed: Option<String> = None;
+    for _ in 0..2 {
+        let mock = MockSpotify::start().await;
+        mock.state().saved = ["a", "b", "c", "d", "e"].map(String::from).to_vec();
+        mock.state().take_over_after = Some(5);
+        let mut afk = SpotiAfk::new(&mock)
+            .env("PLAYLIST_NAME", "liked")
+            .env("PLAYBACK_ORDER", "shuffle");
+        if let Some(seed) = &seed {
+            afk = afk.env("SHUFFLE_SEED", seed);
+        }
+        afk.cache_token("access-1", "refresh-1", 3600);
+
+        let output = afk.run(&[]).await;
+
+        assert!(output.status.success(), "{}", stderr(&output));
+        seed = stdout(&output)
+            .split("Using seed ")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(String::from);
+        runs.push(std::mem::take(&mut mock.state().queued));
+    }
+
+    let mut played = runs[0].clone();
+    played.sort();
+    assert_eq!(played, ["a", "b", "c", "d", "e"]);
+    assert_eq!(runs[0], runs[1]);
+}
+
+#[tokio::test]
+async fn afk_run_pauses_and_logs_the_listen_time() {
+    let mock = MockSpotify::start().await;
+    mock.state().take_over_after = Some(3);
+    let afk = SpotiAfk::new(&mock)
+        .env("LISTEN_TIME", "0-0")
+        .env("PAUSE_CHANCE", "100")
+        .env("PAUSE_LENGTH", "0")
+        .env("CHECK_JITTER", "50");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("skipping after 0s, pausing 0s after 0s"));
+    assert_eq!(mock.requests("PUT /v1/me/player/pause").len(), 1);
+    assert_eq!(
+        mock.requests("PUT /v1/me/player/play").len(),
+        2,
+        "the track is started and played on after the pause"
+    );
+    assert_eq!(mock.requests("POST /v1/me/player/next").len(), 1);
+}
+
+#[tokio::test]
+async fn afk_run_leaves_out_local_files_and_filtered_tracks() {
+    let mock = MockSpotify::start().await;
+    mock.state().saved = ["local1", "explicit1", "liked1", "liked1", "local2"]
+        .map(String::from)
+        .to_vec();
+    mock.state().take_over_after = Some(2);
+    let afk = SpotiAfk::new(&mock)
+        .env("PLAYLIST_NAME", "liked")
+        .env("FILTER_EXCLUDE_EXPLICIT", "true");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(
+        "Left out 4 tracks of the Liked Songs (local file: 2, explicit: 1, duplicate: 1)"
+    ));
+    assert_eq!(mock.state().queued, ["liked1", "liked1"]);
+}
+
+#[tokio::test]
+async fn afk_run_plays_liked_songs_and_podcast_episodes() {
+    let mock = MockSpotify::start().await;
+    mock.state().saved = vec![String::from("liked1"), String::from("liked2")];
+    mock.state()
+        .shows
+        .push(MockPlaylist::new("show1", "Podcast", 2));
+    mock.state().take_over_after = Some(2);
+    let afk = SpotiAfk::new(&mock)
+        .env("PLAYLISTS", "liked, spotify:show:show1")
+        .env("PLAYLIST_ROTATION", "round-robin");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&[]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Playing from the Liked Songs (2 tracks)"));
+    assert!(stdout(&output).contains("Playing from the show Podcast (2 tracks)"));
+    assert_eq!(
+        mock.state().queued,
+        ["liked2", "spotify:episode:show1track2"]
+    );
+    assert!(mock.requests("GET /v1/me/playlists").is_empty());
+}
+
+#[tokio::test]
+async fn lists_devices_with_the_active_one() {
+    let mock = MockSpotify::start().await;
+    mock.state().devices[1].is_active = true;
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&["list-devices"]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(
+        stdout(&output),
+        "device1  AFK_DEVICE (Computer)\ndevice2  Phone (Computer), active\n"
+    );
+}
+
+#[tokio::test]
+async fn rejected_access_token_is_a_spotify_error() {
+    let mock = MockSpotify::start().await;
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("revoked", "refresh-1", 3600);
+
+    let output = afk.run(&["list-playlists"]).await;
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("getting playlists"));
+}
+
+#[tokio::test]
+async fn throttled_request_waits_for_retry_after() {
+    let mock = MockSpotify::start().await;
+    mock.state().failures.push(Failure {
+        path: "/v1/me/playlists",
+        status: 429,
+        times: 1,
+        retry_after: Some(1),
+    });
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let started = std::time::Instant::now();
+    let output = afk.run(&["list-playlists"]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+    assert!(stdout(&output).contains("Spotify is rate limiting, retrying in 1s"));
+    assert_eq!(mock.requests("GET /v1/me/playlists").len(), 2);
+}
+
+#[tokio::test]
+async fn failing_server_is_a_spotify_error_once_the_retries_run_out() {
+    let mock = MockSpotify::start().await;
+    mock.state().failures.push(Failure {
+        path: "/v1/me/player/devices",
+        status: 503,
+        times: 5,
+        retry_after: None,
+    });
+    let afk = SpotiAfk::new(&mock).env("API_RETRIES", "1");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let output = afk.run(&["list-devices"]).await;
+
+    assert_eq!(output.status.code(), Some(5));
+    assert!(stderr(&output).contains("getting devices"));
+    assert_eq!(mock.requests("GET /v1/me/player/devices").len(), 2);
+}
+
+#[tokio::test]
+async fn expired_token_is_refreshed() {
+    let mock = MockSpotify::start().await;
+    mock.state().access_token = String::from("expired");
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("expired", "refresh-1", -60);
+
+    let output = afk.run(&["list-devices"]).await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(mock.requests("POST /api/token").len(), 1);
+    let cached = read(&afk.path("token.json"));
+    assert!(cached.contains("\"access-2\""));
+    assert!(cached.contains("\"refresh-1\""));
+}
+
+#[tokio::test]
+async fn cached_token_without_every_scope_logs_in_again() {
+    let mock = MockSpotify::start().await;
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token_with_scope(
+        "access-1",
+        "refresh-1",
+        3600,
+        "user-modify-playback-state playlist-read-private user-read-playback-state \
+         user-read-private",
+    );
+
+    let output = afk.login(&["auth"], "code-1").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("lacks the user-library-read permission"));
+    assert!(read(&afk.path("token.json")).contains("\"access-2\""));
+}
+
+#[tokio::test]
+async fn afk_run_refreshes_the_token_before_it_expires() {
+    let mock = MockSpotify::start().await;
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 301);
+
+    let (output, _) = tokio::join!(
+        afk.run(&[]),
+        mock.take_over_after_requests("POST /api/token", 1)
+    );
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(read(&afk.path("token.json")).contains("\"access-2\""));
+    assert_eq!(mock.state().tokens_used.last().unwrap(), "access-2");
+}
+
+#[tokio::test]
+async fn afk_run_keeps_playing_while_the_refresh_is_retried() {
+    let mock = MockSpotify::start().await;
+    mock.state().failures.push(Failure {
+        path: "/api/token",
+        status: 503,
+        times: 1,
+        retry_after: None,
+    });
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 300);
+
+    let (output, _) = tokio::join!(
+        afk.run(&[]),
+        mock.take_over_after_requests("POST /api/token", 1)
+    );
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Refreshing the Spotify login failed"));
+    assert!(stdout(&output).contains("trying again in 15s"));
+    assert!(read(&afk.path("token.json")).contains("\"access-1\""));
+}
+
+#[tokio::test]
+async fn afk_run_stops_when_the_refresh_token_is_refused() {
+    let mock = MockSpotify::start().await;
+    mock.state().refresh_token = String::from("refresh-2");
+    let afk = SpotiAfk::new(&mock);
+    afk.cache_token("access-1", "refresh-1", 300);
+
+    let output = afk.run(&[]).await;
+
+    assert_eq!(output.status.code(), Some(14));
+    assert!(stderr(&output).contains("run `spoti_afk auth` to log in again"));
+    assert_eq!(mock.requests("POST /api/token").len(), 1);
+}
+
+#[tokio::test]
+async fn afk_run_logs_in_again_when_the_refresh_token_is_refused() {
+    let mock = MockSpotify::start().await;
+    mock.state().refresh_token = String::from("refresh-2");
+    let afk = SpotiAfk::new(&mock).env("AUTH_REAUTH", "true");
+    afk.cache_token("access-1", "refresh-1", 300);
+
+    // A refused refresh, then the code of the new login
+    let (output, _) = tokio::join!(
+        afk.login(&[], "code-1"),
+        mock.take_over_after_requests("POST /api/token", 2)
+    );
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Spotify refused to refresh the login"));
+    let cached = read(&afk.path("token.json"));
+    assert!(cached.contains("\"access-2\""));
+    assert!(cached.contains("\"refresh-2\""));
+    assert_eq!(mock.state().tokens_used.last().unwrap(), "access-2");
+}
+
+#[tokio::test]
+async fn logs_in_with_the_pasted_redirect_url() {
+    let mock = MockSpotify::start().await;
+    let afk = SpotiAfk::new(&mock);
+
+    let output = afk.login(&["auth"], "code-1").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(read(&afk.path("token.json")).contains("\"access-2\""));
+}
+
+#[tokio::test]
+async fn logs_in_with_the_url_pasted_after_opening_the_browser() {
+    let mock = MockSpotify::start().await;
+    let afk = SpotiAfk::new(&mock)
+        .env("AUTH_HEADLESS", "false")
+        .env("BROWSER", "true");
+
+    let output = afk.login(&["auth"], "code-1").await;
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("Please paste the url you were redirected to:"));
+    assert!(read(&afk.path("token.json")).contains("\"access-2\""));
+}
+
+#[tokio::test]
+async fn rejected_authorization_code_fails_the_login() {
+    let mock = MockSpotify::start().await;
+    let afk = SpotiAfk::new(&mock);
+
+    let output = afk.login(&["auth"], "wrong-code").await;
+
+    assert_eq!(output.status.code(), Some(4));
+    assert!(!afk.path("token.json").exists());
+}
+
+#[tokio::test]
+async fn afk_run_waits_for_the_connection() {
+    let mock = MockSpotify::start().await;
+    mock.state().take_over_after = Some(1);
+
+    // Nothing listens on the probe address until the connection comes back
+    let probe = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = probe.local_addr().unwrap();
+    drop(probe);
+    let afk = SpotiAfk::new(&mock)
+        .env("NETWORK_PROBE", &address.to_string())
+        .env("NETWORK_PROBE_INTERVAL", "1");
+    afk.cache_token("access-1", "refresh-1", 3600);
+
+    let reconnect = async {
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        assert!(mock.state().requests.is_empty());
+        tokio::net::TcpListener::bind(address).await.unwrap()
+    };
+    let (output, _probe) = tokio::join!(afk.run(&[]), reconnect);
+
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains(&format!("No connection to {}, waiting for it", address)));
+    assert_eq!(mock.state().queued.len(), 2);
+}